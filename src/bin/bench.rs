@@ -0,0 +1,568 @@
+//! Recall and throughput benchmark of the vector indexes.
+//!
+//! Loads an ANN-benchmark dataset, either TEXMEX style `.fvecs` / `.bvecs` base and
+//! query files with an optional `.ivecs` ground truth, or a single ann-benchmarks
+//! `.hdf5` file holding `train`, `test` and `neighbors` (needs the `hdf5` feature),
+//! builds the chosen index and reports recall@k, QPS, latency percentiles, build
+//! time and memory.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use ndarray::Array2;
+use serde::Serialize;
+
+use vecdb_rs::index::{
+    similarity, HnswIndexOption, HnswParams, HnswSearchOption, Index, IndexType, InsertParams,
+    MetricType, SearchQuery, VamanaIndex, VamanaIndexOption, VamanaSearchOption,
+};
+use vecdb_rs::vecdb::{new_index, DatabaseParams};
+
+type BenchResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Parser, Debug)]
+#[command(about = "Measures recall@k, QPS, latency, build time and memory of an index")]
+struct Args {
+    /// Base vectors (.fvecs, .bvecs) or an ann-benchmarks file (.hdf5, .h5)
+    #[arg(long)]
+    base: PathBuf,
+    /// Query vectors (.fvecs, .bvecs), unused for ann-benchmarks files
+    #[arg(long)]
+    queries: Option<PathBuf>,
+    /// Nearest neighbours of each query (.ivecs), computed by exact scan when missing
+    #[arg(long)]
+    ground_truth: Option<PathBuf>,
+    /// Flat, Hnsw, MmapFlat or Vamana
+    #[arg(long, default_value = "Hnsw", value_parser = parse_index_type)]
+    index_type: IndexType,
+    /// L2 or IP
+    #[arg(long, default_value = "L2", value_parser = parse_metric_type)]
+    metric_type: MetricType,
+    #[arg(long, default_value_t = 10)]
+    k: usize,
+    /// Only load the first vectors of the base set
+    #[arg(long)]
+    max_base: Option<usize>,
+    /// Only run the first queries
+    #[arg(long)]
+    max_queries: Option<usize>,
+    /// Vectors inserted per call, the vamana graph is built from all of them at once
+    #[arg(long, default_value_t = 10_000)]
+    batch_size: usize,
+    /// Directory of the on-disk indexes, a temporary one when missing
+    #[arg(long)]
+    index_dir: Option<PathBuf>,
+
+    #[arg(long)]
+    ef_construction: Option<u32>,
+    #[arg(long)]
+    max_nb_connection: Option<u32>,
+    #[arg(long)]
+    max_layer: Option<u32>,
+    #[arg(long)]
+    ef_search: Option<u32>,
+    /// Insert into the HNSW graph from several threads
+    #[arg(long)]
+    parallel: bool,
+
+    #[arg(long)]
+    max_degree: Option<u32>,
+    #[arg(long)]
+    alpha: Option<f32>,
+    #[arg(long)]
+    search_list_size: Option<u32>,
+
+    /// Print the report as a single JSON line
+    #[arg(long)]
+    json: bool,
+}
+
+fn parse_index_type(value: &str) -> Result<IndexType, String> {
+    match value.to_lowercase().as_str() {
+        "flat" => Ok(IndexType::Flat),
+        "hnsw" => Ok(IndexType::Hnsw),
+        "mmapflat" => Ok(IndexType::MmapFlat),
+        "vamana" => Ok(IndexType::Vamana),
+        _ => Err(format!("unknown index type: {value}")),
+    }
+}
+
+fn parse_metric_type(value: &str) -> Result<MetricType, String> {
+    match value.to_lowercase().as_str() {
+        "l2" => Ok(MetricType::L2),
+        "ip" => Ok(MetricType::IP),
+        _ => Err(format!("unknown metric type: {value}")),
+    }
+}
+
+struct Dataset {
+    dim: usize,
+    // row-major vectors
+    base: Vec<f32>,
+    queries: Vec<f32>,
+    // 0-based positions in `base` of the nearest neighbours of each query, best first
+    ground_truth: Option<Vec<Vec<usize>>>,
+}
+
+impl Dataset {
+    fn num_base(&self) -> usize {
+        self.base.len() / self.dim
+    }
+
+    fn num_queries(&self) -> usize {
+        self.queries.len() / self.dim
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    index_type: IndexType,
+    metric_type: MetricType,
+    dim: usize,
+    num_base: usize,
+    num_queries: usize,
+    k: usize,
+    build_secs: f64,
+    // growth of the resident set size while building, Linux only
+    build_memory_mb: Option<f64>,
+    recall: f64,
+    qps: f64,
+    p50_ms: f64,
+    p99_ms: f64,
+}
+
+// Reads `.fvecs`, `.ivecs` and `.bvecs` files: every vector is stored as a little
+// endian i32 dimension followed by that many components of `elem_size` bytes
+fn read_vecs<T>(
+    path: &Path,
+    elem_size: usize,
+    limit: Option<usize>,
+    decode: fn(&[u8]) -> T,
+) -> BenchResult<(Vec<Vec<T>>, usize)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut vectors = vec![];
+    let mut dim = 0;
+    let mut dim_bytes = [0u8; 4];
+
+    while limit.is_none_or(|limit| vectors.len() < limit) {
+        match reader.read_exact(&mut dim_bytes) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+
+        let row_dim = i32::from_le_bytes(dim_bytes) as usize;
+        if dim != 0 && row_dim != dim {
+            return Err(format!(
+                "vector {} of {} has dimension {row_dim}, expected {dim}",
+                vectors.len(),
+                path.display()
+            )
+            .into());
+        }
+        dim = row_dim;
+
+        let mut bytes = vec![0u8; dim * elem_size];
+        reader.read_exact(&mut bytes)?;
+        vectors.push(bytes.chunks_exact(elem_size).map(decode).collect());
+    }
+
+    Ok((vectors, dim))
+}
+
+// Reads base or query vectors as flat f32 rows
+fn read_vectors(path: &Path, limit: Option<usize>) -> BenchResult<(Vec<f32>, usize)> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+
+    let (vectors, dim) = match extension {
+        "fvecs" => read_vecs(path, 4, limit, |b| {
+            f32::from_le_bytes(b.try_into().unwrap())
+        })?,
+        "bvecs" => read_vecs(path, 1, limit, |b| b[0] as f32)?,
+        _ => return Err(format!("unsupported vector file: {}", path.display()).into()),
+    };
+
+    Ok((vectors.concat(), dim))
+}
+
+fn read_ground_truth(path: &Path, limit: Option<usize>) -> BenchResult<Vec<Vec<usize>>> {
+    let (neighbours, _) = read_vecs(path, 4, limit, |b| {
+        i32::from_le_bytes(b.try_into().unwrap()) as usize
+    })?;
+
+    Ok(neighbours)
+}
+
+#[cfg(feature = "hdf5")]
+fn read_hdf5(path: &Path, args: &Args) -> BenchResult<Dataset> {
+    let file = hdf5::File::open(path)?;
+
+    let read_rows = |name: &str, limit: Option<usize>| -> BenchResult<(Vec<f32>, usize)> {
+        let dataset = file.dataset(name)?;
+        let dim = dataset.shape()[1];
+        let mut rows = dataset.read_raw::<f32>()?;
+        if let Some(limit) = limit {
+            rows.truncate(limit * dim);
+        }
+        Ok((rows, dim))
+    };
+
+    let (base, dim) = read_rows("train", args.max_base)?;
+    let (queries, _) = read_rows("test", args.max_queries)?;
+
+    // neighbours are only valid against the full train set
+    let ground_truth = match args.max_base {
+        Some(_) => None,
+        None => {
+            let dataset = file.dataset("neighbors")?;
+            let width = dataset.shape()[1];
+            let neighbours = dataset.read_raw::<i32>()?;
+
+            Some(
+                neighbours
+                    .chunks_exact(width)
+                    .take(queries.len() / dim)
+                    .map(|row| row.iter().map(|id| *id as usize).collect())
+                    .collect(),
+            )
+        }
+    };
+
+    Ok(Dataset {
+        dim,
+        base,
+        queries,
+        ground_truth,
+    })
+}
+
+#[cfg(not(feature = "hdf5"))]
+fn read_hdf5(path: &Path, _args: &Args) -> BenchResult<Dataset> {
+    Err(format!(
+        "reading {} needs the binary built with the hdf5 feature",
+        path.display()
+    )
+    .into())
+}
+
+fn load_dataset(args: &Args) -> BenchResult<Dataset> {
+    let extension = args.base.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if matches!(extension, "hdf5" | "h5") {
+        return read_hdf5(&args.base, args);
+    }
+
+    let queries_path = args
+        .queries
+        .as_ref()
+        .ok_or("--queries is required with .fvecs and .bvecs base files")?;
+
+    let (base, dim) = read_vectors(&args.base, args.max_base)?;
+    let (queries, query_dim) = read_vectors(queries_path, args.max_queries)?;
+    if dim != query_dim {
+        return Err(
+            format!("base dimension {dim} does not match query dimension {query_dim}").into(),
+        );
+    }
+
+    // neighbours are only valid against the full base set
+    let ground_truth = match (&args.ground_truth, args.max_base) {
+        (Some(path), None) => Some(read_ground_truth(path, args.max_queries)?),
+        _ => None,
+    };
+
+    Ok(Dataset {
+        dim,
+        base,
+        queries,
+        ground_truth,
+    })
+}
+
+fn exact_neighbours(dataset: &Dataset, metric_type: &MetricType, k: usize) -> Vec<Vec<usize>> {
+    let dim = dataset.dim;
+
+    dataset
+        .queries
+        .chunks_exact(dim)
+        .map(|query| {
+            let mut hits = dataset
+                .base
+                .chunks_exact(dim)
+                .enumerate()
+                .map(|(position, vector)| (position, similarity(metric_type, query, vector)))
+                .collect::<Vec<_>>();
+            hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+            hits.truncate(k);
+
+            hits.into_iter().map(|(position, _)| position).collect()
+        })
+        .collect()
+}
+
+// Resident set size of the process, read from procfs
+fn resident_memory_mb() -> Option<f64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<f64>()
+        .ok()?;
+
+    Some(kb / 1024.0)
+}
+
+fn percentile_ms(sorted: &[Duration], percentile: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+
+    let rank = ((sorted.len() as f64 * percentile).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1].as_secs_f64() * 1000.0
+}
+
+fn run(args: Args) -> BenchResult<Report> {
+    let dataset = load_dataset(&args)?;
+    let dim = dataset.dim;
+    eprintln!(
+        "loaded {} base and {} query vectors of dimension {dim}",
+        dataset.num_base(),
+        dataset.num_queries()
+    );
+
+    let ground_truth = match &dataset.ground_truth {
+        Some(ground_truth) => ground_truth.clone(),
+        None => {
+            eprintln!("computing the ground truth by exact scan");
+            exact_neighbours(&dataset, &args.metric_type, args.k)
+        }
+    };
+
+    let params = DatabaseParams {
+        dim: dim as u32,
+        metric_type: args.metric_type.clone(),
+        index_type: args.index_type.clone(),
+        hnsw_params: Some(HnswIndexOption {
+            ef_construction: args.ef_construction,
+            max_elements: Some(dataset.num_base() as u32),
+            max_nb_connection: args.max_nb_connection,
+            max_layer: args.max_layer,
+            ef_search: args.ef_search,
+        }),
+        vamana_params: Some(VamanaIndexOption {
+            max_degree: args.max_degree,
+            search_list_size: args.search_list_size,
+            alpha: args.alpha,
+            pq_subspaces: None,
+            beam_width: None,
+        }),
+        text_fields: None,
+        bm25_params: None,
+        vector_fields: None,
+        brute_force_selectivity: None,
+        shadow_sample_rate: None,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+
+    let temp_dir = std::env::temp_dir().join(format!("vecdb-bench-{}", std::process::id()));
+    let index_dir = args.index_dir.clone().unwrap_or(temp_dir.clone());
+    std::fs::create_dir_all(&index_dir)?;
+    let index_path = index_dir.join("index.bin");
+    if index_path.exists() {
+        return Err(format!("{} already exists", index_path.display()).into());
+    }
+
+    let memory_before = resident_memory_mb();
+    let build_start = Instant::now();
+
+    // labels start at 1, ground truth positions start at 0
+    let index: Arc<Mutex<dyn Index + Send>> = match args.index_type {
+        // the vamana graph is built in one go, vectors inserted afterwards would only
+        // reach its exact fresh index
+        IndexType::Vamana => {
+            let labels = (1..=dataset.num_base() as u64).collect::<Vec<_>>();
+            Arc::new(Mutex::new(VamanaIndex::build(
+                &index_path,
+                params.dim,
+                params.metric_type,
+                params.vamana_params,
+                &labels,
+                &dataset.base,
+            )?))
+        }
+        _ => {
+            let index = new_index(params, &index_path)?;
+            {
+                let mut index = index.lock().unwrap();
+
+                for (batch, rows) in dataset
+                    .base
+                    .chunks(args.batch_size.max(1) * dim)
+                    .enumerate()
+                {
+                    let data = Array2::from_shape_vec((rows.len() / dim, dim), rows.to_vec())?;
+                    let first = (batch * args.batch_size.max(1)) as u64 + 1;
+                    let labels = (first..first + data.nrows() as u64).collect::<Vec<_>>();
+
+                    index.insert(&InsertParams::new(&data, &labels).with(HnswParams {
+                        parallel: args.parallel,
+                    }))?;
+                }
+            }
+            index
+        }
+    };
+
+    let build_time = build_start.elapsed();
+    let build_memory_mb = memory_before
+        .zip(resident_memory_mb())
+        .map(|(before, after)| after - before);
+    eprintln!("built {:?} index in {build_time:?}", args.index_type);
+
+    let mut latencies = Vec::with_capacity(dataset.num_queries());
+    let mut found = 0;
+    let mut expected = 0;
+
+    let search_start = Instant::now();
+    let mut index = index.lock().unwrap();
+    for (query, truth) in dataset.queries.chunks_exact(dim).zip(&ground_truth) {
+        let mut search_query = SearchQuery::new(query.to_vec());
+        if let Some(ef_search) = args.ef_search {
+            search_query = search_query.with(&HnswSearchOption { ef_search });
+        }
+        if let Some(search_list_size) = args.search_list_size {
+            search_query = search_query.with(&VamanaSearchOption { search_list_size });
+        }
+
+        let query_start = Instant::now();
+        let result = index.search(&search_query, args.k)?;
+        latencies.push(query_start.elapsed());
+
+        let truth = &truth[..truth.len().min(args.k)];
+        found += result
+            .labels
+            .iter()
+            .filter(|label| truth.contains(&(**label as usize - 1)))
+            .count();
+        expected += truth.len();
+    }
+    let search_time = search_start.elapsed();
+    drop(index);
+
+    if args.index_dir.is_none() {
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    latencies.sort();
+
+    Ok(Report {
+        index_type: args.index_type,
+        metric_type: args.metric_type,
+        dim,
+        num_base: dataset.num_base(),
+        num_queries: latencies.len(),
+        k: args.k,
+        build_secs: build_time.as_secs_f64(),
+        build_memory_mb,
+        recall: found as f64 / expected.max(1) as f64,
+        qps: latencies.len() as f64 / search_time.as_secs_f64(),
+        p50_ms: percentile_ms(&latencies, 0.5),
+        p99_ms: percentile_ms(&latencies, 0.99),
+    })
+}
+
+fn main() -> BenchResult<()> {
+    let args = Args::parse();
+    let json = args.json;
+
+    let report = run(args)?;
+
+    if json {
+        println!("{}", serde_json::to_string(&report)?);
+    } else {
+        println!("index type    {:?}", report.index_type);
+        println!("metric type   {:?}", report.metric_type);
+        println!(
+            "vectors       {} base, {} queries, dim {}",
+            report.num_base, report.num_queries, report.dim
+        );
+        println!("build time    {:.3} s", report.build_secs);
+        if let Some(memory) = report.build_memory_mb {
+            println!("build memory  {memory:.1} MB");
+        }
+        println!("recall@{}     {:.4}", report.k, report.recall);
+        println!("QPS           {:.1}", report.qps);
+        println!("latency p50   {:.3} ms", report.p50_ms);
+        println!("latency p99   {:.3} ms", report.p99_ms);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_vecs(path: &Path, rows: &[Vec<f32>]) {
+        let mut file = File::create(path).unwrap();
+        for row in rows {
+            file.write_all(&(row.len() as i32).to_le_bytes()).unwrap();
+            for value in row {
+                file.write_all(&value.to_le_bytes()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_vectors() {
+        let dir = std::env::temp_dir().join(format!("vecdb-bench-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("base.fvecs");
+        write_vecs(&path, &[vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]]);
+
+        let (vectors, dim) = read_vectors(&path, None).unwrap();
+        assert_eq!(dim, 2);
+        assert_eq!(vectors, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        let (vectors, _) = read_vectors(&path, Some(1)).unwrap();
+        assert_eq!(vectors, vec![1.0, 2.0]);
+
+        let path = dir.join("base.bvecs");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&2i32.to_le_bytes()).unwrap();
+        file.write_all(&[7, 255]).unwrap();
+        let (vectors, _) = read_vectors(&path, None).unwrap();
+        assert_eq!(vectors, vec![7.0, 255.0]);
+
+        assert!(read_vectors(&dir.join("base.txt"), None).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_exact_neighbours() {
+        let dataset = Dataset {
+            dim: 1,
+            base: vec![0.0, 5.0, 1.0, 3.0],
+            queries: vec![2.9, 0.1],
+            ground_truth: None,
+        };
+
+        assert_eq!(
+            exact_neighbours(&dataset, &MetricType::L2, 2),
+            vec![vec![3, 2], vec![0, 2]]
+        );
+
+        let latencies = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(percentile_ms(&latencies, 0.5), 50.0);
+        assert_eq!(percentile_ms(&latencies, 0.99), 99.0);
+    }
+}
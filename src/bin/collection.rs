@@ -0,0 +1,786 @@
+//! Bulk import and export of collections.
+//!
+//! `import` builds the collection of a config file straight from Parquet or Arrow IPC
+//! files, writing to the database directly instead of going through the HTTP API and
+//! the WAL. Rows keep the ids of the id column. `export` writes the stored docs back
+//! to Parquet with the same layout: `id`, the default vector field as a fixed size
+//! list `vector`, each named vector field as `vector_<name>` (a list of fixed size
+//! lists for multi-vector fields), sparse vectors as `sparse_indices` and
+//! `sparse_values`, and the `doc` and `attributes` JSON objects as strings. Vector
+//! columns are null where a doc has no vector. Both must run while the server is
+//! stopped, since the database is opened by a single process.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow::array::{
+    Array, ArrayRef, AsArray, FixedSizeListArray, Float32Array, ListArray, RecordBatch,
+    StringArray, UInt32Array, UInt64Array,
+};
+use arrow::buffer::{NullBuffer, OffsetBuffer};
+use arrow::compute::cast;
+use arrow::datatypes::{
+    DataType, Field, FieldRef, Float32Type, Int64Type, Schema, UInt32Type, UInt64Type,
+};
+use arrow::ipc::reader::FileReader;
+use clap::{Args, Parser, Subcommand};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde_json::Value;
+
+use vecdb_rs::index::SparseVector;
+use vecdb_rs::settings::{AppConfig, DEFAULT_CONFIG_PATH};
+use vecdb_rs::vecdb::{
+    DatabaseParams, DocMap, VdbScrollArgs, VdbUpsertArgs, VectorArgs, VectorDatabase,
+};
+
+type CliResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+// keys of a stored doc written to their own columns or not exported
+const RESERVED_KEYS: [&str; 5] = [
+    "id",
+    "vector",
+    "attributes",
+    "sparse_vector",
+    "named_vectors",
+];
+
+const SPARSE_INDICES_COLUMN: &str = "sparse_indices";
+const SPARSE_VALUES_COLUMN: &str = "sparse_values";
+
+// Column of the vectors of a named vector field
+fn vector_column(field: &str) -> String {
+    format!("vector_{field}")
+}
+
+#[derive(Parser, Debug)]
+#[command(
+    about = "Imports collections from Parquet or Arrow IPC files and exports them to Parquet"
+)]
+struct Cli {
+    /// Config file of the server, giving the database path and parameters, with the
+    /// same VECDB_* environment overrides
+    #[arg(long, default_value = DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+    /// Rows read, written or exported at once
+    #[arg(long, default_value_t = 10_000)]
+    batch_size: usize,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Upserts the rows of Parquet (.parquet) or Arrow IPC (.arrow, .ipc, .feather) files
+    Import(ImportArgs),
+    /// Writes every stored doc to a Parquet file
+    Export {
+        #[arg(long)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Args, Debug)]
+struct ImportArgs {
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Unsigned integer column of the doc ids, which start at 1
+    #[arg(long, default_value = "id")]
+    id_column: String,
+    /// Fixed size list column of the vectors of the default vector field
+    #[arg(long, default_value = "vector")]
+    vector_column: String,
+    /// String column of the docs as JSON objects, skipped when missing
+    #[arg(long, default_value = "doc")]
+    doc_column: String,
+    /// String column of the attributes as JSON objects, skipped when missing
+    #[arg(long, default_value = "attributes")]
+    attributes_column: String,
+    /// Integer columns added to the attributes under their own names
+    #[arg(long, value_delimiter = ',')]
+    attribute_columns: Vec<String>,
+}
+
+// Reads the record batches of a Parquet or Arrow IPC file, guessing the format from
+// the extension
+fn read_batches(
+    path: &Path,
+    batch_size: usize,
+) -> CliResult<Box<dyn Iterator<Item = Result<RecordBatch, arrow::error::ArrowError>>>> {
+    let file = File::open(path)?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("parquet") => Ok(Box::new(
+            ParquetRecordBatchReaderBuilder::try_new(file)?
+                .with_batch_size(batch_size)
+                .build()?,
+        )),
+        Some("arrow" | "ipc" | "feather") => Ok(Box::new(FileReader::try_new(file, None)?)),
+        _ => Err(format!("unknown file format of {}", path.display()).into()),
+    }
+}
+
+fn column<'a>(batch: &'a RecordBatch, name: &str) -> CliResult<&'a ArrayRef> {
+    batch
+        .column_by_name(name)
+        .ok_or_else(|| format!("missing column {name}").into())
+}
+
+fn read_ids(batch: &RecordBatch, name: &str) -> CliResult<Vec<u64>> {
+    let ids = cast(column(batch, name)?, &DataType::UInt64)?;
+    if ids.null_count() > 0 {
+        return Err(format!("column {name} holds null ids").into());
+    }
+
+    Ok(ids.as_primitive::<UInt64Type>().values().to_vec())
+}
+
+// The column of the given name unless it is missing or holds only nulls, as the
+// columns of vector fields a collection never used do
+fn present_column<'a>(batch: &'a RecordBatch, name: &str) -> Option<&'a ArrayRef> {
+    batch
+        .column_by_name(name)
+        .filter(|column| column.null_count() < column.len())
+}
+
+// Row-major vectors of a fixed size list column, of any float type
+fn read_vectors(batch: &RecordBatch, name: &str, dim: usize) -> CliResult<Vec<f32>> {
+    fixed_size_list_values(column(batch, name)?, name, dim)
+}
+
+fn fixed_size_list_values(vectors: &ArrayRef, name: &str, dim: usize) -> CliResult<Vec<f32>> {
+    let vectors = vectors
+        .as_fixed_size_list_opt()
+        .ok_or(format!("column {name} is not a fixed size list"))?;
+    if vectors.value_length() as usize != dim {
+        return Err(format!(
+            "vectors of column {name} have dimension {}, expected {dim}",
+            vectors.value_length()
+        )
+        .into());
+    }
+    if vectors.null_count() > 0 {
+        return Err(format!("column {name} holds null vectors").into());
+    }
+
+    // values of a sliced list start at its offset
+    let values = vectors
+        .values()
+        .slice(vectors.offset() * dim, vectors.len() * dim);
+    let values = cast(&values, &DataType::Float32)?;
+    if values.null_count() > 0 {
+        return Err(format!("column {name} holds null values").into());
+    }
+
+    Ok(values.as_primitive::<Float32Type>().values().to_vec())
+}
+
+// Flat vectors of each row of a list of fixed size lists column, empty for nulls
+fn read_multi_vectors(batch: &RecordBatch, name: &str, dim: usize) -> CliResult<Vec<Vec<f32>>> {
+    column(batch, name)?
+        .as_list_opt::<i32>()
+        .ok_or(format!("column {name} is not a list"))?
+        .iter()
+        .map(|row| match row {
+            Some(row) => fixed_size_list_values(&row, name, dim),
+            None => Ok(vec![]),
+        })
+        .collect()
+}
+
+// Sparse vectors of the sparse columns, None when they are missing or all null
+fn read_sparse_vectors(batch: &RecordBatch) -> CliResult<Option<Vec<SparseVector>>> {
+    let (Some(indices), Some(values)) = (
+        present_column(batch, SPARSE_INDICES_COLUMN),
+        present_column(batch, SPARSE_VALUES_COLUMN),
+    ) else {
+        return Ok(None);
+    };
+    let lists = |column: &ArrayRef, name: &str, data_type: &DataType| {
+        column
+            .as_list_opt::<i32>()
+            .ok_or(format!("column {name} is not a list"))?
+            .iter()
+            .map(|row| row.map(|row| cast(&row, data_type)).transpose())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Box::<dyn std::error::Error>::from)
+    };
+    let indices = lists(indices, SPARSE_INDICES_COLUMN, &DataType::UInt32)?;
+    let values = lists(values, SPARSE_VALUES_COLUMN, &DataType::Float32)?;
+
+    let vectors = indices
+        .into_iter()
+        .zip(values)
+        .map(|(indices, values)| SparseVector {
+            indices: indices
+                .map(|i| i.as_primitive::<UInt32Type>().values().to_vec())
+                .unwrap_or_default(),
+            values: values
+                .map(|v| v.as_primitive::<Float32Type>().values().to_vec())
+                .unwrap_or_default(),
+        })
+        .collect();
+
+    Ok(Some(vectors))
+}
+
+// JSON objects of a string column, None for nulls or when the column is missing
+fn read_json_objects(batch: &RecordBatch, name: &str) -> CliResult<Vec<Option<DocMap>>> {
+    let Some(objects) = batch.column_by_name(name) else {
+        return Ok(vec![None; batch.num_rows()]);
+    };
+    let objects = cast(objects, &DataType::Utf8)?;
+
+    objects
+        .as_string::<i32>()
+        .iter()
+        .map(|object| {
+            object
+                .map(|object| {
+                    serde_json::from_str::<DocMap>(object)
+                        .map_err(|e| format!("invalid JSON object in column {name}: {e}").into())
+                })
+                .transpose()
+        })
+        .collect()
+}
+
+fn read_attributes(batch: &RecordBatch, args: &ImportArgs) -> CliResult<Vec<Option<DocMap>>> {
+    let mut attributes = read_json_objects(batch, &args.attributes_column)?;
+
+    for name in &args.attribute_columns {
+        let values = cast(column(batch, name)?, &DataType::Int64)?;
+        for (attrs, value) in attributes
+            .iter_mut()
+            .zip(values.as_primitive::<Int64Type>())
+        {
+            if let Some(value) = value {
+                attrs
+                    .get_or_insert_with(DocMap::new)
+                    .insert(name.clone(), Value::from(value));
+            }
+        }
+    }
+
+    Ok(attributes)
+}
+
+async fn import(vdb: &mut VectorDatabase, args: &ImportArgs, batch_size: usize) -> CliResult<()> {
+    let dim = vdb.params().dim as usize;
+    let vector_fields = vdb.params().vector_fields.clone().unwrap_or_default();
+    let mut total = 0;
+
+    for input in &args.inputs {
+        for batch in read_batches(input, batch_size)? {
+            let batch = batch?;
+            let ids = read_ids(&batch, &args.id_column)?;
+
+            let mut named_data = HashMap::new();
+            let mut multi_data = HashMap::new();
+            for field in &vector_fields {
+                let name = vector_column(&field.name);
+                if present_column(&batch, &name).is_none() {
+                    continue;
+                }
+                if field.is_multi_vector() {
+                    let rows = read_multi_vectors(&batch, &name, field.dim as usize)?;
+                    multi_data.insert(field.name.clone(), rows);
+                } else {
+                    let vectors = read_vectors(&batch, &name, field.dim as usize)?;
+                    named_data.insert(field.name.clone(), vectors);
+                }
+            }
+
+            // the default field may be left out when the rows hold named vectors
+            let flat_data = if present_column(&batch, &args.vector_column).is_some()
+                || (named_data.is_empty() && multi_data.is_empty())
+            {
+                read_vectors(&batch, &args.vector_column, dim)?
+            } else {
+                vec![]
+            };
+
+            let upsert_args = VdbUpsertArgs {
+                vectors: VectorArgs {
+                    flat_data,
+                    data_row: batch.num_rows(),
+                    data_dim: dim,
+                    sparse_data: read_sparse_vectors(&batch)?,
+                    named_data: Some(named_data),
+                    multi_data: Some(multi_data),
+                },
+                docs: read_json_objects(&batch, &args.doc_column)?,
+                attributes: read_attributes(&batch, args)?,
+                hnsw_params: None,
+            };
+
+            vdb.import(ids, upsert_args).await?;
+            total += batch.num_rows();
+        }
+
+        eprintln!("imported {}, {total} rows so far", input.display());
+    }
+
+    Ok(())
+}
+
+fn item_field(data_type: DataType) -> FieldRef {
+    Arc::new(Field::new("item", data_type, false))
+}
+
+fn vector_type(dim: u32) -> DataType {
+    DataType::FixedSizeList(item_field(DataType::Float32), dim as i32)
+}
+
+fn export_schema(params: &DatabaseParams) -> Schema {
+    let mut fields = vec![
+        Field::new("id", DataType::UInt64, false),
+        Field::new("vector", vector_type(params.dim), true),
+    ];
+    for field in params.vector_fields.iter().flatten() {
+        let data_type = if field.is_multi_vector() {
+            DataType::List(item_field(vector_type(field.dim)))
+        } else {
+            vector_type(field.dim)
+        };
+        fields.push(Field::new(vector_column(&field.name), data_type, true));
+    }
+    fields.extend([
+        Field::new(
+            SPARSE_INDICES_COLUMN,
+            DataType::List(item_field(DataType::UInt32)),
+            true,
+        ),
+        Field::new(
+            SPARSE_VALUES_COLUMN,
+            DataType::List(item_field(DataType::Float32)),
+            true,
+        ),
+        Field::new("doc", DataType::Utf8, false),
+        Field::new("attributes", DataType::Utf8, false),
+    ]);
+
+    Schema::new(fields)
+}
+
+// Values of a stored vector, flattening the rows of multi-vector fields
+fn json_floats(value: &Value) -> Vec<f32> {
+    match value {
+        Value::Array(values) => values.iter().flat_map(json_floats).collect(),
+        value => vec![value.as_f64().unwrap_or(0.0) as f32],
+    }
+}
+
+// Fixed size lists of the vectors of each doc, null where a doc has none
+fn vector_array(
+    field: &str,
+    ids: &[u64],
+    rows: &[Option<Vec<f32>>],
+    dim: usize,
+) -> CliResult<FixedSizeListArray> {
+    let mut values = Vec::with_capacity(rows.len() * dim);
+    for (id, row) in ids.iter().zip(rows) {
+        match row {
+            Some(row) if row.len() == dim => values.extend(row),
+            Some(row) => {
+                return Err(format!(
+                    "doc {id} has a vector of dimension {} in field {field}, expected {dim}",
+                    row.len()
+                )
+                .into())
+            }
+            None => values.resize(values.len() + dim, 0.0),
+        }
+    }
+    let nulls = NullBuffer::from(rows.iter().map(Option::is_some).collect::<Vec<_>>());
+
+    Ok(FixedSizeListArray::try_new(
+        item_field(DataType::Float32),
+        dim as i32,
+        Arc::new(Float32Array::from(values)),
+        Some(nulls),
+    )?)
+}
+
+// Lists of the given lengths over the values, null where a doc has none
+fn list_array(item: FieldRef, lengths: &[Option<usize>], values: ArrayRef) -> CliResult<ListArray> {
+    let nulls = NullBuffer::from(lengths.iter().map(Option::is_some).collect::<Vec<_>>());
+    let offsets = OffsetBuffer::from_lengths(lengths.iter().map(|len| len.unwrap_or(0)));
+
+    Ok(ListArray::try_new(item, offsets, values, Some(nulls))?)
+}
+
+// Lists of the token vectors of each doc for a multi-vector field
+fn multi_vector_array(
+    field: &str,
+    ids: &[u64],
+    rows: &[Option<Vec<f32>>],
+    dim: usize,
+) -> CliResult<ListArray> {
+    if let Some((id, row)) = ids
+        .iter()
+        .zip(rows)
+        .find(|(_, row)| row.as_ref().is_some_and(|row| row.len() % dim != 0))
+    {
+        return Err(format!(
+            "doc {id} has vectors of length {} in field {field}, not a multiple of {dim}",
+            row.as_ref().map_or(0, Vec::len)
+        )
+        .into());
+    }
+
+    let lengths = rows
+        .iter()
+        .map(|row| row.as_ref().map(|row| row.len() / dim))
+        .collect::<Vec<_>>();
+    let values = rows.iter().flatten().flatten().copied().collect::<Vec<_>>();
+    let vectors = FixedSizeListArray::try_new(
+        item_field(DataType::Float32),
+        dim as i32,
+        Arc::new(Float32Array::from(values)),
+        None,
+    )?;
+
+    list_array(
+        item_field(vector_type(dim as u32)),
+        &lengths,
+        Arc::new(vectors),
+    )
+}
+
+// One record batch of stored docs, as returned by a scroll with vectors
+fn export_batch(
+    schema: &Arc<Schema>,
+    docs: Vec<DocMap>,
+    params: &DatabaseParams,
+) -> CliResult<RecordBatch> {
+    let vector_fields = params.vector_fields.as_deref().unwrap_or_default();
+    let mut ids = Vec::with_capacity(docs.len());
+    let mut vectors = Vec::with_capacity(docs.len());
+    let mut named_vectors = vec![Vec::with_capacity(docs.len()); vector_fields.len()];
+    let mut sparse_vectors = Vec::with_capacity(docs.len());
+    let mut doc_objects = Vec::with_capacity(docs.len());
+    let mut attributes = Vec::with_capacity(docs.len());
+
+    for mut doc in docs {
+        let id = doc.get("id").and_then(Value::as_u64).unwrap_or(0);
+        ids.push(id);
+
+        vectors.push(match doc.remove("vector") {
+            Some(vector @ Value::Array(_)) => Some(json_floats(&vector)),
+            _ => None,
+        });
+
+        let mut named = match doc.remove("named_vectors") {
+            Some(Value::Object(named)) => named,
+            _ => Default::default(),
+        };
+        for (field, rows) in vector_fields.iter().zip(&mut named_vectors) {
+            rows.push(named.remove(&field.name).map(|vector| json_floats(&vector)));
+        }
+
+        let sparse_vector = doc
+            .remove("sparse_vector")
+            .map(serde_json::from_value::<SparseVector>)
+            .transpose()
+            .map_err(|e| format!("doc {id} has an invalid sparse vector: {e}"))?;
+        sparse_vectors.push(sparse_vector);
+
+        attributes.push(
+            doc.remove("attributes")
+                .unwrap_or_else(|| Value::Object(Default::default()))
+                .to_string(),
+        );
+        doc.retain(|key, _| !RESERVED_KEYS.contains(&key.as_str()));
+        doc_objects.push(serde_json::to_string(&doc)?);
+    }
+
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from(ids.clone())),
+        Arc::new(vector_array("vector", &ids, &vectors, params.dim as usize)?),
+    ];
+    for (field, rows) in vector_fields.iter().zip(&named_vectors) {
+        let dim = field.dim as usize;
+        columns.push(if field.is_multi_vector() {
+            Arc::new(multi_vector_array(&field.name, &ids, rows, dim)?)
+        } else {
+            Arc::new(vector_array(&field.name, &ids, rows, dim)?)
+        });
+    }
+
+    let lengths = sparse_vectors
+        .iter()
+        .map(|sparse| sparse.as_ref().map(|sparse| sparse.indices.len()))
+        .collect::<Vec<_>>();
+    let (indices, values): (Vec<u32>, Vec<f32>) = sparse_vectors
+        .into_iter()
+        .flatten()
+        .flat_map(|sparse| sparse.indices.into_iter().zip(sparse.values))
+        .unzip();
+    columns.push(Arc::new(list_array(
+        item_field(DataType::UInt32),
+        &lengths,
+        Arc::new(UInt32Array::from(indices)),
+    )?));
+    columns.push(Arc::new(list_array(
+        item_field(DataType::Float32),
+        &lengths,
+        Arc::new(Float32Array::from(values)),
+    )?));
+
+    columns.push(Arc::new(StringArray::from(doc_objects)));
+    columns.push(Arc::new(StringArray::from(attributes)));
+
+    Ok(RecordBatch::try_new(Arc::clone(schema), columns)?)
+}
+
+fn export(vdb: &VectorDatabase, output: &Path, batch_size: usize) -> CliResult<()> {
+    let schema = Arc::new(export_schema(vdb.params()));
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+    let mut writer =
+        ArrowWriter::try_new(File::create(output)?, Arc::clone(&schema), Some(properties))?;
+
+    let mut cursor = None;
+    let mut total = 0;
+    loop {
+        let page = vdb.scroll(VdbScrollArgs {
+            limit: batch_size,
+            cursor,
+            filter_inputs: None,
+            with_vectors: Some(true),
+        })?;
+
+        total += page.results.len();
+        writer.write(&export_batch(&schema, page.results, vdb.params())?)?;
+
+        cursor = page.next_cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    writer.close()?;
+    eprintln!("exported {total} docs to {}", output.display());
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> CliResult<()> {
+    let cli = Cli::parse();
+    if cli.batch_size == 0 {
+        return Err("batch size must be positive".into());
+    }
+
+    let config = AppConfig::load(&cli.config, None)?;
+    let mut vdb = VectorDatabase::new(config.file_path, config.database)?;
+
+    match &cli.command {
+        Command::Import(args) => import(&mut vdb, args, cli.batch_size).await,
+        Command::Export { output } => export(&vdb, output, cli.batch_size),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn params() -> DatabaseParams {
+        serde_json::from_value(json!({
+            "dim": 2,
+            "metric_type": "L2",
+            "index_type": "Flat",
+            "version": "0.1.0",
+            "vector_fields": [
+                {"name": "title", "dim": 2, "metric_type": "L2", "index_type": "Flat"},
+                {
+                    "name": "tokens",
+                    "dim": 2,
+                    "metric_type": "IP",
+                    "index_type": "Flat",
+                    "multi_vector": true,
+                },
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn import_args(args: &[&str]) -> ImportArgs {
+        let cli = Cli::parse_from(["collection", "import"].iter().chain(args));
+        match cli.command {
+            Command::Import(args) => args,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_export_batch_round_trip() {
+        let params = params();
+        let schema = Arc::new(export_schema(&params));
+        let stored = vec![
+            DocMap::from([
+                ("id".to_string(), json!(3)),
+                ("vector".to_string(), json!([1.0, 2.0])),
+                ("attributes".to_string(), json!({"odd": 1})),
+                ("title".to_string(), json!("a")),
+                (
+                    "named_vectors".to_string(),
+                    json!({"title": [5.0, 6.0], "tokens": [[1.0, 0.0], [0.0, 1.0]]}),
+                ),
+                (
+                    "sparse_vector".to_string(),
+                    json!({"indices": [4, 9], "values": [0.5, 0.25]}),
+                ),
+            ]),
+            DocMap::from([
+                ("id".to_string(), json!(7)),
+                ("vector".to_string(), json!([3.0, 4.0])),
+                ("named_vectors".to_string(), json!({"title": [7.0, 8.0]})),
+            ]),
+        ];
+
+        let batch = export_batch(&schema, stored.clone(), &params).unwrap();
+        assert_eq!(read_ids(&batch, "id").unwrap(), vec![3, 7]);
+        assert_eq!(
+            read_vectors(&batch, "vector", 2).unwrap(),
+            vec![1.0, 2.0, 3.0, 4.0]
+        );
+        assert!(read_vectors(&batch, "vector", 3).is_err());
+
+        let docs = read_json_objects(&batch, "doc").unwrap();
+        assert_eq!(docs[0].as_ref().unwrap()["title"], json!("a"));
+        assert!(docs[1].as_ref().unwrap().is_empty());
+
+        let args = import_args(&["data.parquet"]);
+        let attributes = read_attributes(&batch, &args).unwrap();
+        assert_eq!(attributes[0].as_ref().unwrap()["odd"], json!(1));
+
+        // sliced batches read the vectors of their own rows
+        assert_eq!(
+            read_vectors(&batch.slice(1, 1), "vector", 2).unwrap(),
+            vec![3.0, 4.0]
+        );
+
+        assert_eq!(
+            read_vectors(&batch, "vector_title", 2).unwrap(),
+            vec![5.0, 6.0, 7.0, 8.0]
+        );
+        assert_eq!(
+            read_multi_vectors(&batch, "vector_tokens", 2).unwrap(),
+            vec![vec![1.0, 0.0, 0.0, 1.0], vec![]]
+        );
+        let sparse = read_sparse_vectors(&batch).unwrap().unwrap();
+        assert_eq!(sparse[0].indices, vec![4, 9]);
+        assert_eq!(sparse[0].values, vec![0.5, 0.25]);
+        assert!(sparse[1].indices.is_empty());
+
+        // docs without a default vector get a null one instead of failing the export
+        let mut stored = stored;
+        stored[1].insert("vector".to_string(), Value::Null);
+        let batch = export_batch(&schema, stored.clone(), &params).unwrap();
+        assert_eq!(column(&batch, "vector").unwrap().null_count(), 1);
+        assert!(read_vectors(&batch, "vector", 2).is_err());
+
+        stored[1].insert("named_vectors".to_string(), json!({"title": [1.0]}));
+        assert!(export_batch(&schema, stored, &params).is_err());
+    }
+
+    #[test]
+    fn test_attribute_columns() {
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "id",
+                Arc::new(arrow::array::Int32Array::from(vec![1, 2])) as ArrayRef,
+            ),
+            (
+                "lang",
+                Arc::new(arrow::array::Int32Array::from(vec![Some(5), None])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+
+        let args = import_args(&["data.arrow", "--attribute-columns", "lang"]);
+        let attributes = read_attributes(&batch, &args).unwrap();
+        assert_eq!(attributes[0].as_ref().unwrap()["lang"], json!(5));
+        assert!(attributes[1].is_none());
+
+        assert_eq!(read_ids(&batch, "id").unwrap(), vec![1, 2]);
+        assert!(read_ids(&batch, "missing").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_export() {
+        let dir = std::env::temp_dir().join(format!("vecdb-collection-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let params = params();
+
+        let source = dir.join("source.parquet");
+        let schema = Arc::new(export_schema(&params));
+        let docs = (1..=5)
+            .map(|i| {
+                let mut doc = DocMap::from([
+                    ("id".to_string(), json!(i * 10)),
+                    ("vector".to_string(), json!([i as f32, 0.0])),
+                    ("attributes".to_string(), json!({"odd": i % 2})),
+                    ("rank".to_string(), json!(i)),
+                    (
+                        "named_vectors".to_string(),
+                        json!({"title": [0.0, i as f32], "tokens": [[i as f32, 1.0]]}),
+                    ),
+                ]);
+                if i == 2 {
+                    doc.insert(
+                        "sparse_vector".to_string(),
+                        json!({"indices": [i], "values": [1.0]}),
+                    );
+                }
+                doc
+            })
+            .collect();
+        let mut writer =
+            ArrowWriter::try_new(File::create(&source).unwrap(), Arc::clone(&schema), None)
+                .unwrap();
+        writer
+            .write(&export_batch(&schema, docs, &params).unwrap())
+            .unwrap();
+        writer.close().unwrap();
+
+        let mut vdb = VectorDatabase::new(dir.join("db"), params).unwrap();
+        let args = import_args(&[source.to_str().unwrap()]);
+        import(&mut vdb, &args, 2).await.unwrap();
+        // the ids are taken
+        assert!(import(&mut vdb, &args, 2).await.is_err());
+
+        let output = dir.join("output.parquet");
+        export(&vdb, &output, 2).unwrap();
+
+        let batches = read_batches(&output, 10)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let batch = arrow::compute::concat_batches(&schema, &batches).unwrap();
+        assert_eq!(read_ids(&batch, "id").unwrap(), vec![10, 20, 30, 40, 50]);
+        assert_eq!(read_vectors(&batch, "vector", 2).unwrap()[8], 5.0);
+        let docs = read_json_objects(&batch, "doc").unwrap();
+        assert_eq!(docs[2].as_ref().unwrap()["rank"], json!(3));
+        let attributes = read_json_objects(&batch, "attributes").unwrap();
+        assert_eq!(attributes[2].as_ref().unwrap()["odd"], json!(1));
+        assert_eq!(read_vectors(&batch, "vector_title", 2).unwrap()[9], 5.0);
+        assert_eq!(
+            read_multi_vectors(&batch, "vector_tokens", 2).unwrap()[3],
+            vec![4.0, 1.0]
+        );
+        let sparse = read_sparse_vectors(&batch).unwrap().unwrap();
+        assert_eq!(sparse[1].indices, vec![2]);
+        assert!(sparse[0].indices.is_empty());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -17,6 +17,7 @@ pub struct IntFilterInput {
     pub target: i64,
 }
 
+#[derive(Default)]
 pub struct IntFilterIndex {
     pub int_field_filters: AttrLookupTable,
 }
@@ -51,6 +52,53 @@ impl IntFilterIndex {
         }
     }
 
+    // Number of ids per value of the field, within `filter` when given. The `limit`
+    // most frequent values are returned, ties broken by ascending value
+    pub fn facet(
+        &self,
+        field: &str,
+        filter: Option<&RoaringBitmap>,
+        limit: usize,
+    ) -> Vec<(i64, u64)> {
+        let Some(filter_map_by_value) = self.int_field_filters.get(field) else {
+            return vec![];
+        };
+
+        let mut counts = filter_map_by_value
+            .iter()
+            .map(|(value, bitmap)| {
+                let count = match filter {
+                    Some(filter) => bitmap.intersection_len(filter),
+                    None => bitmap.len(),
+                };
+                (*value, count)
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect::<Vec<_>>();
+
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts.truncate(limit);
+
+        counts
+    }
+
+    // Value of the field for each id, None for ids without the field
+    pub fn values_of(&self, field: &str, ids: &[u64]) -> Vec<Option<i64>> {
+        let mut values = vec![None; ids.len()];
+
+        if let Some(filter_map_by_value) = self.int_field_filters.get(field) {
+            for (value, bitmap) in filter_map_by_value {
+                for (id, slot) in ids.iter().zip(values.iter_mut()) {
+                    if bitmap.contains(*id as u32) {
+                        *slot = Some(*value);
+                    }
+                }
+            }
+        }
+
+        values
+    }
+
     pub fn apply(&self, input: &IntFilterInput, bitmap: &RoaringBitmap) -> RoaringBitmap {
         if input.op == FilterOp::Equal {
             let cur_bitmap_opt = self
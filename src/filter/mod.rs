@@ -9,7 +9,7 @@ pub use index::{FilterOp, IntFilterIndex, IntFilterInput};
 use roaring::RoaringBitmap;
 use std::fmt::Debug;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct IdFilter(RoaringBitmap);
 
 #[allow(dead_code)]
@@ -0,0 +1,414 @@
+use futures::lock::Mutex;
+use serde_json::Value;
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+use tracing::{event, span, Level};
+
+use crate::filter::{FilterOp, IntFilterInput};
+use crate::index::{HnswParams, HnswSearchOption, VamanaSearchOption};
+use crate::merror::DBError;
+use crate::vecdb::{DocMap, VdbSearchArgs, VdbUpsertArgs, VectorArgs, VectorDatabase};
+
+pub mod proto {
+    tonic::include_proto!("vecdb");
+}
+
+use proto::vector_db_server::{VectorDb, VectorDbServer};
+
+// bulk upserts carry far more than the 4 MiB tonic accepts by default
+const MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+
+// Serves the gRPC API from the database state shared with the HTTP API
+pub struct VectorDbService {
+    vdb: Arc<Mutex<VectorDatabase>>,
+}
+
+impl VectorDbService {
+    pub fn new(vdb: Arc<Mutex<VectorDatabase>>) -> Self {
+        Self { vdb }
+    }
+
+    pub fn into_server(self) -> VectorDbServer<Self> {
+        VectorDbServer::new(self)
+            .max_decoding_message_size(MAX_MESSAGE_SIZE)
+            .max_encoding_message_size(MAX_MESSAGE_SIZE)
+    }
+}
+
+// Same classification as the HTTP error codes
+fn status_of(e: DBError) -> Status {
+    match e {
+        DBError::ValidationError(_) => Status::invalid_argument(e.to_string()),
+        DBError::NotFoundError(_) => Status::not_found(e.to_string()),
+        DBError::ConflictError(_) => Status::failed_precondition(e.to_string()),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+fn search_args(request: proto::SearchRequest) -> Result<VdbSearchArgs, Status> {
+    let filter_inputs = request
+        .filters
+        .into_iter()
+        .map(|filter| {
+            let op = match proto::FilterOp::try_from(filter.op) {
+                Ok(proto::FilterOp::Equal) => FilterOp::Equal,
+                Ok(proto::FilterOp::NotEqual) => FilterOp::NotEqual,
+                Err(_) => {
+                    return Err(Status::invalid_argument(format!(
+                        "unknown filter op: {}",
+                        filter.op
+                    )))
+                }
+            };
+
+            Ok(IntFilterInput {
+                field: filter.field,
+                op,
+                target: filter.target,
+            })
+        })
+        .collect::<Result<Vec<_>, Status>>()?;
+
+    Ok(VdbSearchArgs {
+        query: request.query,
+        k: request.k as usize,
+        filter_inputs: (!filter_inputs.is_empty()).then_some(filter_inputs),
+        hnsw_params: request
+            .ef_search
+            .map(|ef_search| HnswSearchOption { ef_search }),
+        vamana_params: request
+            .search_list_size
+            .map(|search_list_size| VamanaSearchOption { search_list_size }),
+        with_vectors: Some(request.with_vectors),
+        vector_field: request.vector_field,
+        ..Default::default()
+    })
+}
+
+// Moves the id, score and default vector of a doc out of its JSON
+fn document(mut doc: DocMap) -> Result<proto::Document, Status> {
+    let id = doc.remove("id").and_then(|id| id.as_u64()).unwrap_or(0);
+    let score = doc
+        .remove("score")
+        .and_then(|score| score.as_f64())
+        .map(|score| score as f32);
+    let vector = match doc.remove("vector") {
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(|v| v.as_f64().map(|v| v as f32))
+            .collect(),
+        _ => vec![],
+    };
+
+    Ok(proto::Document {
+        id,
+        score,
+        json: serde_json::to_string(&doc)
+            .map_err(|e| Status::internal(format!("unable to serialize doc: {e}")))?,
+        vector,
+    })
+}
+
+fn documents(docs: Vec<DocMap>) -> Result<Vec<proto::Document>, Status> {
+    docs.into_iter().map(document).collect()
+}
+
+// Parses one JSON object per row, none at all meaning that no row has one
+fn json_rows(name: &str, rows: &[String], data_row: usize) -> Result<Option<Vec<DocMap>>, Status> {
+    if rows.is_empty() {
+        return Ok(None);
+    }
+
+    if rows.len() != data_row {
+        return Err(Status::invalid_argument(format!(
+            "unexpected number of {name}: {}, expected {data_row}",
+            rows.len()
+        )));
+    }
+
+    rows.iter()
+        .map(|row| {
+            serde_json::from_str(row)
+                .map_err(|e| Status::invalid_argument(format!("invalid JSON in {name}: {e}")))
+        })
+        .collect::<Result<Vec<_>, Status>>()
+        .map(Some)
+}
+
+fn upsert_args(request: proto::UpsertRequest) -> Result<VdbUpsertArgs, Status> {
+    let dim = request.dim as usize;
+    if dim == 0 || request.flat_data.len() % dim != 0 {
+        return Err(Status::invalid_argument(format!(
+            "flat data length {} is not a multiple of dimension {dim}",
+            request.flat_data.len()
+        )));
+    }
+    let data_row = request.flat_data.len() / dim;
+
+    let docs = match json_rows("docs", &request.docs, data_row)? {
+        Some(docs) => docs.into_iter().map(Some).collect(),
+        None => vec![None; data_row],
+    };
+    let attributes = json_rows("attributes", &request.attributes, data_row)?
+        .map(|attributes| attributes.into_iter().map(Some).collect())
+        .unwrap_or_default();
+
+    Ok(VdbUpsertArgs {
+        vectors: VectorArgs {
+            flat_data: request.flat_data,
+            data_row,
+            data_dim: dim,
+            sparse_data: None,
+            named_data: None,
+            multi_data: None,
+        },
+        docs,
+        attributes,
+        hnsw_params: request.parallel.then_some(HnswParams { parallel: true }),
+    })
+}
+
+#[tonic::async_trait]
+impl VectorDb for VectorDbService {
+    async fn search(
+        &self,
+        request: Request<proto::SearchRequest>,
+    ) -> Result<Response<proto::SearchResponse>, Status> {
+        let span = span!(Level::TRACE, "grpc_search");
+        let _enter = span.enter();
+
+        let search_args = search_args(request.into_inner())?;
+
+        let results = {
+            let mut vdb_guard = self.vdb.lock().await;
+
+            vdb_guard.query(search_args).await.map_err(status_of)?
+        };
+
+        Ok(Response::new(proto::SearchResponse {
+            results: documents(results)?,
+        }))
+    }
+
+    async fn batch_search(
+        &self,
+        request: Request<proto::BatchSearchRequest>,
+    ) -> Result<Response<proto::BatchSearchResponse>, Status> {
+        let span = span!(Level::TRACE, "grpc_batch_search");
+        let _enter = span.enter();
+
+        let searches = request
+            .into_inner()
+            .searches
+            .into_iter()
+            .map(search_args)
+            .collect::<Result<Vec<_>, Status>>()?;
+        event!(Level::INFO, "Received batch of {} searches", searches.len());
+
+        let mut results = Vec::with_capacity(searches.len());
+        {
+            let mut vdb_guard = self.vdb.lock().await;
+
+            for search_args in searches {
+                let docs = vdb_guard.query(search_args).await.map_err(status_of)?;
+                results.push(proto::SearchResponse {
+                    results: documents(docs)?,
+                });
+            }
+        }
+
+        Ok(Response::new(proto::BatchSearchResponse { results }))
+    }
+
+    async fn upsert(
+        &self,
+        request: Request<proto::UpsertRequest>,
+    ) -> Result<Response<proto::UpsertResponse>, Status> {
+        let span = span!(Level::TRACE, "grpc_upsert");
+        let _enter = span.enter();
+
+        let upsert_args = upsert_args(request.into_inner())?;
+        let rows = upsert_args.vectors.data_row as u64;
+        event!(Level::INFO, "Received upsert of {rows} rows");
+
+        {
+            let mut vdb_guard = self.vdb.lock().await;
+
+            vdb_guard.upsert(upsert_args).await.map_err(status_of)?;
+        }
+
+        Ok(Response::new(proto::UpsertResponse { rows }))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<proto::DeleteRequest>,
+    ) -> Result<Response<proto::DeleteResponse>, Status> {
+        let span = span!(Level::TRACE, "grpc_delete");
+        let _enter = span.enter();
+
+        let ids = request.into_inner().ids;
+
+        let deleted = {
+            let mut vdb_guard = self.vdb.lock().await;
+
+            vdb_guard.delete(&ids).await.map_err(status_of)?
+        };
+
+        Ok(Response::new(proto::DeleteResponse {
+            deleted: deleted as u64,
+        }))
+    }
+
+    async fn get(
+        &self,
+        request: Request<proto::GetRequest>,
+    ) -> Result<Response<proto::GetResponse>, Status> {
+        let span = span!(Level::TRACE, "grpc_get");
+        let _enter = span.enter();
+
+        let request = request.into_inner();
+
+        let docs = {
+            let vdb_guard = self.vdb.lock().await;
+
+            vdb_guard
+                .get(&request.ids, request.with_vectors)
+                .map_err(status_of)?
+        };
+
+        Ok(Response::new(proto::GetResponse {
+            docs: documents(docs)?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{IndexType, MetricType};
+    use crate::vecdb::DatabaseParams;
+
+    fn test_service() -> VectorDbService {
+        let db_path = std::env::temp_dir()
+            .join("test_grpc")
+            .join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&db_path).unwrap();
+
+        let params = DatabaseParams {
+            dim: 2,
+            metric_type: MetricType::L2,
+            index_type: IndexType::Flat,
+            hnsw_params: None,
+            vamana_params: None,
+            text_fields: None,
+            bm25_params: None,
+            vector_fields: None,
+            brute_force_selectivity: None,
+            shadow_sample_rate: None,
+            version: "0.1.0".to_string(),
+        };
+
+        VectorDbService::new(Arc::new(Mutex::new(
+            VectorDatabase::new(db_path, params).unwrap(),
+        )))
+    }
+
+    #[tokio::test]
+    async fn test_grpc_service() {
+        let service = test_service();
+
+        let response = service
+            .upsert(Request::new(proto::UpsertRequest {
+                flat_data: vec![0.0, 0.0, 1.0, 1.0, 5.0, 5.0],
+                dim: 2,
+                docs: vec![],
+                attributes: vec![
+                    r#"{"odd": 1}"#.to_string(),
+                    r#"{"odd": 0}"#.to_string(),
+                    r#"{"odd": 1}"#.to_string(),
+                ],
+                parallel: false,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(response.into_inner().rows, 3);
+
+        let search = |query: Vec<f32>, filters: Vec<proto::Filter>| proto::SearchRequest {
+            query,
+            k: 2,
+            filters,
+            with_vectors: true,
+            ..Default::default()
+        };
+        let ids = |docs: &[proto::Document]| docs.iter().map(|doc| doc.id).collect::<Vec<_>>();
+
+        let results = service
+            .search(Request::new(search(vec![0.9, 0.9], vec![])))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        assert_eq!(ids(&results), vec![2, 1]);
+        assert_eq!(results[0].vector, vec![1.0, 1.0]);
+        assert!(results[0].score.is_none());
+
+        let results = service
+            .batch_search(Request::new(proto::BatchSearchRequest {
+                searches: vec![
+                    search(vec![5.0, 5.0], vec![]),
+                    search(
+                        vec![0.9, 0.9],
+                        vec![proto::Filter {
+                            field: "odd".to_string(),
+                            op: proto::FilterOp::Equal as i32,
+                            target: 1,
+                        }],
+                    ),
+                ],
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        assert_eq!(ids(&results[0].results), vec![3, 2]);
+        assert_eq!(ids(&results[1].results), vec![1, 3]);
+
+        let deleted = service
+            .delete(Request::new(proto::DeleteRequest { ids: vec![1, 7] }))
+            .await
+            .unwrap()
+            .into_inner()
+            .deleted;
+        assert_eq!(deleted, 1);
+
+        let docs = service
+            .get(Request::new(proto::GetRequest {
+                ids: vec![1, 3],
+                with_vectors: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .docs;
+        assert_eq!(ids(&docs), vec![3]);
+        let json: Value = serde_json::from_str(&docs[0].json).unwrap();
+        assert_eq!(json["attributes"]["odd"], 1);
+
+        let status = service
+            .upsert(Request::new(proto::UpsertRequest {
+                flat_data: vec![0.0, 0.0, 1.0],
+                dim: 2,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = service
+            .search(Request::new(search(vec![1.0], vec![])))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
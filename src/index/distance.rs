@@ -0,0 +1,179 @@
+use crate::index::MetricType;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+// number of independent accumulators, wide enough for the compiler to emit
+// packed SIMD instructions for the inner loops
+const LANES: usize = 8;
+
+pub fn l2_sqr(a: &[f32], b: &[f32]) -> f32 {
+    let mut acc = [0f32; LANES];
+
+    let a_chunks = a.chunks_exact(LANES);
+    let b_chunks = b.chunks_exact(LANES);
+    let tail: f32 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum();
+
+    for (ca, cb) in a_chunks.zip(b_chunks) {
+        for i in 0..LANES {
+            let d = ca[i] - cb[i];
+            acc[i] += d * d;
+        }
+    }
+
+    acc.iter().sum::<f32>() + tail
+}
+
+pub fn inner_product(a: &[f32], b: &[f32]) -> f32 {
+    let mut acc = [0f32; LANES];
+
+    let a_chunks = a.chunks_exact(LANES);
+    let b_chunks = b.chunks_exact(LANES);
+    let tail: f32 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+
+    for (ca, cb) in a_chunks.zip(b_chunks) {
+        for i in 0..LANES {
+            acc[i] += ca[i] * cb[i];
+        }
+    }
+
+    acc.iter().sum::<f32>() + tail
+}
+
+/// Returns the distance with the same convention as faiss:
+/// squared euclidean distance for L2, raw inner product for IP.
+pub fn distance(metric_type: &MetricType, a: &[f32], b: &[f32]) -> f32 {
+    match metric_type {
+        MetricType::L2 => l2_sqr(a, b),
+        MetricType::IP => inner_product(a, b),
+    }
+}
+
+/// Returns a score where higher is better: the negated squared euclidean
+/// distance for L2, the raw inner product for IP.
+pub fn similarity(metric_type: &MetricType, a: &[f32], b: &[f32]) -> f32 {
+    match metric_type {
+        MetricType::L2 => -l2_sqr(a, b),
+        MetricType::IP => inner_product(a, b),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    // smaller is better, regardless of the metric
+    key: f32,
+    label: u64,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .total_cmp(&other.key)
+            .then(self.label.cmp(&other.label))
+    }
+}
+
+/// Keeps the k best labels seen during an exact scan.
+pub struct TopK {
+    k: usize,
+    metric_type: MetricType,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl TopK {
+    pub fn new(k: usize, metric_type: MetricType) -> Self {
+        Self {
+            k,
+            metric_type,
+            heap: BinaryHeap::with_capacity(k + 1),
+        }
+    }
+
+    pub fn push(&mut self, label: u64, distance: f32) {
+        if self.k == 0 {
+            return;
+        }
+
+        let key = match self.metric_type {
+            MetricType::L2 => distance,
+            MetricType::IP => -distance,
+        };
+        let candidate = Candidate { key, label };
+
+        if self.heap.len() < self.k {
+            self.heap.push(candidate);
+        } else if let Some(worst) = self.heap.peek() {
+            if candidate < *worst {
+                self.heap.pop();
+                self.heap.push(candidate);
+            }
+        }
+    }
+
+    /// Returns `(distances, labels)` ordered from the best to the worst match.
+    pub fn into_sorted(self) -> (Vec<f32>, Vec<u64>) {
+        let metric_type = self.metric_type;
+
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|c| match metric_type {
+                MetricType::L2 => (c.key, c.label),
+                MetricType::IP => (-c.key, c.label),
+            })
+            .unzip()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distances() {
+        let a = (0..19).map(|e| e as f32).collect::<Vec<f32>>();
+        let b = (0..19).map(|e| (e * 2) as f32).collect::<Vec<f32>>();
+
+        let expected_l2: f32 = a.iter().zip(&b).map(|(x, y)| (x - y) * (x - y)).sum();
+        let expected_ip: f32 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+
+        assert_eq!(l2_sqr(&a, &b), expected_l2);
+        assert_eq!(inner_product(&a, &b), expected_ip);
+    }
+
+    #[test]
+    fn test_top_k() {
+        let mut top_l2 = TopK::new(2, MetricType::L2);
+        let mut top_ip = TopK::new(2, MetricType::IP);
+        for (label, distance) in [(1u64, 3.0f32), (2, 1.0), (3, 2.0), (4, 5.0)] {
+            top_l2.push(label, distance);
+            top_ip.push(label, distance);
+        }
+
+        assert_eq!(top_l2.into_sorted(), (vec![1.0, 2.0], vec![2, 3]));
+        assert_eq!(top_ip.into_sorted(), (vec![5.0, 3.0], vec![4, 1]));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::index::{Index, MetricType, SearchResult};
 use crate::merror::IndexError;
+use faiss::selector::IdSelector;
 use faiss::Index as FIndex;
 use faiss::{index_factory, IdMap};
 use std::cmp::min;
@@ -108,6 +109,28 @@ impl Index for FlatIndex {
 
         Ok(search_res)
     }
+
+    fn count(&self) -> usize {
+        self.index.lock().map_or(0, |index| index.ntotal() as usize)
+    }
+
+    fn remove(&mut self, labels: &[u64]) -> Result<usize, IndexError> {
+        let mut index_guard = self
+            .index
+            .lock()
+            .map_err(|e| IndexError::UnexpectedError(format!("Failed to lock index: {e}")))?;
+
+        let ids = labels
+            .iter()
+            .map(|&id| faiss::Idx::new(id))
+            .collect::<Vec<_>>();
+        let selector =
+            IdSelector::batch(&ids).map_err(|e| IndexError::RemovalError(e.to_string()))?;
+
+        index_guard
+            .remove_ids(&selector)
+            .map_err(|e| IndexError::RemovalError(e.to_string()))
+    }
 }
 
 #[cfg(test)]
@@ -189,4 +212,20 @@ mod tests {
         assert_eq!(search_result.labels.len(), k);
         assert_ne!(search_result.labels[0], labels[0]);
     }
+
+    #[test]
+    fn test_remove() {
+        let (mut index, data, labels) = setup(3, 4, MetricType::L2);
+        let insert_result = index.insert(&InsertParams::new(&data, &labels));
+        assert!(insert_result.is_ok());
+
+        let removed = index.remove(&labels[..1]);
+        assert!(removed.is_ok(), "error from remove {:?}", removed.err());
+        assert_eq!(removed.unwrap(), 1);
+
+        let result = index
+            .search(&SearchQuery::new(vec![1.0, 2.0, 3.0, 4.0]), 3)
+            .unwrap();
+        assert_eq!(result.labels, labels[1..].to_vec());
+    }
 }
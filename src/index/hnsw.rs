@@ -14,6 +14,7 @@ const DEFAULT_EF_CONSTRUCTION: u32 = 200;
 const DEFAULT_MAX_ELEMENTS: u32 = 500;
 const DEFAULT_MAX_NB_CONNECTION: u32 = 16;
 const DEFAULT_MAX_LAYER: u32 = 3;
+const DEFAULT_EF_SEARCH: u32 = 64;
 
 lazy_static! {
     static ref EF_CONSTRUCTION: u32 =
@@ -23,6 +24,7 @@ lazy_static! {
     static ref MAX_NB_CONNECTION: u32 =
         read_hnsw_config_from_env("HNSW_MAX_NB_CONNECTION", DEFAULT_MAX_NB_CONNECTION);
     static ref MAX_LAYER: u32 = read_hnsw_config_from_env("HNSW_MAX_LAYER", DEFAULT_MAX_LAYER);
+    static ref EF_SEARCH: u32 = read_hnsw_config_from_env("HNSW_EF_SEARCH", DEFAULT_EF_SEARCH);
 }
 
 fn read_hnsw_config_from_env(name: &str, default: u32) -> u32 {
@@ -33,7 +35,6 @@ fn read_hnsw_config_from_env(name: &str, default: u32) -> u32 {
 }
 
 pub trait HnswIndexTrait: hnsw_api::AnnT<Val = FT> + Send + Sync {
-    #[allow(unused)]
     fn get_nb_point(&self) -> usize;
 
     fn search_filter(
@@ -67,17 +68,20 @@ where
 pub struct HnswIndex {
     index: Box<dyn HnswIndexTrait>,
     dim: u32,
+    ef_search: u32,
 }
 
 unsafe impl Send for HnswIndex {}
 unsafe impl Sync for HnswIndex {}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct HnswIndexOption {
     pub ef_construction: Option<u32>,
     pub max_elements: Option<u32>,
     pub max_nb_connection: Option<u32>,
     pub max_layer: Option<u32>,
+    // used by queries that do not set their own ef_search
+    pub ef_search: Option<u32>,
 }
 
 struct HnswIndexSetting {
@@ -85,6 +89,7 @@ struct HnswIndexSetting {
     max_elements: u32,
     max_nb_connection: u32,
     max_layer: u32,
+    ef_search: u32,
 }
 
 impl From<HnswIndexOption> for HnswIndexSetting {
@@ -94,6 +99,7 @@ impl From<HnswIndexOption> for HnswIndexSetting {
             max_elements: option.max_elements.unwrap_or(*MAX_ELEMENTS),
             max_nb_connection: option.max_nb_connection.unwrap_or(*MAX_NB_CONNECTION),
             max_layer: option.max_layer.unwrap_or(*MAX_LAYER),
+            ef_search: option.ef_search.unwrap_or(*EF_SEARCH),
         }
     }
 }
@@ -105,6 +111,7 @@ impl From<HnswIndexSetting> for Option<HnswIndexOption> {
             max_elements: Some(setting.max_elements),
             max_nb_connection: Some(setting.max_nb_connection),
             max_layer: Some(setting.max_layer),
+            ef_search: Some(setting.ef_search),
         })
     }
 }
@@ -122,6 +129,7 @@ impl HnswIndex {
                 max_elements: *MAX_ELEMENTS,
                 max_nb_connection: *MAX_NB_CONNECTION,
                 max_layer: *MAX_LAYER,
+                ef_search: *EF_SEARCH,
             },
         };
 
@@ -145,6 +153,7 @@ impl HnswIndex {
         Ok(Self {
             index: index_box,
             dim,
+            ef_search: setting.ef_search,
         })
     }
 }
@@ -194,22 +203,33 @@ impl Index for HnswIndex {
     }
 
     fn search(&mut self, query: &SearchQuery, k: usize) -> Result<SearchResult, IndexError> {
-        let hnsw_opt = query.get_hnsw()?;
+        let ef_search = query
+            .get_hnsw()
+            .map_or(self.ef_search, |hnsw_opt| hnsw_opt.ef_search) as usize;
 
         if let Some(filter) = &query.id_filter {
-            let neighbours =
-                self.index
-                    .search_filter(&query.vector, k, hnsw_opt.ef_search as usize, filter);
+            let neighbours = self
+                .index
+                .search_filter(&query.vector, k, ef_search, filter);
 
             return Ok(neighbours.into());
         }
 
-        let neighbours =
-            self.index
-                .search_neighbours(&query.vector, k, hnsw_opt.ef_search as usize);
+        let neighbours = self.index.search_neighbours(&query.vector, k, ef_search);
 
         Ok(neighbours.into())
     }
+
+    fn count(&self) -> usize {
+        self.index.get_nb_point()
+    }
+
+    fn remove(&mut self, _labels: &[u64]) -> Result<usize, IndexError> {
+        // hnsw_rs has no support for deleting points from the graph
+        Err(IndexError::RemovalError(
+            "HNSW index does not support removing vectors".to_string(),
+        ))
+    }
 }
 
 #[cfg(test)]
@@ -230,6 +250,7 @@ mod tests {
                 max_elements: 100,
                 max_nb_connection: 100,
                 max_layer: 16,
+                ef_search: 20,
             }
             .into(),
         )
@@ -283,6 +304,10 @@ mod tests {
         println!("{search_result:?}");
         assert_eq!(search_result.labels.len(), k);
         assert_eq!(search_result.labels[0], labels[0]);
+
+        // falls back to the default ef_search of the index
+        let result = index.search(&SearchQuery::new(vec![1.1, 2.1, 2.9, 3.9]), k);
+        assert_eq!(result.unwrap().labels[0], labels[0]);
     }
 
     #[test]
@@ -0,0 +1,448 @@
+use crate::index::distance::{distance, TopK};
+use crate::index::option::{InsertParams, SearchQuery};
+use crate::index::{Index, MetricType, SearchResult};
+use crate::merror::IndexError;
+use memmap2::MmapMut;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+// file layout:
+// header: magic (8 bytes) | dim (u32) | metric (u32) | count (u64) | live (u64)
+// record: label (u64) | flags (u32) | reserved (u32) | vector (dim * f32, native byte order)
+const MAGIC: &[u8; 8] = b"VDBFLAT1";
+const HEADER_SIZE: usize = 32;
+const RECORD_META_SIZE: usize = 16;
+const INITIAL_CAPACITY: usize = 1024;
+const FLAG_TOMBSTONE: u32 = 1;
+
+/// Flat index whose vectors live in a memory-mapped file instead of the heap.
+///
+/// Records are only ever appended; removed vectors are marked with a tombstone
+/// and skipped by the exact scan. Opening an existing file maps it directly,
+/// no load step is needed.
+pub struct MmapFlatIndex {
+    file: File,
+    mmap: MmapMut,
+    dim: u32,
+    metric_type: MetricType,
+    // records written, removed ones included
+    count: usize,
+    // records not removed
+    live: usize,
+    capacity: usize,
+}
+
+unsafe impl Send for MmapFlatIndex {}
+unsafe impl Sync for MmapFlatIndex {}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+impl MmapFlatIndex {
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        dim: u32,
+        metric_type: MetricType,
+    ) -> Result<Self, IndexError> {
+        let exists = path
+            .as_ref()
+            .metadata()
+            .map(|m| m.len() > 0)
+            .unwrap_or(false);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| {
+                IndexError::InitializationError(format!("failed to open index file: {e}"))
+            })?;
+
+        let record_size = RECORD_META_SIZE + dim as usize * 4;
+
+        if !exists {
+            file.set_len((HEADER_SIZE + INITIAL_CAPACITY * record_size) as u64)
+                .map_err(|e| {
+                    IndexError::InitializationError(format!("failed to allocate index file: {e}"))
+                })?;
+        }
+
+        // SAFETY: the file is owned by this index and is not truncated while mapped
+        let mmap = unsafe { MmapMut::map_mut(&file) }.map_err(|e| {
+            IndexError::InitializationError(format!("failed to map index file: {e}"))
+        })?;
+
+        let capacity = (mmap.len() - HEADER_SIZE) / record_size;
+
+        let mut index = Self {
+            file,
+            mmap,
+            dim,
+            metric_type,
+            count: 0,
+            live: 0,
+            capacity,
+        };
+
+        if exists {
+            index.read_header()?;
+        } else {
+            index.write_header();
+        }
+
+        Ok(index)
+    }
+
+    fn read_header(&mut self) -> Result<(), IndexError> {
+        if self.mmap.len() < HEADER_SIZE || &self.mmap[0..8] != MAGIC {
+            return Err(IndexError::InitializationError(
+                "index file is not a flat mmap index".to_string(),
+            ));
+        }
+
+        let dim = read_u32(&self.mmap, 8);
+        if dim != self.dim {
+            return Err(IndexError::InitializationError(format!(
+                "index file dimension {dim} does not match index dimension {}",
+                self.dim
+            )));
+        }
+
+        let metric = read_u32(&self.mmap, 12);
+        if metric != self.metric_type.clone() as u32 {
+            return Err(IndexError::InitializationError(format!(
+                "index file metric {metric} does not match metric {:?}",
+                self.metric_type
+            )));
+        }
+
+        self.count = read_u64(&self.mmap, 16) as usize;
+        self.live = read_u64(&self.mmap, 24) as usize;
+
+        Ok(())
+    }
+
+    fn write_header(&mut self) {
+        let metric = self.metric_type.clone() as u32;
+
+        self.mmap[0..8].copy_from_slice(MAGIC);
+        self.mmap[8..12].copy_from_slice(&self.dim.to_le_bytes());
+        self.mmap[12..16].copy_from_slice(&metric.to_le_bytes());
+        self.mmap[16..24].copy_from_slice(&(self.count as u64).to_le_bytes());
+        self.mmap[24..32].copy_from_slice(&(self.live as u64).to_le_bytes());
+    }
+
+    fn record_size(&self) -> usize {
+        RECORD_META_SIZE + self.dim as usize * 4
+    }
+
+    fn record_offset(&self, slot: usize) -> usize {
+        HEADER_SIZE + slot * self.record_size()
+    }
+
+    fn label_at(&self, slot: usize) -> u64 {
+        read_u64(&self.mmap, self.record_offset(slot))
+    }
+
+    fn is_tombstone(&self, slot: usize) -> bool {
+        read_u32(&self.mmap, self.record_offset(slot) + 8) & FLAG_TOMBSTONE != 0
+    }
+
+    fn vector_at(&self, slot: usize) -> &[f32] {
+        let offset = self.record_offset(slot) + RECORD_META_SIZE;
+        let bytes = &self.mmap[offset..offset + self.dim as usize * 4];
+
+        // SAFETY: the mapping is page aligned and every record offset is a multiple
+        // of 4, so the vector bytes are properly aligned for f32. Vectors are written
+        // in native byte order by `insert`.
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const f32, self.dim as usize) }
+    }
+
+    fn ensure_capacity(&mut self, extra: usize) -> Result<(), IndexError> {
+        if self.count + extra <= self.capacity {
+            return Ok(());
+        }
+
+        let new_capacity = (self.capacity * 2).max(self.count + extra);
+        let new_len = HEADER_SIZE + new_capacity * self.record_size();
+
+        self.mmap
+            .flush()
+            .map_err(|e| IndexError::InsertionError(format!("failed to flush index file: {e}")))?;
+        self.file
+            .set_len(new_len as u64)
+            .map_err(|e| IndexError::InsertionError(format!("failed to grow index file: {e}")))?;
+
+        // SAFETY: see `open`
+        self.mmap = unsafe { MmapMut::map_mut(&self.file) }
+            .map_err(|e| IndexError::InsertionError(format!("failed to remap index file: {e}")))?;
+        self.capacity = new_capacity;
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Index for MmapFlatIndex {
+    fn insert(&mut self, params: &InsertParams) -> Result<(), IndexError> {
+        if params.data.nrows() != params.labels.len() {
+            return Err(IndexError::InsertionError(format!(
+                "data rows {} and labels {} do not match",
+                params.data.nrows(),
+                params.labels.len()
+            )));
+        }
+
+        if params.data.ncols() != self.dim as usize {
+            return Err(IndexError::InsertionError(format!(
+                "data dimension {} does not match index dimension {}",
+                params.data.ncols(),
+                self.dim
+            )));
+        }
+
+        self.ensure_capacity(params.labels.len())?;
+
+        for (row, &label) in params
+            .data
+            .axis_iter(ndarray::Axis(0))
+            .zip(params.labels.iter())
+        {
+            let offset = self.record_offset(self.count);
+
+            self.mmap[offset..offset + 8].copy_from_slice(&label.to_le_bytes());
+            self.mmap[offset + 8..offset + 16].fill(0);
+
+            let mut value_offset = offset + RECORD_META_SIZE;
+            for value in row.iter() {
+                self.mmap[value_offset..value_offset + 4].copy_from_slice(&value.to_ne_bytes());
+                value_offset += 4;
+            }
+
+            self.count += 1;
+            self.live += 1;
+        }
+
+        self.write_header();
+        self.mmap
+            .flush_async()
+            .map_err(|e| IndexError::InsertionError(format!("failed to flush index file: {e}")))?;
+
+        Ok(())
+    }
+
+    fn search(&mut self, query: &SearchQuery, k: usize) -> Result<SearchResult, IndexError> {
+        if query.vector.len() != self.dim as usize {
+            return Err(IndexError::QueryError(format!(
+                "query dimension {} does not match index dimension {}",
+                query.vector.len(),
+                self.dim
+            )));
+        }
+
+        let mut top_k = TopK::new(k, self.metric_type.clone());
+
+        for slot in 0..self.count {
+            if self.is_tombstone(slot) {
+                continue;
+            }
+
+            let label = self.label_at(slot);
+            if let Some(filter) = &query.id_filter {
+                if !filter.filter(&label) {
+                    continue;
+                }
+            }
+
+            top_k.push(
+                label,
+                distance(&self.metric_type, &query.vector, self.vector_at(slot)),
+            );
+        }
+
+        let (distances, labels) = top_k.into_sorted();
+
+        Ok(SearchResult { distances, labels })
+    }
+
+    fn count(&self) -> usize {
+        self.len()
+    }
+
+    fn remove(&mut self, labels: &[u64]) -> Result<usize, IndexError> {
+        let to_remove: HashSet<u64> = labels.iter().copied().collect();
+        let mut removed = 0;
+
+        for slot in 0..self.count {
+            // labels are unique, the walk ends once all of them are found
+            if removed == to_remove.len() {
+                break;
+            }
+
+            if self.is_tombstone(slot) || !to_remove.contains(&self.label_at(slot)) {
+                continue;
+            }
+
+            let flags_offset = self.record_offset(slot) + 8;
+            self.mmap[flags_offset..flags_offset + 4]
+                .copy_from_slice(&FLAG_TOMBSTONE.to_le_bytes());
+            removed += 1;
+        }
+
+        self.live -= removed;
+        self.write_header();
+
+        self.mmap
+            .flush_async()
+            .map_err(|e| IndexError::RemovalError(format!("failed to flush index file: {e}")))?;
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::IdFilter;
+    use ndarray::Array2 as NMatrix;
+    use std::fs;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    fn setup(
+        nrow: u32,
+        dim: u32,
+        metric_type: MetricType,
+    ) -> (MmapFlatIndex, NMatrix<f32>, Vec<u64>, PathBuf) {
+        let dir = PathBuf::from("/tmp/test_db").join(format!("mmap_{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("index.bin");
+
+        let index =
+            MmapFlatIndex::open(&path, dim, metric_type).expect("Failed to initialize index");
+
+        let data = NMatrix::from_shape_vec(
+            (nrow as usize, dim as usize),
+            (1..(dim * nrow + 1))
+                .map(|e| e as f32)
+                .collect::<Vec<f32>>(),
+        )
+        .unwrap();
+
+        let labels = (1u64..(nrow + 1) as u64).collect::<Vec<u64>>();
+
+        (index, data, labels, dir)
+    }
+
+    #[test]
+    fn test_insert_many() {
+        let (mut index, data, labels, dir) = setup(2, 4, MetricType::L2);
+        let insert_result = index.insert(&InsertParams::new(&data, &labels));
+
+        assert!(insert_result.is_ok());
+        assert_eq!(index.len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_search() {
+        let (mut index, data, labels, dir) = setup(2, 4, MetricType::L2);
+        let insert_result = index.insert(&InsertParams::new(&data, &labels));
+        assert!(insert_result.is_ok());
+
+        let query = vec![1.1, 2.1, 2.9, 3.9];
+        let k: usize = 2;
+        let result = index.search(&SearchQuery::new(query.clone()), k);
+
+        assert!(result.is_ok());
+        let search_result = result.unwrap();
+        assert_eq!(search_result.labels.len(), k);
+        assert_eq!(search_result.labels[0], labels[0]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_search_with_params() {
+        let (mut index, data, labels, dir) = setup(4, 5, MetricType::IP);
+        let insert_result = index.insert(&InsertParams::new(&data, &labels));
+        assert!(insert_result.is_ok());
+
+        let query = vec![1.1, 2.1, 2.9, 3.9, 5.0];
+        let k: usize = 3;
+        let original_result = index.search(&SearchQuery::new(query.clone()), k).unwrap();
+        assert_eq!(original_result.labels[0], labels[3]);
+
+        let mut filter = IdFilter::new();
+        filter.add_all(&labels[..3]);
+        let result = index.search(&SearchQuery::new(query).with(&filter), k);
+
+        assert!(result.is_ok(), "error from search {:?}", result.err());
+        let search_result = result.unwrap();
+        assert_eq!(search_result.labels.len(), k);
+        assert_eq!(search_result.labels[0], labels[2]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_and_reopen() {
+        let (mut index, data, labels, dir) = setup(3, 4, MetricType::L2);
+        index.insert(&InsertParams::new(&data, &labels)).unwrap();
+
+        assert_eq!(index.remove(&labels[..1]).unwrap(), 1);
+        assert_eq!(index.remove(&labels[..1]).unwrap(), 0);
+        assert_eq!(index.count(), 2);
+        drop(index);
+
+        let mut reopened = MmapFlatIndex::open(dir.join("index.bin"), 4, MetricType::L2).unwrap();
+        assert_eq!(reopened.len(), 2);
+
+        let result = reopened
+            .search(&SearchQuery::new(vec![1.0, 2.0, 3.0, 4.0]), 3)
+            .unwrap();
+        assert_eq!(result.labels, vec![labels[1], labels[2]]);
+
+        assert!(MmapFlatIndex::open(dir.join("index.bin"), 8, MetricType::L2).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_grow_file() {
+        let (mut index, _, _, dir) = setup(1, 2, MetricType::L2);
+
+        let nrow = INITIAL_CAPACITY + 10;
+        let data = NMatrix::from_shape_vec(
+            (nrow, 2),
+            (0..nrow * 2).map(|e| e as f32).collect::<Vec<f32>>(),
+        )
+        .unwrap();
+        let labels = (0..nrow as u64).collect::<Vec<u64>>();
+        index.insert(&InsertParams::new(&data, &labels)).unwrap();
+
+        assert_eq!(index.len(), nrow);
+        let result = index
+            .search(&SearchQuery::new(vec![2000.0, 2001.0]), 1)
+            .unwrap();
+        assert_eq!(result.labels, vec![1000]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+
+use crate::index::distance::similarity;
+use crate::index::MetricType;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MmrParams {
+    // trade-off in [0, 1] between relevance (1.0) and diversity (0.0)
+    pub lambda: f32,
+    // number of top hits re-ranked, a multiple of `k` when unset
+    pub candidates: Option<usize>,
+}
+
+fn min_max(values: impl Iterator<Item = f32>) -> (f32, f32) {
+    values.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+        (min.min(v), max.max(v))
+    })
+}
+
+fn normalize(value: f32, (min, max): (f32, f32)) -> f32 {
+    if max > min {
+        (value - min) / (max - min)
+    } else {
+        1.0
+    }
+}
+
+/// Maximal marginal relevance: greedily picks `k` of the `hits`, given best-first
+/// as `(id, score)` pairs, each time taking the one that maximizes
+/// `lambda * relevance - (1 - lambda) * max similarity to the picked ones`.
+/// Scores and pairwise similarities are min-max normalized so that lambda weighs
+/// comparable quantities. Hits without a stored vector are never penalized.
+/// Returns the picked hits with their original scores in pick order.
+pub fn mmr(
+    metric_type: &MetricType,
+    hits: &[(u64, f32)],
+    vectors: &[Option<Vec<f32>>],
+    lambda: f32,
+    k: usize,
+) -> Vec<(u64, f32)> {
+    let n = hits.len();
+    let lambda = lambda.clamp(0.0, 1.0);
+
+    let score_range = min_max(hits.iter().map(|(_, s)| *s));
+    let relevance = hits
+        .iter()
+        .map(|(_, s)| normalize(*s, score_range))
+        .collect::<Vec<_>>();
+
+    let mut similarities = vec![None; n * n];
+    for i in 0..n {
+        for j in (i + 1)..n {
+            if let (Some(a), Some(b)) = (&vectors[i], &vectors[j]) {
+                let s = similarity(metric_type, a, b);
+                similarities[i * n + j] = Some(s);
+                similarities[j * n + i] = Some(s);
+            }
+        }
+    }
+    let similarity_range = min_max(similarities.iter().flatten().copied());
+
+    let mut picked: Vec<usize> = Vec::with_capacity(k.min(n));
+    let mut remaining = (0..n).collect::<Vec<_>>();
+
+    while picked.len() < k && !remaining.is_empty() {
+        let (position, _) = remaining
+            .iter()
+            .enumerate()
+            .map(|(position, &i)| {
+                let redundancy = picked
+                    .iter()
+                    .filter_map(|&j| similarities[i * n + j])
+                    .map(|s| normalize(s, similarity_range))
+                    .fold(0.0, f32::max);
+
+                (
+                    position,
+                    lambda * relevance[i] - (1.0 - lambda) * redundancy,
+                )
+            })
+            // the first best one wins ties, keeping the original order
+            .fold((0, f32::NEG_INFINITY), |best, current| {
+                if current.1 > best.1 {
+                    current
+                } else {
+                    best
+                }
+            });
+
+        picked.push(remaining.remove(position));
+    }
+
+    picked.into_iter().map(|i| hits[i]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mmr() {
+        // 2 is a near duplicate of 1, 3 is less relevant but different
+        let hits = vec![(1, -0.1), (2, -0.11), (3, -0.5)];
+        let vectors = vec![
+            Some(vec![1.0, 0.0]),
+            Some(vec![0.99, 0.01]),
+            Some(vec![0.0, 1.0]),
+        ];
+
+        let ids = |hits: Vec<(u64, f32)>| hits.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+
+        // pure relevance keeps the original order
+        assert_eq!(
+            ids(mmr(&MetricType::L2, &hits, &vectors, 1.0, 3)),
+            vec![1, 2, 3]
+        );
+        // diversity pushes the duplicate down
+        assert_eq!(
+            ids(mmr(&MetricType::L2, &hits, &vectors, 0.3, 2)),
+            vec![1, 3]
+        );
+
+        // original scores are kept
+        assert_eq!(
+            mmr(&MetricType::IP, &hits, &vectors, 0.5, 1),
+            vec![(1, -0.1)]
+        );
+
+        // hits without vectors are only ranked by relevance
+        let vectors = vec![None, None, None];
+        assert_eq!(
+            ids(mmr(&MetricType::L2, &hits, &vectors, 0.5, 3)),
+            vec![1, 2, 3]
+        );
+        assert!(mmr(&MetricType::L2, &[], &[], 0.5, 3).is_empty());
+    }
+}
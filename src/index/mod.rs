@@ -1,18 +1,34 @@
+mod distance;
 mod flat;
 mod hnsw;
+mod mmap;
+mod mmr;
+mod multi;
 mod option;
+mod sparse;
+mod vamana;
 
 use crate::merror::IndexError;
+pub use distance::{similarity, TopK};
 pub use flat::FlatIndex;
 pub use hnsw::{HnswIndex, HnswIndexOption};
 use hnsw_rs::hnsw::Neighbour;
-pub use option::{HnswParams, HnswSearchOption, InsertParams, SearchQuery};
+pub use mmap::MmapFlatIndex;
+pub use mmr::{mmr, MmrParams};
+pub use multi::{max_sim, MultiVectorMap};
+pub use option::{HnswParams, HnswSearchOption, InsertParams, SearchQuery, VamanaSearchOption};
 use serde::{Deserialize, Serialize};
+pub use sparse::{SparseIndex, SparseVector};
+pub use vamana::{VamanaIndex, VamanaIndexOption};
 
 pub trait Index {
     fn insert(&mut self, params: &option::InsertParams) -> Result<(), IndexError>;
     fn search(&mut self, query: &option::SearchQuery, k: usize)
         -> Result<SearchResult, IndexError>;
+    // returns the number of vectors actually removed
+    fn remove(&mut self, labels: &[u64]) -> Result<usize, IndexError>;
+    // number of vectors held, the removed ones left out
+    fn count(&self) -> usize;
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
@@ -20,6 +36,15 @@ pub trait Index {
 pub enum IndexType {
     Flat,
     Hnsw,
+    MmapFlat,
+    Vamana,
+}
+
+impl IndexType {
+    // persistent indexes keep their data on disk and need no reload on startup
+    pub fn is_persistent(&self) -> bool {
+        matches!(self, IndexType::MmapFlat | IndexType::Vamana)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -0,0 +1,106 @@
+use crate::index::distance::similarity;
+use crate::index::MetricType;
+use roaring::RoaringBitmap;
+use std::collections::HashMap;
+
+/// Maps the labels of individual token vectors to the doc owning them,
+/// so that a vector index over tokens can serve multi-vector documents.
+#[derive(Debug, Default)]
+pub struct MultiVectorMap {
+    parents: HashMap<u64, u64>,
+    // token labels of each doc, in the order of the doc vectors
+    tokens: HashMap<u64, Vec<u64>>,
+}
+
+impl MultiVectorMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, parent: u64, token: u64) {
+        self.parents.insert(token, parent);
+        self.tokens.entry(parent).or_default().push(token);
+    }
+
+    pub fn parent_of(&self, token: u64) -> Option<u64> {
+        self.parents.get(&token).copied()
+    }
+
+    pub fn tokens_of(&self, parent: u64) -> &[u64] {
+        self.tokens
+            .get(&parent)
+            .map(|t| t.as_slice())
+            .unwrap_or(&[])
+    }
+
+    // Token labels owned by the allowed docs
+    pub fn token_filter(&self, allowed_parents: &RoaringBitmap) -> RoaringBitmap {
+        allowed_parents
+            .iter()
+            .flat_map(|parent| self.tokens_of(parent as u64))
+            .map(|token| *token as u32)
+            .collect()
+    }
+
+    pub fn remove(&mut self, parent: u64) -> Vec<u64> {
+        let tokens = self.tokens.remove(&parent).unwrap_or_default();
+        for token in &tokens {
+            self.parents.remove(token);
+        }
+        tokens
+    }
+}
+
+/// Late interaction score: for every query vector, the similarity of its best
+/// matching doc vector, summed over the query. Both sides are flat row-major
+/// matrices of `dim` columns, similarities are negated squared distances for L2.
+pub fn max_sim(metric_type: &MetricType, query: &[f32], doc: &[f32], dim: usize) -> f32 {
+    query
+        .chunks_exact(dim)
+        .map(|q| {
+            doc.chunks_exact(dim)
+                .map(|d| similarity(metric_type, q, d))
+                .fold(f32::NEG_INFINITY, f32::max)
+        })
+        .filter(|s| s.is_finite())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multi_vector_map() {
+        let mut map = MultiVectorMap::new();
+        map.insert(1, 10);
+        map.insert(1, 11);
+        map.insert(2, 12);
+
+        assert_eq!(map.parent_of(11), Some(1));
+        assert_eq!(map.parent_of(13), None);
+        assert_eq!(map.tokens_of(1), &[10, 11]);
+
+        let filter = map.token_filter(&RoaringBitmap::from_iter([2u32, 3]));
+        assert_eq!(filter.iter().collect::<Vec<_>>(), vec![12]);
+
+        assert_eq!(map.remove(1), vec![10, 11]);
+        assert_eq!(map.parent_of(10), None);
+        assert!(map.tokens_of(1).is_empty());
+    }
+
+    #[test]
+    fn test_max_sim() {
+        let doc = [1.0, 0.0, 0.0, 1.0];
+
+        // each query vector picks its best doc vector
+        let query = [2.0, 0.0, 0.0, 3.0];
+        assert_eq!(max_sim(&MetricType::IP, &query, &doc, 2), 5.0);
+
+        let query = [1.0, 0.0, 1.0, 1.0];
+        assert_eq!(max_sim(&MetricType::L2, &query, &doc, 2), -1.0);
+
+        // an empty doc contributes nothing
+        assert_eq!(max_sim(&MetricType::IP, &query, &[], 2), 0.0);
+    }
+}
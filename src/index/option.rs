@@ -1,5 +1,6 @@
 use ndarray::Array2 as NMatrix;
 
+use crate::index::sparse::SparseVector;
 use crate::{filter::IdFilter, merror::IndexError};
 
 use serde::{Deserialize, Serialize};
@@ -10,6 +11,8 @@ pub struct SearchQuery {
     pub id_filter: Option<IdFilter>,
 
     hnsw: Option<HnswSearchOption>,
+    vamana: Option<VamanaSearchOption>,
+    sparse: Option<SparseVector>,
 }
 
 pub trait SearchOption {
@@ -27,6 +30,23 @@ impl SearchOption for HnswSearchOption {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VamanaSearchOption {
+    pub search_list_size: u32,
+}
+
+impl SearchOption for VamanaSearchOption {
+    fn set_query(&self, query: &mut SearchQuery) {
+        query.vamana = Some(self.clone());
+    }
+}
+
+impl SearchOption for SparseVector {
+    fn set_query(&self, query: &mut SearchQuery) {
+        query.sparse = Some(self.clone());
+    }
+}
+
 impl SearchOption for IdFilter {
     fn set_query(&self, query: &mut SearchQuery) {
         query.id_filter = Some(self.clone());
@@ -38,6 +58,8 @@ impl SearchQuery {
         Self {
             vector,
             hnsw: None,
+            vamana: None,
+            sparse: None,
             id_filter: None,
         }
     }
@@ -47,9 +69,18 @@ impl SearchQuery {
         self
     }
 
-    pub fn get_hnsw(&self) -> Result<&HnswSearchOption, IndexError> {
-        let result = self.hnsw.as_ref().ok_or(IndexError::QueryError(
-            "HNSW search option is not set".to_string(),
+    // the HNSW and vamana indexes fall back to their build time setting
+    pub fn get_hnsw(&self) -> Option<&HnswSearchOption> {
+        self.hnsw.as_ref()
+    }
+
+    pub fn get_vamana(&self) -> Option<&VamanaSearchOption> {
+        self.vamana.as_ref()
+    }
+
+    pub fn get_sparse(&self) -> Result<&SparseVector, IndexError> {
+        let result = self.sparse.as_ref().ok_or(IndexError::QueryError(
+            "sparse query vector is not set".to_string(),
         ))?;
 
         Ok(result)
@@ -62,6 +93,8 @@ pub struct InsertParams<'a> {
     pub labels: &'a Vec<u64>,
 
     pub hnsw_params: Option<HnswParams>,
+    // only read by the sparse index, which ignores `data`
+    pub sparse_data: Option<&'a [SparseVector]>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -76,6 +109,7 @@ impl<'a> InsertParams<'a> {
             data,
             labels,
             hnsw_params: None,
+            sparse_data: None,
         }
     }
 
@@ -83,6 +117,11 @@ impl<'a> InsertParams<'a> {
         option.set_params(&mut self);
         self
     }
+
+    pub fn with_sparse(mut self, sparse_data: &'a [SparseVector]) -> Self {
+        self.sparse_data = Some(sparse_data);
+        self
+    }
 }
 
 pub trait InsertOption {
@@ -0,0 +1,218 @@
+use crate::index::distance::TopK;
+use crate::index::option::{InsertParams, SearchQuery};
+use crate::index::{Index, MetricType, SearchResult};
+use crate::merror::IndexError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Sparse vector given as parallel lists of dimensions and weights,
+/// e.g. the term weights produced by SPLADE.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SparseVector {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+impl SparseVector {
+    pub fn is_valid(&self) -> bool {
+        self.indices.len() == self.values.len()
+    }
+}
+
+/// Inverted index over sparse vectors, scored by dot product.
+///
+/// Search only walks the posting lists of the dimensions present in the
+/// query, so its cost depends on the query terms rather than on the corpus size.
+#[derive(Default)]
+pub struct SparseIndex {
+    // dimension -> (label, weight)
+    postings: HashMap<u32, Vec<(u64, f32)>>,
+    // label -> dimensions it has a posting in, used for removal
+    label_dims: HashMap<u64, Vec<u32>>,
+}
+
+impl SparseIndex {
+    pub fn new() -> Self {
+        Self {
+            postings: HashMap::new(),
+            label_dims: HashMap::new(),
+        }
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.label_dims.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.label_dims.is_empty()
+    }
+}
+
+impl Index for SparseIndex {
+    fn insert(&mut self, params: &InsertParams) -> Result<(), IndexError> {
+        let sparse_data = params.sparse_data.ok_or(IndexError::InsertionError(
+            "sparse data is not set".to_string(),
+        ))?;
+
+        if sparse_data.len() != params.labels.len() {
+            return Err(IndexError::InsertionError(format!(
+                "sparse vectors {} and labels {} do not match",
+                sparse_data.len(),
+                params.labels.len()
+            )));
+        }
+
+        if let Some(invalid) = sparse_data.iter().find(|v| !v.is_valid()) {
+            return Err(IndexError::InsertionError(format!(
+                "sparse vector has {} indices but {} values",
+                invalid.indices.len(),
+                invalid.values.len()
+            )));
+        }
+
+        self.remove(params.labels)?;
+
+        for (vector, &label) in sparse_data.iter().zip(params.labels.iter()) {
+            for (&dim, &value) in vector.indices.iter().zip(&vector.values) {
+                self.postings.entry(dim).or_default().push((label, value));
+            }
+            self.label_dims.insert(label, vector.indices.clone());
+        }
+
+        Ok(())
+    }
+
+    fn search(&mut self, query: &SearchQuery, k: usize) -> Result<SearchResult, IndexError> {
+        let sparse_query = query.get_sparse()?;
+
+        let mut scores: HashMap<u64, f32> = HashMap::new();
+        for (dim, query_value) in sparse_query.indices.iter().zip(&sparse_query.values) {
+            let Some(posting) = self.postings.get(dim) else {
+                continue;
+            };
+
+            for (label, value) in posting {
+                if let Some(filter) = &query.id_filter {
+                    if !filter.filter(label) {
+                        continue;
+                    }
+                }
+
+                *scores.entry(*label).or_default() += query_value * value;
+            }
+        }
+
+        let mut top_k = TopK::new(k, MetricType::IP);
+        for (label, score) in scores {
+            top_k.push(label, score);
+        }
+
+        let (distances, labels) = top_k.into_sorted();
+
+        Ok(SearchResult { distances, labels })
+    }
+
+    fn count(&self) -> usize {
+        self.label_dims.len()
+    }
+
+    fn remove(&mut self, labels: &[u64]) -> Result<usize, IndexError> {
+        let mut removed = 0;
+
+        for label in labels {
+            let Some(dims) = self.label_dims.remove(label) else {
+                continue;
+            };
+
+            for dim in dims {
+                if let Some(posting) = self.postings.get_mut(&dim) {
+                    posting.retain(|(l, _)| l != label);
+                    if posting.is_empty() {
+                        self.postings.remove(&dim);
+                    }
+                }
+            }
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::IdFilter;
+    use ndarray::Array2 as NMatrix;
+
+    fn sparse(pairs: &[(u32, f32)]) -> SparseVector {
+        SparseVector {
+            indices: pairs.iter().map(|(i, _)| *i).collect(),
+            values: pairs.iter().map(|(_, v)| *v).collect(),
+        }
+    }
+
+    fn setup() -> SparseIndex {
+        let mut index = SparseIndex::new();
+        let data = NMatrix::<f32>::zeros((0, 0));
+        let labels = vec![1, 2, 3];
+        let vectors = vec![
+            sparse(&[(1, 1.0), (7, 0.5)]),
+            sparse(&[(7, 2.0), (100, 1.0)]),
+            sparse(&[(3, 4.0)]),
+        ];
+
+        index
+            .insert(&InsertParams::new(&data, &labels).with_sparse(&vectors))
+            .expect("Failed to insert sparse vectors");
+        index
+    }
+
+    #[test]
+    fn test_search() {
+        let mut index = setup();
+
+        let query = SearchQuery::new(vec![]).with(&sparse(&[(7, 1.0), (1, 1.0)]));
+        let result = index.search(&query, 10).unwrap();
+        assert_eq!(result.labels, vec![2, 1]);
+        assert_eq!(result.distances, vec![2.0, 1.5]);
+
+        let query = SearchQuery::new(vec![])
+            .with(&sparse(&[(7, 1.0), (1, 1.0)]))
+            .with(&IdFilter::from(roaring::RoaringBitmap::from_iter([1u32])));
+        let result = index.search(&query, 10).unwrap();
+        assert_eq!(result.labels, vec![1]);
+
+        // a query without sparse vector is rejected
+        assert!(index.search(&SearchQuery::new(vec![]), 10).is_err());
+    }
+
+    #[test]
+    fn test_insert_invalid() {
+        let mut index = SparseIndex::new();
+        let data = NMatrix::<f32>::zeros((0, 0));
+
+        let vectors = vec![SparseVector {
+            indices: vec![1, 2],
+            values: vec![1.0],
+        }];
+        let result = index.insert(&InsertParams::new(&data, &vec![1]).with_sparse(&vectors));
+        assert!(result.is_err());
+
+        let result = index.insert(&InsertParams::new(&data, &vec![1]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut index = setup();
+
+        assert_eq!(index.remove(&[2, 42]).unwrap(), 1);
+        assert_eq!(index.len(), 2);
+
+        let query = SearchQuery::new(vec![]).with(&sparse(&[(7, 1.0), (100, 1.0)]));
+        let result = index.search(&query, 10).unwrap();
+        assert_eq!(result.labels, vec![1]);
+    }
+}
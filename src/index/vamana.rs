@@ -0,0 +1,1041 @@
+use crate::index::distance::{distance, inner_product, l2_sqr, TopK};
+use crate::index::mmap::MmapFlatIndex;
+use crate::index::option::{InsertParams, SearchQuery};
+use crate::index::{Index, MetricType, SearchResult};
+use crate::merror::IndexError;
+use memmap2::{Mmap, MmapMut};
+use ndarray_rand::rand::rngs::SmallRng;
+use ndarray_rand::rand::seq::SliceRandom;
+use ndarray_rand::rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// file layout:
+// header (64 bytes): magic | dim | metric | num_nodes (u64) | max_degree | medoid
+//                    | pq_subspaces | pq_centroids | search_list_size | beam_width
+//                    | pq_offset (u64) | tombstones (u64)
+// nodes: label (u64) | degree (u32) | flags (u32) | vector (dim * f32) | neighbors (max_degree * u32)
+// pq section: centroids (pq_subspaces * pq_centroids * sub_dim * f32) | codes (num_nodes * pq_subspaces)
+const MAGIC: &[u8; 8] = b"VDBVAMA1";
+const HEADER_SIZE: usize = 64;
+const NODE_META_SIZE: usize = 16;
+const FLAG_TOMBSTONE: u32 = 1;
+const FRESH_FILE_SUFFIX: &str = ".fresh";
+const SCRATCH_FILE_SUFFIX: &str = ".build";
+
+const PQ_MAX_CENTROIDS: usize = 256;
+const PQ_TRAIN_ITERATIONS: usize = 10;
+const PQ_MAX_TRAIN_SIZE: usize = 100_000;
+const BUILD_SEED: u64 = 42;
+
+const DEFAULT_MAX_DEGREE: u32 = 64;
+const DEFAULT_SEARCH_LIST_SIZE: u32 = 100;
+const DEFAULT_ALPHA: f32 = 1.2;
+const DEFAULT_PQ_SUBSPACES: u32 = 8;
+const DEFAULT_BEAM_WIDTH: u32 = 4;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VamanaIndexOption {
+    pub max_degree: Option<u32>,
+    pub search_list_size: Option<u32>,
+    pub alpha: Option<f32>,
+    pub pq_subspaces: Option<u32>,
+    pub beam_width: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+struct VamanaIndexSetting {
+    max_degree: u32,
+    search_list_size: u32,
+    alpha: f32,
+    pq_subspaces: u32,
+    beam_width: u32,
+}
+
+impl From<Option<VamanaIndexOption>> for VamanaIndexSetting {
+    fn from(option: Option<VamanaIndexOption>) -> Self {
+        let option = option.unwrap_or(VamanaIndexOption {
+            max_degree: None,
+            search_list_size: None,
+            alpha: None,
+            pq_subspaces: None,
+            beam_width: None,
+        });
+
+        Self {
+            max_degree: option.max_degree.unwrap_or(DEFAULT_MAX_DEGREE).max(1),
+            search_list_size: option
+                .search_list_size
+                .unwrap_or(DEFAULT_SEARCH_LIST_SIZE)
+                .max(1),
+            alpha: option.alpha.unwrap_or(DEFAULT_ALPHA),
+            pq_subspaces: option.pq_subspaces.unwrap_or(DEFAULT_PQ_SUBSPACES).max(1),
+            beam_width: option.beam_width.unwrap_or(DEFAULT_BEAM_WIDTH).max(1),
+        }
+    }
+}
+
+// smaller is better for both metrics
+fn key_distance(metric_type: &MetricType, a: &[f32], b: &[f32]) -> f32 {
+    match metric_type {
+        MetricType::L2 => l2_sqr(a, b),
+        MetricType::IP => -inner_product(a, b),
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+// vectors of a build in progress, see `VamanaIndex::build_from_iter`
+fn scratch_path(path: &Path) -> PathBuf {
+    let mut scratch: OsString = path.as_os_str().to_owned();
+    scratch.push(SCRATCH_FILE_SUFFIX);
+    PathBuf::from(scratch)
+}
+
+fn fresh_path(path: &Path) -> PathBuf {
+    let mut fresh: OsString = path.as_os_str().to_owned();
+    fresh.push(FRESH_FILE_SUFFIX);
+    PathBuf::from(fresh)
+}
+
+/// Product quantizer compressing each vector into one byte per subspace.
+struct ProductQuantizer {
+    subspaces: usize,
+    sub_dim: usize,
+    num_centroids: usize,
+    // subspaces * num_centroids * sub_dim
+    centroids: Vec<f32>,
+}
+
+impl ProductQuantizer {
+    // uses the largest divisor of dim not bigger than the requested subspaces
+    fn subspaces_for(dim: usize, requested: usize) -> usize {
+        (1..=requested.min(dim).max(1))
+            .rev()
+            .find(|m| dim % m == 0)
+            .unwrap_or(1)
+    }
+
+    fn train(data: &[f32], dim: usize, subspaces: usize, rng: &mut SmallRng) -> Self {
+        let n = data.len() / dim;
+        let sub_dim = dim / subspaces;
+
+        let mut sample: Vec<usize> = (0..n).collect();
+        sample.shuffle(rng);
+        sample.truncate(PQ_MAX_TRAIN_SIZE);
+
+        let num_centroids = sample.len().min(PQ_MAX_CENTROIDS);
+        let mut centroids = vec![0f32; subspaces * num_centroids * sub_dim];
+
+        for s in 0..subspaces {
+            let sub_vector = |i: usize| &data[i * dim + s * sub_dim..i * dim + (s + 1) * sub_dim];
+            let book =
+                &mut centroids[s * num_centroids * sub_dim..(s + 1) * num_centroids * sub_dim];
+
+            for (c, &i) in sample.iter().take(num_centroids).enumerate() {
+                book[c * sub_dim..(c + 1) * sub_dim].copy_from_slice(sub_vector(i));
+            }
+
+            // plain k-means over the training sample
+            for _ in 0..PQ_TRAIN_ITERATIONS {
+                let mut sums = vec![0f32; num_centroids * sub_dim];
+                let mut counts = vec![0usize; num_centroids];
+
+                for &i in sample.iter() {
+                    let v = sub_vector(i);
+                    let c = nearest_centroid(book, sub_dim, v);
+
+                    counts[c] += 1;
+                    for (acc, x) in sums[c * sub_dim..(c + 1) * sub_dim].iter_mut().zip(v) {
+                        *acc += x;
+                    }
+                }
+
+                for c in 0..num_centroids {
+                    // keep the previous centroid for empty clusters
+                    if counts[c] == 0 {
+                        continue;
+                    }
+                    for j in 0..sub_dim {
+                        book[c * sub_dim + j] = sums[c * sub_dim + j] / counts[c] as f32;
+                    }
+                }
+            }
+        }
+
+        Self {
+            subspaces,
+            sub_dim,
+            num_centroids,
+            centroids,
+        }
+    }
+
+    fn codebook(&self, s: usize) -> &[f32] {
+        let size = self.num_centroids * self.sub_dim;
+        &self.centroids[s * size..(s + 1) * size]
+    }
+
+    fn encode(&self, vector: &[f32], code: &mut [u8]) {
+        for (s, c) in code.iter_mut().enumerate() {
+            let v = &vector[s * self.sub_dim..(s + 1) * self.sub_dim];
+            *c = nearest_centroid(self.codebook(s), self.sub_dim, v) as u8;
+        }
+    }
+
+    // per subspace distances from the query to every centroid
+    fn distance_table(&self, metric_type: &MetricType, query: &[f32]) -> Vec<f32> {
+        let mut table = Vec::with_capacity(self.subspaces * self.num_centroids);
+
+        for s in 0..self.subspaces {
+            let q = &query[s * self.sub_dim..(s + 1) * self.sub_dim];
+            for centroid in self.codebook(s).chunks_exact(self.sub_dim) {
+                table.push(key_distance(metric_type, q, centroid));
+            }
+        }
+
+        table
+    }
+
+    fn approx_distance(&self, table: &[f32], code: &[u8]) -> f32 {
+        code.iter()
+            .enumerate()
+            .map(|(s, &c)| table[s * self.num_centroids + c as usize])
+            .sum()
+    }
+}
+
+fn nearest_centroid(book: &[f32], sub_dim: usize, v: &[f32]) -> usize {
+    book.chunks_exact(sub_dim)
+        .enumerate()
+        .map(|(c, centroid)| (c, l2_sqr(v, centroid)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(c, _)| c)
+        .unwrap_or(0)
+}
+
+/// In-memory Vamana graph construction, only used while building the index offline.
+struct GraphBuilder<'a> {
+    data: &'a [f32],
+    dim: usize,
+    metric_type: &'a MetricType,
+    max_degree: usize,
+    search_list_size: usize,
+}
+
+impl GraphBuilder<'_> {
+    fn vector(&self, i: u32) -> &[f32] {
+        &self.data[i as usize * self.dim..(i as usize + 1) * self.dim]
+    }
+
+    fn dist(&self, a: u32, b: u32) -> f32 {
+        key_distance(self.metric_type, self.vector(a), self.vector(b))
+    }
+
+    fn medoid(&self, n: usize) -> u32 {
+        let mut centroid = vec![0f32; self.dim];
+        for i in 0..n {
+            for (c, x) in centroid.iter_mut().zip(self.vector(i as u32)) {
+                *c += x / n as f32;
+            }
+        }
+
+        (0..n as u32)
+            .min_by(|&a, &b| {
+                key_distance(self.metric_type, &centroid, self.vector(a)).total_cmp(&key_distance(
+                    self.metric_type,
+                    &centroid,
+                    self.vector(b),
+                ))
+            })
+            .unwrap_or(0)
+    }
+
+    // greedy search with exact distances, returns the expanded nodes
+    fn greedy_search(&self, graph: &[Vec<u32>], start: u32, target: u32) -> Vec<u32> {
+        let mut list: Vec<(f32, u32)> = vec![(self.dist(start, target), start)];
+        let mut seen: HashSet<u32> = HashSet::from([start]);
+        let mut expanded: HashSet<u32> = HashSet::new();
+        let mut expanded_order = vec![];
+
+        while let Some(&(_, node)) = list.iter().find(|(_, n)| !expanded.contains(n)) {
+            expanded.insert(node);
+            expanded_order.push(node);
+
+            for &nb in graph[node as usize].iter() {
+                if seen.insert(nb) {
+                    list.push((self.dist(nb, target), nb));
+                }
+            }
+
+            list.sort_by(|a, b| a.0.total_cmp(&b.0));
+            list.truncate(self.search_list_size);
+        }
+
+        expanded_order
+    }
+
+    fn robust_prune(&self, p: u32, candidates: impl Iterator<Item = u32>, alpha: f32) -> Vec<u32> {
+        let mut unique: HashSet<u32> = HashSet::new();
+        let mut pool: Vec<(f32, u32)> = candidates
+            .filter(|&c| c != p && unique.insert(c))
+            .map(|c| (self.dist(p, c), c))
+            .collect();
+        pool.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut pool = VecDeque::from(pool);
+
+        let mut result = Vec::with_capacity(self.max_degree);
+        while result.len() < self.max_degree {
+            let Some((_, best)) = pool.pop_front() else {
+                break;
+            };
+            result.push(best);
+
+            pool.retain(|&(d_p_c, c)| {
+                let d_best_c = self.dist(best, c);
+                // alpha only makes sense for non-negative distances
+                let occluded = match self.metric_type {
+                    MetricType::L2 => alpha * d_best_c <= d_p_c,
+                    MetricType::IP => d_best_c <= d_p_c,
+                };
+                !occluded
+            });
+        }
+
+        result
+    }
+
+    fn build(&self, n: usize, alpha: f32, rng: &mut SmallRng) -> (Vec<Vec<u32>>, u32) {
+        if n == 0 {
+            return (vec![], 0);
+        }
+
+        // start from a random graph
+        let init_degree = self.max_degree.min(n - 1);
+        let mut graph: Vec<Vec<u32>> = (0..n as u32)
+            .map(|i| {
+                let mut neighbors = HashSet::new();
+                while neighbors.len() < init_degree {
+                    let j = rng.gen_range(0..n as u32);
+                    if j != i {
+                        neighbors.insert(j);
+                    }
+                }
+                neighbors.into_iter().collect()
+            })
+            .collect();
+
+        let medoid = self.medoid(n);
+        let mut order: Vec<u32> = (0..n as u32).collect();
+
+        // first pass without long range edges, second pass with the configured alpha
+        for pass_alpha in [1.0, alpha] {
+            order.shuffle(rng);
+
+            for &p in order.iter() {
+                let visited = self.greedy_search(&graph, medoid, p);
+                let candidates = visited.into_iter().chain(graph[p as usize].clone());
+                graph[p as usize] = self.robust_prune(p, candidates, pass_alpha);
+
+                for j in graph[p as usize].clone() {
+                    let back_edges = &mut graph[j as usize];
+                    if back_edges.contains(&p) {
+                        continue;
+                    }
+
+                    back_edges.push(p);
+                    if back_edges.len() > self.max_degree {
+                        let candidates = back_edges.clone().into_iter();
+                        graph[j as usize] = self.robust_prune(j, candidates, pass_alpha);
+                    }
+                }
+            }
+        }
+
+        (graph, medoid)
+    }
+}
+
+/// DiskANN style index: a Vamana graph whose full vectors and adjacency lists stay
+/// on disk while only the PQ codes are kept in memory.
+///
+/// The graph is built offline from the raw vectors with [`VamanaIndex::build`].
+/// Vectors inserted afterwards go to an exact mmap'd flat index next to the graph
+/// file and are merged into the graph on the next rebuild.
+pub struct VamanaIndex {
+    _file: File,
+    mmap: MmapMut,
+    dim: u32,
+    metric_type: MetricType,
+    setting: VamanaIndexSetting,
+    num_nodes: usize,
+    medoid: u32,
+    pq: ProductQuantizer,
+    pq_codes: Vec<u8>,
+    // node of every live label, so that removals do not walk the nodes on disk
+    label_nodes: HashMap<u64, u32>,
+    tombstones: usize,
+    fresh: MmapFlatIndex,
+}
+
+unsafe impl Send for VamanaIndex {}
+unsafe impl Sync for VamanaIndex {}
+
+impl VamanaIndex {
+    /// Builds the graph for the given vectors and writes it to `path`.
+    pub fn build<P: AsRef<Path>>(
+        path: P,
+        dim: u32,
+        metric_type: MetricType,
+        option: Option<VamanaIndexOption>,
+        labels: &[u64],
+        data: &[f32],
+    ) -> Result<Self, IndexError> {
+        let setting = VamanaIndexSetting::from(option);
+        let n = labels.len();
+        let dim_usize = dim as usize;
+
+        if data.len() != n * dim_usize {
+            return Err(IndexError::InitializationError(format!(
+                "data length {} does not match {n} vectors of dimension {dim}",
+                data.len()
+            )));
+        }
+
+        let mut rng = SmallRng::seed_from_u64(BUILD_SEED);
+
+        let builder = GraphBuilder {
+            data,
+            dim: dim_usize,
+            metric_type: &metric_type,
+            max_degree: setting.max_degree as usize,
+            search_list_size: setting.search_list_size as usize,
+        };
+        let (graph, medoid) = builder.build(n, setting.alpha, &mut rng);
+
+        let subspaces = ProductQuantizer::subspaces_for(dim_usize, setting.pq_subspaces as usize);
+        let pq = ProductQuantizer::train(data, dim_usize, subspaces, &mut rng);
+
+        let max_degree = setting.max_degree as usize;
+        let node_size = NODE_META_SIZE + dim_usize * 4 + max_degree * 4;
+        let pq_offset = HEADER_SIZE + n * node_size;
+
+        let file = File::create(&path).map_err(|e| {
+            IndexError::InitializationError(format!("failed to create index file: {e}"))
+        })?;
+        let mut writer = BufWriter::new(file);
+        let write_err = |e: std::io::Error| {
+            IndexError::InitializationError(format!("failed to write index file: {e}"))
+        };
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&dim.to_le_bytes());
+        header[12..16].copy_from_slice(&(metric_type.clone() as u32).to_le_bytes());
+        header[16..24].copy_from_slice(&(n as u64).to_le_bytes());
+        header[24..28].copy_from_slice(&setting.max_degree.to_le_bytes());
+        header[28..32].copy_from_slice(&medoid.to_le_bytes());
+        header[32..36].copy_from_slice(&(pq.subspaces as u32).to_le_bytes());
+        header[36..40].copy_from_slice(&(pq.num_centroids as u32).to_le_bytes());
+        header[40..44].copy_from_slice(&setting.search_list_size.to_le_bytes());
+        header[44..48].copy_from_slice(&setting.beam_width.to_le_bytes());
+        header[48..56].copy_from_slice(&(pq_offset as u64).to_le_bytes());
+        writer.write_all(&header).map_err(write_err)?;
+
+        for (i, neighbors) in graph.iter().enumerate() {
+            writer
+                .write_all(&labels[i].to_le_bytes())
+                .map_err(write_err)?;
+            writer
+                .write_all(&(neighbors.len() as u32).to_le_bytes())
+                .map_err(write_err)?;
+            writer.write_all(&0u32.to_le_bytes()).map_err(write_err)?;
+            for value in &data[i * dim_usize..(i + 1) * dim_usize] {
+                writer.write_all(&value.to_ne_bytes()).map_err(write_err)?;
+            }
+            for slot in 0..max_degree {
+                let nb = neighbors.get(slot).copied().unwrap_or(0);
+                writer.write_all(&nb.to_ne_bytes()).map_err(write_err)?;
+            }
+        }
+
+        for value in pq.centroids.iter() {
+            writer.write_all(&value.to_le_bytes()).map_err(write_err)?;
+        }
+
+        let mut code = vec![0u8; pq.subspaces];
+        for i in 0..n {
+            pq.encode(&data[i * dim_usize..(i + 1) * dim_usize], &mut code);
+            writer.write_all(&code).map_err(write_err)?;
+        }
+
+        writer.flush().map_err(write_err)?;
+        drop(writer);
+
+        // a fresh build already contains every vector
+        let fresh = fresh_path(path.as_ref());
+        if fresh.exists() {
+            std::fs::remove_file(&fresh).map_err(|e| {
+                IndexError::InitializationError(format!("failed to reset fresh vectors: {e}"))
+            })?;
+        }
+
+        Self::open(path, dim, metric_type)
+    }
+
+    /// Builds the graph like [`VamanaIndex::build`] out of streamed vectors.
+    ///
+    /// The vectors are written to a scratch file next to `path` and mapped back for the
+    /// build, so that they are held by the page cache rather than read into memory.
+    pub fn build_from_iter<P, I, E>(
+        path: P,
+        dim: u32,
+        metric_type: MetricType,
+        option: Option<VamanaIndexOption>,
+        vectors: I,
+    ) -> Result<Self, IndexError>
+    where
+        P: AsRef<Path>,
+        I: Iterator<Item = Result<(u64, Vec<f32>), E>>,
+        E: std::fmt::Display,
+    {
+        let scratch_path = scratch_path(path.as_ref());
+        let result =
+            Self::build_from_scratch(&path, &scratch_path, dim, metric_type, option, vectors);
+        std::fs::remove_file(&scratch_path).ok();
+
+        result
+    }
+
+    fn build_from_scratch<P, I, E>(
+        path: P,
+        scratch_path: &Path,
+        dim: u32,
+        metric_type: MetricType,
+        option: Option<VamanaIndexOption>,
+        vectors: I,
+    ) -> Result<Self, IndexError>
+    where
+        P: AsRef<Path>,
+        I: Iterator<Item = Result<(u64, Vec<f32>), E>>,
+        E: std::fmt::Display,
+    {
+        let scratch_err = |e: std::io::Error| {
+            IndexError::InitializationError(format!("failed to write build vectors: {e}"))
+        };
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(scratch_path)
+            .map_err(scratch_err)?;
+
+        let mut labels = vec![];
+        let mut writer = BufWriter::new(&file);
+        for item in vectors {
+            let (label, vector) = item.map_err(|e| {
+                IndexError::InitializationError(format!("failed to read build vectors: {e}"))
+            })?;
+
+            if vector.len() != dim as usize {
+                return Err(IndexError::InitializationError(format!(
+                    "vector {label} has dimension {}, expected {dim}",
+                    vector.len()
+                )));
+            }
+
+            for x in vector {
+                writer.write_all(&x.to_ne_bytes()).map_err(scratch_err)?;
+            }
+            labels.push(label);
+        }
+        writer.flush().map_err(scratch_err)?;
+        drop(writer);
+
+        if labels.is_empty() {
+            return Self::build(path, dim, metric_type, option, &[], &[]);
+        }
+
+        let mmap = unsafe { Mmap::map(&file) }.map_err(scratch_err)?;
+        // SAFETY: the mapping is page aligned and holds the vectors written above in
+        // native byte order
+        let data =
+            unsafe { std::slice::from_raw_parts(mmap.as_ptr() as *const f32, mmap.len() / 4) };
+
+        Self::build(path, dim, metric_type, option, &labels, data)
+    }
+
+    /// Opens a graph previously written by [`VamanaIndex::build`].
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        dim: u32,
+        metric_type: MetricType,
+    ) -> Result<Self, IndexError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|e| {
+                IndexError::InitializationError(format!("failed to open index file: {e}"))
+            })?;
+
+        // SAFETY: the file is owned by this index and is not truncated while mapped
+        let mmap = unsafe { MmapMut::map_mut(&file) }.map_err(|e| {
+            IndexError::InitializationError(format!("failed to map index file: {e}"))
+        })?;
+
+        if mmap.len() < HEADER_SIZE || &mmap[0..8] != MAGIC {
+            return Err(IndexError::InitializationError(
+                "index file is not a vamana index".to_string(),
+            ));
+        }
+
+        let file_dim = read_u32(&mmap, 8);
+        if file_dim != dim {
+            return Err(IndexError::InitializationError(format!(
+                "index file dimension {file_dim} does not match index dimension {dim}",
+            )));
+        }
+
+        let metric = read_u32(&mmap, 12);
+        if metric != metric_type.clone() as u32 {
+            return Err(IndexError::InitializationError(format!(
+                "index file metric {metric} does not match metric {metric_type:?}",
+            )));
+        }
+
+        let num_nodes = read_u64(&mmap, 16) as usize;
+        let setting = VamanaIndexSetting {
+            max_degree: read_u32(&mmap, 24),
+            search_list_size: read_u32(&mmap, 40),
+            alpha: DEFAULT_ALPHA,
+            pq_subspaces: read_u32(&mmap, 32),
+            beam_width: read_u32(&mmap, 44),
+        };
+        let medoid = read_u32(&mmap, 28);
+        let num_centroids = read_u32(&mmap, 36) as usize;
+        let pq_offset = read_u64(&mmap, 48) as usize;
+
+        let subspaces = setting.pq_subspaces as usize;
+        let sub_dim = dim as usize / subspaces;
+        let centroids_len = subspaces * num_centroids * sub_dim;
+        let codes_offset = pq_offset + centroids_len * 4;
+
+        if mmap.len() < codes_offset + num_nodes * subspaces {
+            return Err(IndexError::InitializationError(
+                "index file is truncated".to_string(),
+            ));
+        }
+
+        let centroids = mmap[pq_offset..codes_offset]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let pq_codes = mmap[codes_offset..codes_offset + num_nodes * subspaces].to_vec();
+        let tombstones = read_u64(&mmap, 56) as usize;
+
+        let fresh = MmapFlatIndex::open(fresh_path(path.as_ref()), dim, metric_type.clone())?;
+
+        let mut index = Self {
+            _file: file,
+            mmap,
+            dim,
+            metric_type,
+            setting,
+            num_nodes,
+            medoid,
+            pq: ProductQuantizer {
+                subspaces,
+                sub_dim,
+                num_centroids,
+                centroids,
+            },
+            pq_codes,
+            label_nodes: HashMap::new(),
+            tombstones,
+            fresh,
+        };
+        index.label_nodes = (0..num_nodes as u32)
+            .filter(|node| !index.is_tombstone(*node))
+            .map(|node| (index.label_at(node), node))
+            .collect();
+
+        Ok(index)
+    }
+
+    /// Opens the index at `path`, or builds an empty graph there when there is none yet.
+    pub fn open_or_create<P: AsRef<Path>>(
+        path: P,
+        dim: u32,
+        metric_type: MetricType,
+        option: Option<VamanaIndexOption>,
+    ) -> Result<Self, IndexError> {
+        if path.as_ref().exists() {
+            return Self::open(path, dim, metric_type);
+        }
+
+        Self::build(path, dim, metric_type, option, &[], &[])
+    }
+
+    fn node_size(&self) -> usize {
+        NODE_META_SIZE + self.dim as usize * 4 + self.setting.max_degree as usize * 4
+    }
+
+    fn node_offset(&self, node: u32) -> usize {
+        HEADER_SIZE + node as usize * self.node_size()
+    }
+
+    fn label_at(&self, node: u32) -> u64 {
+        read_u64(&self.mmap, self.node_offset(node))
+    }
+
+    fn is_tombstone(&self, node: u32) -> bool {
+        read_u32(&self.mmap, self.node_offset(node) + 12) & FLAG_TOMBSTONE != 0
+    }
+
+    fn vector_at(&self, node: u32) -> &[f32] {
+        let offset = self.node_offset(node) + NODE_META_SIZE;
+        let bytes = &self.mmap[offset..offset + self.dim as usize * 4];
+
+        // SAFETY: the mapping is page aligned and every node offset is a multiple of 4,
+        // vectors are written in native byte order by `build`
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const f32, self.dim as usize) }
+    }
+
+    fn neighbors_at(&self, node: u32) -> &[u32] {
+        let offset = self.node_offset(node);
+        let degree = read_u32(&self.mmap, offset + 8) as usize;
+        let start = offset + NODE_META_SIZE + self.dim as usize * 4;
+        let bytes = &self.mmap[start..start + degree * 4];
+
+        // SAFETY: same as `vector_at`
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const u32, degree) }
+    }
+
+    fn pq_code(&self, node: u32) -> &[u8] {
+        let m = self.pq.subspaces;
+        &self.pq_codes[node as usize * m..(node as usize + 1) * m]
+    }
+
+    // beam search guided by PQ distances, every expanded node is re-ranked with its full vector
+    fn search_graph(&self, query: &SearchQuery, search_list_size: usize, top_k: &mut TopK) {
+        if self.num_nodes == 0 {
+            return;
+        }
+
+        let table = self.pq.distance_table(&self.metric_type, &query.vector);
+        let approx = |node: u32| self.pq.approx_distance(&table, self.pq_code(node));
+
+        let mut list: Vec<(f32, u32)> = vec![(approx(self.medoid), self.medoid)];
+        let mut seen: HashSet<u32> = HashSet::from([self.medoid]);
+        let mut expanded: HashSet<u32> = HashSet::new();
+
+        loop {
+            let frontier: Vec<u32> = list
+                .iter()
+                .filter(|(_, n)| !expanded.contains(n))
+                .take(self.setting.beam_width as usize)
+                .map(|(_, n)| *n)
+                .collect();
+
+            if frontier.is_empty() {
+                break;
+            }
+
+            for node in frontier {
+                expanded.insert(node);
+
+                if !self.is_tombstone(node) {
+                    let label = self.label_at(node);
+                    let allowed = query
+                        .id_filter
+                        .as_ref()
+                        .map(|filter| filter.filter(&label))
+                        .unwrap_or(true);
+
+                    if allowed {
+                        top_k.push(
+                            label,
+                            distance(&self.metric_type, &query.vector, self.vector_at(node)),
+                        );
+                    }
+                }
+
+                for &nb in self.neighbors_at(node) {
+                    if seen.insert(nb) {
+                        list.push((approx(nb), nb));
+                    }
+                }
+            }
+
+            list.sort_by(|a, b| a.0.total_cmp(&b.0));
+            list.truncate(search_list_size);
+        }
+    }
+}
+
+impl Index for VamanaIndex {
+    fn insert(&mut self, params: &InsertParams) -> Result<(), IndexError> {
+        // the graph itself is immutable, new vectors wait in the fresh index until the next build
+        self.fresh.insert(params)
+    }
+
+    fn search(&mut self, query: &SearchQuery, k: usize) -> Result<SearchResult, IndexError> {
+        if query.vector.len() != self.dim as usize {
+            return Err(IndexError::QueryError(format!(
+                "query dimension {} does not match index dimension {}",
+                query.vector.len(),
+                self.dim
+            )));
+        }
+
+        let search_list_size = query
+            .get_vamana()
+            .map(|opt| opt.search_list_size)
+            .unwrap_or(self.setting.search_list_size) as usize;
+
+        let mut top_k = TopK::new(k, self.metric_type.clone());
+        self.search_graph(query, search_list_size.max(k), &mut top_k);
+
+        let fresh_result = self.fresh.search(query, k)?;
+        for (label, dist) in fresh_result.labels.iter().zip(fresh_result.distances) {
+            top_k.push(*label, dist);
+        }
+
+        let (distances, labels) = top_k.into_sorted();
+
+        Ok(SearchResult { distances, labels })
+    }
+
+    fn count(&self) -> usize {
+        self.num_nodes - self.tombstones + self.fresh.len()
+    }
+
+    fn remove(&mut self, labels: &[u64]) -> Result<usize, IndexError> {
+        let mut removed = 0;
+
+        for label in labels {
+            let Some(node) = self.label_nodes.remove(label) else {
+                continue;
+            };
+
+            // tombstoned nodes are still traversed but never returned
+            let flags_offset = self.node_offset(node) + 12;
+            self.mmap[flags_offset..flags_offset + 4]
+                .copy_from_slice(&FLAG_TOMBSTONE.to_le_bytes());
+            removed += 1;
+        }
+
+        self.tombstones += removed;
+        self.mmap[56..64].copy_from_slice(&(self.tombstones as u64).to_le_bytes());
+
+        self.mmap
+            .flush_async()
+            .map_err(|e| IndexError::RemovalError(format!("failed to flush index file: {e}")))?;
+
+        Ok(removed + self.fresh.remove(labels)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::IdFilter;
+    use crate::index::option::VamanaSearchOption;
+    use ndarray::Array2 as NMatrix;
+    use std::fs;
+    use uuid::Uuid;
+
+    fn setup(nrow: usize, dim: usize) -> (Vec<u64>, Vec<f32>, PathBuf) {
+        let dir = PathBuf::from("/tmp/test_db").join(format!("vamana_{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut rng = SmallRng::seed_from_u64(7);
+        let data = (0..nrow * dim)
+            .map(|_| rng.gen_range(-1.0..1.0))
+            .collect::<Vec<f32>>();
+        let labels = (1u64..(nrow + 1) as u64).collect::<Vec<u64>>();
+
+        (labels, data, dir)
+    }
+
+    fn option() -> Option<VamanaIndexOption> {
+        Some(VamanaIndexOption {
+            max_degree: Some(16),
+            search_list_size: Some(32),
+            alpha: None,
+            pq_subspaces: Some(4),
+            beam_width: None,
+        })
+    }
+
+    #[test]
+    fn test_build_and_search() {
+        let (labels, data, dir) = setup(300, 8);
+        let mut index = VamanaIndex::build(
+            dir.join("index.bin"),
+            8,
+            MetricType::L2,
+            option(),
+            &labels,
+            &data,
+        )
+        .expect("Failed to build index");
+
+        let mut hits = 0;
+        for i in 0..50 {
+            let query = data[i * 8..(i + 1) * 8].to_vec();
+            let result = index.search(&SearchQuery::new(query), 5).unwrap();
+
+            assert_eq!(result.labels.len(), 5);
+            if result.labels[0] == labels[i] {
+                hits += 1;
+            }
+        }
+        assert!(hits >= 48, "only {hits} of 50 points found themselves");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_build_from_iter() {
+        let (labels, data, dir) = setup(200, 8);
+        let vectors = || {
+            labels
+                .iter()
+                .zip(data.chunks_exact(8))
+                .map(|(label, vector)| Ok::<_, String>((*label, vector.to_vec())))
+        };
+
+        let mut built = VamanaIndex::build(
+            dir.join("built.bin"),
+            8,
+            MetricType::L2,
+            option(),
+            &labels,
+            &data,
+        )
+        .unwrap();
+        let mut streamed = VamanaIndex::build_from_iter(
+            dir.join("index.bin"),
+            8,
+            MetricType::L2,
+            option(),
+            vectors(),
+        )
+        .unwrap();
+        assert_eq!(streamed.count(), 200);
+        assert!(!scratch_path(&dir.join("index.bin")).exists());
+
+        for i in 0..20 {
+            let query = SearchQuery::new(data[i * 8..(i + 1) * 8].to_vec());
+            assert_eq!(
+                streamed.search(&query, 5).unwrap(),
+                built.search(&query, 5).unwrap()
+            );
+        }
+
+        let short = vectors().chain([Ok((201, vec![0.0; 4]))]);
+        let res =
+            VamanaIndex::build_from_iter(dir.join("short.bin"), 8, MetricType::L2, option(), short);
+        assert!(res.is_err());
+        assert!(!scratch_path(&dir.join("short.bin")).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_search_with_params() {
+        let (labels, data, dir) = setup(200, 8);
+        let mut index = VamanaIndex::build(
+            dir.join("index.bin"),
+            8,
+            MetricType::IP,
+            option(),
+            &labels,
+            &data,
+        )
+        .expect("Failed to build index");
+
+        let mut filter = IdFilter::new();
+        filter.add_all(&labels[100..]);
+
+        let query = data[0..8].to_vec();
+        let result = index
+            .search(
+                &SearchQuery::new(query)
+                    .with(&VamanaSearchOption {
+                        search_list_size: 200,
+                    })
+                    .with(&filter),
+                10,
+            )
+            .unwrap();
+
+        assert!(!result.labels.is_empty());
+        assert!(result.labels.iter().all(|l| *l > 100));
+        assert!(result.distances.windows(2).all(|w| w[0] >= w[1]));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_fresh_insert_remove_and_reopen() {
+        let (labels, data, dir) = setup(100, 8);
+        let path = dir.join("index.bin");
+        let mut index =
+            VamanaIndex::build(&path, 8, MetricType::L2, option(), &labels, &data).unwrap();
+
+        let fresh_data = NMatrix::from_shape_vec((1, 8), vec![5.0; 8]).unwrap();
+        index
+            .insert(&InsertParams::new(&fresh_data, &vec![1000]))
+            .unwrap();
+
+        let result = index.search(&SearchQuery::new(vec![5.0; 8]), 1).unwrap();
+        assert_eq!(result.labels, vec![1000]);
+
+        assert_eq!(index.count(), 101);
+        assert_eq!(index.remove(&[labels[0], 1000]).unwrap(), 2);
+        assert_eq!(index.remove(&[labels[0]]).unwrap(), 0);
+        assert_eq!(index.count(), 99);
+        drop(index);
+
+        // the tombstones are counted in the header
+        let mut reopened = VamanaIndex::open(&path, 8, MetricType::L2).unwrap();
+        assert_eq!(reopened.count(), 99);
+        assert_eq!(reopened.remove(&[labels[0], labels[1]]).unwrap(), 1);
+        assert_eq!(reopened.count(), 98);
+        let result = reopened
+            .search(&SearchQuery::new(data[0..8].to_vec()), 3)
+            .unwrap();
+        assert!(!result.labels.contains(&labels[0]));
+        assert!(!result.labels.contains(&1000));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_empty_graph() {
+        let (_, _, dir) = setup(0, 4);
+        let mut index =
+            VamanaIndex::open_or_create(dir.join("index.bin"), 4, MetricType::L2, None).unwrap();
+
+        let result = index.search(&SearchQuery::new(vec![0.0; 4]), 3).unwrap();
+        assert!(result.labels.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
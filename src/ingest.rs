@@ -0,0 +1,655 @@
+use futures::lock::Mutex;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Display;
+use tracing::{event, Level};
+
+use crate::merror::DBError;
+use crate::vecdb::{validate_attributes, DocMap, VdbUpsertArgs, VectorArgs, VectorDatabase};
+
+// rows upserted at once when no batch size is given
+pub const DEFAULT_INGEST_BATCH_SIZE: usize = 1000;
+pub const MAX_INGEST_BATCH_SIZE: usize = 100_000;
+// a row is never buffered beyond this size, whatever the format
+const MAX_ROW_BYTES: usize = 64 * 1024 * 1024;
+// row errors listed in the summary, the ones beyond are only counted
+const MAX_ROW_ERRORS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngestFormat {
+    // one JSON row per line
+    Ndjson,
+    // frames of a little-endian u32 length followed by the u32 dimension, the f32
+    // values of the vector and optionally the JSON row without its vector
+    Framed,
+}
+
+impl IngestFormat {
+    pub fn from_content_type(content_type: &str) -> Result<Self, DBError> {
+        match content_type.split(';').next().unwrap_or_default().trim() {
+            "application/x-ndjson" | "application/jsonl" => Ok(IngestFormat::Ndjson),
+            "application/octet-stream" => Ok(IngestFormat::Framed),
+            other => Err(DBError::ValidationError(format!(
+                "unsupported ingest content type: {other}, expected application/x-ndjson or application/octet-stream"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct IngestRow {
+    // a stored doc with this id is replaced by the row. The row is stored under a new
+    // id and the doc is deleted, so an update re-keys the doc. The summary lists the
+    // ids the rows are stored under
+    pub id: Option<u64>,
+    #[serde(default)]
+    pub vector: Vec<f32>,
+    pub doc: Option<DocMap>,
+    pub attributes: Option<HashMap<String, Value>>,
+}
+
+// position of a row in the stream and the row, unless it cannot be decoded
+pub type DecodedRow = (usize, Result<IngestRow, String>);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RowError {
+    // 0-based position of the row in the stream
+    pub row: usize,
+    pub message: String,
+}
+
+// `len` consecutive rows from `row` on, stored under consecutive ids from `id` on
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IdRange {
+    pub row: usize,
+    pub id: u64,
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct IngestSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    pub failed: usize,
+    pub errors: Vec<RowError>,
+    // ids of the written rows, in ranges so that they do not grow with every row
+    pub ids: Vec<IdRange>,
+}
+
+impl IngestSummary {
+    fn fail(&mut self, row: usize, message: String) {
+        self.failed += 1;
+        if self.errors.len() < MAX_ROW_ERRORS {
+            self.errors.push(RowError { row, message });
+        }
+    }
+
+    fn assign(&mut self, row: usize, id: u64) {
+        match self.ids.last_mut() {
+            Some(range) if range.row + range.len == row && range.id + range.len as u64 == id => {
+                range.len += 1
+            }
+            _ => self.ids.push(IdRange { row, id, len: 1 }),
+        }
+    }
+}
+
+// Splits the chunks of a body into rows, whatever the chunk boundaries
+pub struct IngestDecoder {
+    format: IngestFormat,
+    buffer: Vec<u8>,
+    // position of the next row in the stream
+    row: usize,
+}
+
+impl IngestDecoder {
+    pub fn new(format: IngestFormat) -> Self {
+        Self {
+            format,
+            buffer: vec![],
+            row: 0,
+        }
+    }
+
+    // Position of the next row in the stream
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    // Takes a chunk of the body and returns the rows it completes. An error means
+    // the stream cannot be split any further
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<DecodedRow>, String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut rows = vec![];
+        let mut start = 0;
+        while let Some((payload, end)) = self.next_payload(start)? {
+            if let Some(row) = self.decode(payload) {
+                rows.push((self.row, row));
+                self.row += 1;
+            }
+            start = end;
+        }
+        self.buffer.drain(..start);
+
+        if self.buffer.len() > MAX_ROW_BYTES {
+            return Err(format!("row exceeds {MAX_ROW_BYTES} bytes"));
+        }
+
+        Ok(rows)
+    }
+
+    // Returns the last row of a body not ending with a newline
+    pub fn finish(&mut self) -> Result<Vec<DecodedRow>, String> {
+        if self.buffer.is_empty() {
+            return Ok(vec![]);
+        }
+
+        match self.format {
+            IngestFormat::Ndjson => {
+                let buffer = std::mem::take(&mut self.buffer);
+                let rows = self
+                    .decode(&buffer)
+                    .map(|row| vec![(self.row, row)])
+                    .unwrap_or_default();
+                self.row += rows.len();
+
+                Ok(rows)
+            }
+            IngestFormat::Framed => Err("frame is truncated".to_string()),
+        }
+    }
+
+    // Bounds of the next complete row payload in the buffer from `start`
+    fn next_payload(&self, start: usize) -> Result<Option<(&[u8], usize)>, String> {
+        let rest = &self.buffer[start..];
+        match self.format {
+            IngestFormat::Ndjson => Ok(rest
+                .iter()
+                .position(|b| *b == b'\n')
+                .map(|pos| (&rest[..pos], start + pos + 1))),
+            IngestFormat::Framed => {
+                let Some(header) = rest.get(..4) else {
+                    return Ok(None);
+                };
+                let len = u32::from_le_bytes(header.try_into().unwrap()) as usize;
+                if len > MAX_ROW_BYTES {
+                    return Err(format!("row exceeds {MAX_ROW_BYTES} bytes"));
+                }
+
+                Ok(rest
+                    .get(4..4 + len)
+                    .map(|payload| (payload, start + 4 + len)))
+            }
+        }
+    }
+
+    // None for the blank lines of NDJSON, which are not rows
+    fn decode(&self, payload: &[u8]) -> Option<Result<IngestRow, String>> {
+        match self.format {
+            IngestFormat::Ndjson => {
+                if payload.iter().all(u8::is_ascii_whitespace) {
+                    return None;
+                }
+
+                Some(serde_json::from_slice(payload).map_err(|e| format!("invalid JSON: {e}")))
+            }
+            IngestFormat::Framed => Some(decode_frame(payload)),
+        }
+    }
+}
+
+fn decode_frame(payload: &[u8]) -> Result<IngestRow, String> {
+    let dim = payload
+        .get(..4)
+        .map(|dim| u32::from_le_bytes(dim.try_into().unwrap()) as usize)
+        .ok_or("frame misses the vector dimension")?;
+    let vector_bytes = payload
+        .get(4..4 + dim * 4)
+        .ok_or(format!("frame too short for a vector of dimension {dim}"))?;
+    let vector = vector_bytes
+        .chunks_exact(4)
+        .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+        .collect();
+
+    let json = &payload[4 + dim * 4..];
+    let mut row = if json.iter().all(u8::is_ascii_whitespace) {
+        IngestRow::default()
+    } else {
+        serde_json::from_slice::<IngestRow>(json).map_err(|e| format!("invalid JSON: {e}"))?
+    };
+    row.vector = vector;
+
+    Ok(row)
+}
+
+// Checks a row up front, so that one invalid row does not fail its whole batch
+fn validate_row(row: &IngestRow, dim: usize) -> Result<(), String> {
+    if row.vector.len() != dim {
+        return Err(format!(
+            "vector dimension {} does not match index dimension {dim}",
+            row.vector.len()
+        ));
+    }
+
+    match &row.attributes {
+        Some(attributes) => validate_attributes(attributes).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+// Upserts a batch of valid rows, then deletes the docs they replace. The rows are
+// stored under new ids, and are deleted again when the replaced docs cannot be. Failed
+// rows are recorded in the summary, an error is only returned when the rows of a failed
+// batch cannot be deleted again
+async fn ingest_batch(
+    vdb: &mut VectorDatabase,
+    batch: Vec<(usize, IngestRow)>,
+    summary: &mut IngestSummary,
+) -> Result<(), DBError> {
+    let mut seen = BTreeSet::new();
+    let batch = batch
+        .into_iter()
+        .filter(|(row, ingest_row)| match ingest_row.id {
+            Some(id) if !seen.insert(id) => {
+                summary.fail(*row, format!("id {id} is repeated in the batch"));
+                false
+            }
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    let replaced_ids = batch
+        .iter()
+        .filter_map(|(_, row)| row.id)
+        .collect::<Vec<_>>();
+    let existing = match vdb.get(&replaced_ids, false) {
+        Ok(docs) => docs
+            .iter()
+            .filter_map(|doc| doc.get("id").and_then(Value::as_u64))
+            .collect::<BTreeSet<_>>(),
+        Err(e) => {
+            for (row, _) in batch {
+                summary.fail(row, e.to_string());
+            }
+
+            return Ok(());
+        }
+    };
+
+    let dim = vdb.params().dim as usize;
+    let mut flat_data = Vec::with_capacity(batch.len() * dim);
+    let mut docs = Vec::with_capacity(batch.len());
+    let mut attributes = Vec::with_capacity(batch.len());
+    let mut rows = Vec::with_capacity(batch.len());
+    for (row, ingest_row) in batch {
+        flat_data.extend(ingest_row.vector);
+        docs.push(ingest_row.doc);
+        attributes.push(ingest_row.attributes);
+        rows.push((row, ingest_row.id));
+    }
+
+    let upsert_args = VdbUpsertArgs {
+        vectors: VectorArgs {
+            flat_data,
+            data_row: rows.len(),
+            data_dim: dim,
+            sparse_data: None,
+            named_data: None,
+            multi_data: None,
+        },
+        docs,
+        attributes,
+        hnsw_params: None,
+    };
+
+    let new_ids = match vdb.insert(upsert_args).await {
+        Ok(new_ids) => new_ids,
+        Err(e) => {
+            event!(
+                Level::WARN,
+                "Failed to ingest batch of {} rows: {e}",
+                rows.len()
+            );
+            for (row, _) in rows {
+                summary.fail(row, e.to_string());
+            }
+
+            return Ok(());
+        }
+    };
+
+    if let Err(e) = vdb
+        .delete(&existing.iter().copied().collect::<Vec<_>>())
+        .await
+    {
+        event!(
+            Level::WARN,
+            "Failed to delete the docs replaced by a batch of {} rows: {e}",
+            rows.len()
+        );
+        // the replaced docs are kept, not a second copy of them
+        if let Err(rollback_err) = vdb.delete(&new_ids).await {
+            for (row, _) in rows {
+                summary.fail(
+                    row,
+                    format!("{e}, and the row could not be deleted again: {rollback_err}"),
+                );
+            }
+
+            return Err(rollback_err);
+        }
+        for (row, _) in rows {
+            summary.fail(row, e.to_string());
+        }
+
+        return Ok(());
+    }
+
+    for ((row, id), new_id) in rows.into_iter().zip(new_ids) {
+        match id {
+            Some(id) if existing.contains(&id) => summary.updated += 1,
+            _ => summary.inserted += 1,
+        }
+        summary.assign(row, new_id);
+    }
+
+    Ok(())
+}
+
+// Upserts the rows of a body in batches as they arrive. The body is not read while a
+// batch is written, and the database is only locked for one batch at a time. A body
+// that cannot be read or split into rows fails at that row, after the rows before it are
+// written. A batch that leaves the database out of step with the summary stops the
+// ingest, the summary then covers the rows up to that batch
+pub async fn ingest<S, B, E>(
+    vdb: &Mutex<VectorDatabase>,
+    mut body: S,
+    format: IngestFormat,
+    batch_size: usize,
+) -> Result<IngestSummary, DBError>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: Display,
+{
+    if batch_size == 0 || batch_size > MAX_INGEST_BATCH_SIZE {
+        return Err(DBError::ValidationError(format!(
+            "ingest batch size {batch_size} is not between 1 and {MAX_INGEST_BATCH_SIZE}"
+        )));
+    }
+
+    let dim = vdb.lock().await.params().dim as usize;
+    let mut decoder = IngestDecoder::new(format);
+    let mut summary = IngestSummary::default();
+    let mut batch = Vec::with_capacity(batch_size);
+
+    loop {
+        let (rows, done) = match body.next().await {
+            Some(Ok(chunk)) => (decoder.push(chunk.as_ref()), false),
+            Some(Err(e)) => {
+                summary.fail(decoder.row(), format!("unable to read request body: {e}"));
+                break;
+            }
+            None => (decoder.finish(), true),
+        };
+
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(message) => {
+                summary.fail(decoder.row(), message);
+                break;
+            }
+        };
+
+        for (row, ingest_row) in rows {
+            match ingest_row.and_then(|r| validate_row(&r, dim).map(|_| r)) {
+                Ok(ingest_row) => batch.push((row, ingest_row)),
+                Err(message) => summary.fail(row, message),
+            }
+
+            if batch.len() == batch_size {
+                let mut vdb_guard = vdb.lock().await;
+                let batch = std::mem::take(&mut batch);
+                if let Err(e) = ingest_batch(&mut vdb_guard, batch, &mut summary).await {
+                    event!(Level::ERROR, "Stopped ingest after a failed batch: {e}");
+                    return Ok(summary);
+                }
+            }
+        }
+
+        if done {
+            break;
+        }
+    }
+
+    if !batch.is_empty() {
+        let mut vdb_guard = vdb.lock().await;
+        if let Err(e) = ingest_batch(&mut vdb_guard, batch, &mut summary).await {
+            event!(Level::ERROR, "Stopped ingest after a failed batch: {e}");
+        }
+    }
+
+    event!(
+        Level::INFO,
+        "Ingested {} rows, updated {}, failed {}",
+        summary.inserted,
+        summary.updated,
+        summary.failed
+    );
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{IndexType, MetricType};
+    use crate::vecdb::DatabaseParams;
+    use serde_json::json;
+
+    fn test_database() -> Mutex<VectorDatabase> {
+        let db_path = std::env::temp_dir()
+            .join("test_ingest")
+            .join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&db_path).unwrap();
+
+        let params = DatabaseParams {
+            dim: 2,
+            metric_type: MetricType::L2,
+            index_type: IndexType::Flat,
+            hnsw_params: None,
+            vamana_params: None,
+            text_fields: None,
+            bm25_params: None,
+            vector_fields: None,
+            brute_force_selectivity: None,
+            shadow_sample_rate: None,
+            version: "0.1.0".to_string(),
+        };
+
+        Mutex::new(VectorDatabase::new(db_path, params).unwrap())
+    }
+
+    // Cuts a body into chunks of `size` bytes, splitting rows anywhere
+    fn chunks(body: &[u8], size: usize) -> impl Stream<Item = Result<Vec<u8>, String>> + Unpin {
+        futures::stream::iter(
+            body.chunks(size)
+                .map(|chunk| Ok(chunk.to_vec()))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn frame(vector: &[f32], json: &str) -> Vec<u8> {
+        let mut payload = (vector.len() as u32).to_le_bytes().to_vec();
+        for value in vector {
+            payload.extend(value.to_le_bytes());
+        }
+        payload.extend(json.as_bytes());
+
+        let mut frame = (payload.len() as u32).to_le_bytes().to_vec();
+        frame.extend(payload);
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_ingest_ndjson() {
+        let vdb = test_database();
+
+        let body = [
+            r#"{"vector": [0.0, 0.0], "doc": {"name": "a"}, "attributes": {"odd": 1}}"#,
+            "",
+            r#"{"vector": [1.0, 1.0]}"#,
+            r#"{"vector": [1.0]}"#,
+            r#"{"vector": [2.0, 2.0], "attributes": {"odd": "yes"}}"#,
+            r#"not json"#,
+            r#"{"vector": [3.0, 3.0], "doc": {"name": "d"}}"#,
+        ]
+        .join("\n");
+
+        let summary = ingest(&vdb, chunks(body.as_bytes(), 7), IngestFormat::Ndjson, 2)
+            .await
+            .unwrap();
+        assert_eq!(summary.inserted, 3);
+        assert_eq!(summary.updated, 0);
+        assert_eq!(summary.failed, 3);
+        assert_eq!(
+            summary.errors.iter().map(|e| e.row).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert!(summary.errors[0].message.contains("dimension"));
+        assert_eq!(
+            summary.ids,
+            vec![
+                IdRange {
+                    row: 0,
+                    id: 1,
+                    len: 2
+                },
+                IdRange {
+                    row: 5,
+                    id: 3,
+                    len: 1
+                },
+            ]
+        );
+
+        let docs = vdb.lock().await.get(&[1, 2, 3], false).unwrap();
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs[0]["name"], json!("a"));
+        assert_eq!(docs[0]["attributes"]["odd"], json!(1));
+        assert_eq!(docs[2]["name"], json!("d"));
+
+        // rows with the id of a stored doc replace it
+        let body = r#"{"id": 1, "vector": [5.0, 5.0], "doc": {"name": "e"}}
+{"id": 42, "vector": [6.0, 6.0]}
+"#;
+        let summary = ingest(&vdb, chunks(body.as_bytes(), 1), IngestFormat::Ndjson, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            IngestSummary {
+                inserted: 1,
+                updated: 1,
+                failed: 0,
+                errors: vec![],
+                ids: vec![IdRange {
+                    row: 0,
+                    id: 4,
+                    len: 2
+                }],
+            }
+        );
+
+        {
+            let vdb_guard = vdb.lock().await;
+            assert!(vdb_guard.get(&[1], false).unwrap().is_empty());
+            assert_eq!(vdb_guard.get(&[4], false).unwrap()[0]["name"], json!("e"));
+            assert_eq!(vdb_guard.stats().unwrap().doc_count, 4);
+        }
+
+        // a batch cannot replace a doc twice
+        let body = r#"{"id": 4, "vector": [7.0, 7.0], "doc": {"name": "f"}}
+{"id": 4, "vector": [8.0, 8.0], "doc": {"name": "g"}}
+"#;
+        let summary = ingest(&vdb, chunks(body.as_bytes(), 3), IngestFormat::Ndjson, 10)
+            .await
+            .unwrap();
+        assert_eq!(summary.updated, 1);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.errors[0].row, 1);
+        assert!(summary.errors[0].message.contains("repeated"));
+
+        let vdb_guard = vdb.lock().await;
+        assert!(vdb_guard.get(&[4], false).unwrap().is_empty());
+        assert_eq!(vdb_guard.get(&[6], false).unwrap()[0]["name"], json!("f"));
+        assert_eq!(vdb_guard.stats().unwrap().doc_count, 4);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_framed() {
+        let vdb = test_database();
+
+        let mut body = frame(&[0.0, 1.0], r#"{"attributes": {"odd": 1}}"#);
+        body.extend(frame(&[1.0, 2.0], ""));
+        body.extend(frame(&[1.0, 2.0, 3.0], ""));
+        body.extend(frame(&[2.0, 3.0], "{"));
+        // truncated last frame
+        body.extend(&frame(&[3.0, 4.0], "")[..6]);
+
+        let summary = ingest(&vdb, chunks(&body, 5), IngestFormat::Framed, 1)
+            .await
+            .unwrap();
+        assert_eq!(summary.inserted, 2);
+        assert_eq!(summary.failed, 3);
+        assert_eq!(
+            summary.errors.iter().map(|e| e.row).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert_eq!(summary.errors[2].message, "frame is truncated");
+
+        let docs = vdb.lock().await.get(&[1, 2], true).unwrap();
+        assert_eq!(docs[0]["vector"], json!([0.0, 1.0]));
+        assert_eq!(docs[0]["attributes"]["odd"], json!(1));
+        assert_eq!(docs[1]["vector"], json!([1.0, 2.0]));
+    }
+
+    #[tokio::test]
+    async fn test_ingest_errors() {
+        let vdb = test_database();
+
+        let result = ingest(&vdb, chunks(b"", 1), IngestFormat::Ndjson, 0).await;
+        assert!(matches!(result, Err(DBError::ValidationError(_))));
+
+        // the rows read before the body fails are written and listed
+        let body = futures::stream::iter(vec![
+            Ok(b"{\"vector\": [0.0, 0.0]}\n".to_vec()),
+            Err("connection reset"),
+        ]);
+        let summary = ingest(&vdb, body, IngestFormat::Ndjson, 10).await.unwrap();
+        assert_eq!(summary.inserted, 1);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.errors[0].row, 1);
+        assert!(summary.errors[0].message.contains("connection reset"));
+        assert_eq!(summary.ids[0].id, 1);
+
+        let mut body = (MAX_ROW_BYTES as u32 + 1).to_le_bytes().to_vec();
+        body.extend([0; 16]);
+        let summary = ingest(&vdb, chunks(&body, 8), IngestFormat::Framed, 10)
+            .await
+            .unwrap();
+        assert_eq!(summary.failed, 1);
+
+        assert_eq!(
+            IngestFormat::from_content_type("application/x-ndjson; charset=utf-8").unwrap(),
+            IngestFormat::Ndjson
+        );
+        assert!(IngestFormat::from_content_type("application/json").is_err());
+    }
+}
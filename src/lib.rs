@@ -0,0 +1,11 @@
+pub mod filter;
+pub mod grpc;
+pub mod index;
+pub mod ingest;
+pub mod merror;
+pub mod metrics;
+pub mod persistence;
+pub mod scalar;
+pub mod settings;
+pub mod text;
+pub mod vecdb;
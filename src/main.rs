@@ -1,46 +1,90 @@
-mod filter;
-mod index;
-mod merror;
-mod persistence;
-mod scalar;
-mod vecdb;
-
 use axum::{
-    extract::{Json, State},
-    http::StatusCode,
-    routing::post,
+    body::Body,
+    extract::{FromRef, Json, MatchedPath, Request, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
     Router,
 };
 use axum_extra::extract::WithRejection;
 use axum_macros::debug_handler;
+use clap::Parser;
 use futures::lock::Mutex;
-use merror::ApiError;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::str::FromStr;
-use std::{net::SocketAddr, sync::Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 use tracing::{event, span, Level};
 use tracing_subscriber::fmt as tracing_fmt;
 
-use vecdb::{DatabaseParams, DocMap, VdbSearchArgs, VdbUpsertArgs, VectorDatabase};
+use vecdb_rs::grpc::VectorDbService;
+use vecdb_rs::ingest::{self, IngestFormat, IngestSummary};
+use vecdb_rs::merror::ApiError;
+use vecdb_rs::metrics;
+use vecdb_rs::settings::{
+    AppConfig, DEFAULT_CONFIG_PATH, HEALTH_URL, METRICS_URL, READY_URL, STATS_URL,
+};
+use vecdb_rs::vecdb::{
+    CountResult, DatabaseStats, DocMap, FacetCount, ScrollPage, SearchGroup, TuneResult,
+    VdbCountArgs, VdbFacetArgs, VdbRebuildArgs, VdbRecommendArgs, VdbScrollArgs, VdbSearchArgs,
+    VdbTuneArgs, VdbUpsertArgs, VectorDatabase,
+};
+
+#[derive(Parser, Debug)]
+#[command(about = "Serves a vector database over HTTP and gRPC")]
+struct Cli {
+    /// Config file, whose values the VECDB_* environment variables override, e.g.
+    /// VECDB_SERVER__PORT=8000 or VECDB_DATABASE__DIM=768
+    #[arg(long, default_value = DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+    /// Address the HTTP and gRPC servers listen on, over the config and environment
+    #[arg(long)]
+    bind_address: Option<IpAddr>,
+    /// Print the effective config and exit
+    #[arg(long)]
+    print_config: bool,
+}
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct AppConfig {
-    pub database: DatabaseParams,
-    pub file_path: String,
-    pub server: ServerConfig,
+#[derive(Clone)]
+struct AppState {
+    vdb: Arc<Mutex<VectorDatabase>>,
+    // set once the stored vectors are loaded into the indexes and the WAL is replayed
+    ready: Arc<AtomicBool>,
+    ingest_batch_size: usize,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct ServerConfig {
-    pub search_url_suffix: String,
-    pub upsert_url_suffix: String,
-    pub port: u16,
-    pub log_level: String,
+impl FromRef<AppState> for Arc<Mutex<VectorDatabase>> {
+    fn from_ref(state: &AppState) -> Self {
+        Arc::clone(&state.vdb)
+    }
 }
 
 #[derive(Debug, Serialize)]
 struct VectorSearchResponse {
     results: Vec<DocMap>, // pretend these are doc IDs or similar
+    // set instead of `results` for searches with `group_by`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<SearchGroup>>,
+    // passed back as `cursor` to fetch the next page of results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct FacetResponse {
+    facets: HashMap<String, Vec<FacetCount>>,
+}
+
+#[derive(Debug, Serialize)]
+struct TuneResponse {
+    message: String,
+    result: TuneResult,
 }
 
 #[derive(Debug, Serialize)]
@@ -48,11 +92,21 @@ struct VectorUpsertResponse {
     message: String,
 }
 
+#[derive(Debug, Serialize)]
+struct IndexRebuildResponse {
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    status: String,
+}
+
 #[debug_handler]
 async fn handle_vector_search(
     State(vdb): State<Arc<Mutex<VectorDatabase>>>,
     WithRejection(Json(payload), _): WithRejection<Json<VdbSearchArgs>, ApiError>,
-) -> (StatusCode, Json<VectorSearchResponse>) {
+) -> Result<Json<VectorSearchResponse>, ApiError> {
     let span = span!(Level::TRACE, "handle_vector_search");
     let _enter = span.enter();
 
@@ -67,25 +121,184 @@ async fn handle_vector_search(
         filter_inputs: payload.filter_inputs,
         k: payload.k,
         hnsw_params: payload.hnsw_params,
+        vamana_params: payload.vamana_params,
+        with_vectors: payload.with_vectors,
+        text_query: payload.text_query,
+        sparse_query: payload.sparse_query,
+        vector_field: payload.vector_field,
+        named_queries: payload.named_queries,
+        multi_queries: payload.multi_queries,
+        fusion: payload.fusion,
+        mmr: payload.mmr,
+        group_by: payload.group_by,
+        offset: payload.offset,
+        cursor: payload.cursor,
     };
 
     let results = {
         let mut vdb_guard = vdb.lock().await;
 
-        vdb_guard.query(search_args).await
+        if search_args.group_by.is_some() {
+            vdb_guard
+                .query_groups(search_args)
+                .await
+                .map(|groups| VectorSearchResponse {
+                    results: vec![],
+                    groups: Some(groups),
+                    next_cursor: None,
+                })
+        } else {
+            vdb_guard
+                .query_page(search_args)
+                .await
+                .map(|page| VectorSearchResponse {
+                    results: page.results,
+                    groups: None,
+                    next_cursor: page.next_cursor,
+                })
+        }
     };
 
     match results {
-        Ok(results) => {
+        Ok(response) => {
             event!(Level::INFO, "Search successful");
-            (StatusCode::OK, Json(VectorSearchResponse { results }))
+            Ok(Json(response))
         }
         Err(e) => {
-            event!(Level::ERROR, "Error during vector search: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(VectorSearchResponse { results: vec![] }),
-            )
+            event!(Level::WARN, "Error during vector search: {}", e);
+            Err(e.into())
+        }
+    }
+}
+
+#[debug_handler]
+async fn handle_recommend(
+    State(vdb): State<Arc<Mutex<VectorDatabase>>>,
+    WithRejection(Json(payload), _): WithRejection<Json<VdbRecommendArgs>, ApiError>,
+) -> Result<Json<VectorSearchResponse>, ApiError> {
+    let span = span!(Level::TRACE, "handle_recommend");
+    let _enter = span.enter();
+
+    event!(
+        Level::INFO,
+        "Received recommend request with payload: {:?}",
+        payload
+    );
+
+    let results = {
+        let mut vdb_guard = vdb.lock().await;
+
+        vdb_guard.recommend(payload).await
+    };
+
+    match results {
+        Ok(results) => {
+            event!(Level::INFO, "Recommend successful");
+            Ok(Json(VectorSearchResponse {
+                results,
+                groups: None,
+                next_cursor: None,
+            }))
+        }
+        Err(e) => {
+            event!(Level::WARN, "Error during recommend: {}", e);
+            Err(e.into())
+        }
+    }
+}
+
+#[debug_handler]
+async fn handle_scroll(
+    State(vdb): State<Arc<Mutex<VectorDatabase>>>,
+    WithRejection(Json(payload), _): WithRejection<Json<VdbScrollArgs>, ApiError>,
+) -> Result<Json<ScrollPage>, ApiError> {
+    let span = span!(Level::TRACE, "handle_scroll");
+    let _enter = span.enter();
+
+    event!(
+        Level::INFO,
+        "Received scroll request with payload: {:?}",
+        payload
+    );
+
+    let results = {
+        let vdb_guard = vdb.lock().await;
+
+        vdb_guard.scroll(payload)
+    };
+
+    match results {
+        Ok(page) => {
+            event!(Level::INFO, "Scroll successful");
+            Ok(Json(page))
+        }
+        Err(e) => {
+            event!(Level::WARN, "Error during scroll: {}", e);
+            Err(e.into())
+        }
+    }
+}
+
+#[debug_handler]
+async fn handle_count(
+    State(vdb): State<Arc<Mutex<VectorDatabase>>>,
+    WithRejection(Json(payload), _): WithRejection<Json<VdbCountArgs>, ApiError>,
+) -> Result<Json<CountResult>, ApiError> {
+    let span = span!(Level::TRACE, "handle_count");
+    let _enter = span.enter();
+
+    event!(
+        Level::INFO,
+        "Received count request with payload: {:?}",
+        payload
+    );
+
+    let results = {
+        let vdb_guard = vdb.lock().await;
+
+        vdb_guard.count(payload)
+    };
+
+    match results {
+        Ok(result) => {
+            event!(Level::INFO, "Count successful");
+            Ok(Json(result))
+        }
+        Err(e) => {
+            event!(Level::WARN, "Error during count: {}", e);
+            Err(e.into())
+        }
+    }
+}
+
+#[debug_handler]
+async fn handle_facet(
+    State(vdb): State<Arc<Mutex<VectorDatabase>>>,
+    WithRejection(Json(payload), _): WithRejection<Json<VdbFacetArgs>, ApiError>,
+) -> Result<Json<FacetResponse>, ApiError> {
+    let span = span!(Level::TRACE, "handle_facet");
+    let _enter = span.enter();
+
+    event!(
+        Level::INFO,
+        "Received facet request with payload: {:?}",
+        payload
+    );
+
+    let results = {
+        let vdb_guard = vdb.lock().await;
+
+        vdb_guard.facet(payload)
+    };
+
+    match results {
+        Ok(facets) => {
+            event!(Level::INFO, "Facet successful");
+            Ok(Json(FacetResponse { facets }))
+        }
+        Err(e) => {
+            event!(Level::WARN, "Error during facet: {}", e);
+            Err(e.into())
         }
     }
 }
@@ -94,7 +307,7 @@ async fn handle_vector_search(
 async fn handle_vector_upsert(
     State(vdb): State<Arc<Mutex<VectorDatabase>>>,
     WithRejection(Json(payload), _): WithRejection<Json<VdbUpsertArgs>, ApiError>,
-) -> (StatusCode, Json<VectorUpsertResponse>) {
+) -> Result<Json<VectorUpsertResponse>, ApiError> {
     let span = span!(Level::TRACE, "handle_vector_upsert");
     let _enter = span.enter();
 
@@ -116,42 +329,310 @@ async fn handle_vector_upsert(
             let response = VectorUpsertResponse {
                 message: "Upsert successful".to_string(),
             };
-            (StatusCode::OK, Json(response))
+            Ok(Json(response))
         }
         Err(e) => {
-            event!(Level::ERROR, "Error during vector upsert: {e}");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(VectorUpsertResponse {
-                    message: format!("Error during vector upsert: {e}"),
-                }),
-            )
+            event!(Level::WARN, "Error during vector upsert: {e}");
+            Err(e.into())
         }
     }
 }
 
-fn parse_settings() -> Result<AppConfig, config::ConfigError> {
-    let setting = config::Config::builder()
-        .add_source(config::File::with_name("config.toml"))
-        .build()?;
+#[debug_handler]
+async fn handle_ingest(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<IngestSummary>, ApiError> {
+    let span = span!(Level::TRACE, "handle_ingest");
+    let _enter = span.enter();
+
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let format = IngestFormat::from_content_type(content_type)?;
 
-    let dec_setting: AppConfig = setting.try_deserialize()?;
+    event!(
+        Level::INFO,
+        "Received ingest request with format {:?}",
+        format
+    );
 
-    Ok(dec_setting)
+    // the body is read as it arrives, so no body size limit applies
+    let results = ingest::ingest(
+        &state.vdb,
+        body.into_data_stream(),
+        format,
+        state.ingest_batch_size,
+    )
+    .await;
+
+    match results {
+        Ok(summary) => Ok(Json(summary)),
+        Err(e) => {
+            event!(Level::WARN, "Error during ingest: {e}");
+            Err(e.into())
+        }
+    }
+}
+
+#[debug_handler]
+async fn handle_tune(
+    State(vdb): State<Arc<Mutex<VectorDatabase>>>,
+    WithRejection(Json(payload), _): WithRejection<Json<VdbTuneArgs>, ApiError>,
+) -> Result<Json<TuneResponse>, ApiError> {
+    let span = span!(Level::TRACE, "handle_tune");
+    let _enter = span.enter();
+
+    event!(
+        Level::INFO,
+        "Received ef_search tuning request with payload: {:?}",
+        payload
+    );
+
+    let results = {
+        let mut vdb_guard = vdb.lock().await;
+
+        vdb_guard.tune_ef_search(payload).await
+    };
+
+    match results {
+        Ok(result) => {
+            event!(Level::INFO, "ef_search tuning successful");
+            Ok(Json(TuneResponse {
+                message: "ef_search tuning successful".to_string(),
+                result,
+            }))
+        }
+        Err(e) => {
+            event!(Level::WARN, "Error during ef_search tuning: {e}");
+            Err(e.into())
+        }
+    }
+}
+
+#[debug_handler]
+async fn handle_index_rebuild(
+    State(vdb): State<Arc<Mutex<VectorDatabase>>>,
+    WithRejection(Json(payload), _): WithRejection<Json<VdbRebuildArgs>, ApiError>,
+) -> Result<Json<IndexRebuildResponse>, ApiError> {
+    let span = span!(Level::TRACE, "handle_index_rebuild");
+    let _enter = span.enter();
+
+    event!(
+        Level::INFO,
+        "Received index rebuild request with payload: {:?}",
+        payload
+    );
+
+    let results = {
+        let mut vdb_guard = vdb.lock().await;
+
+        vdb_guard.rebuild_index(payload).await
+    };
+
+    match results {
+        Ok(_) => {
+            event!(Level::INFO, "Index rebuild successful");
+            let response = IndexRebuildResponse {
+                message: "Index rebuild successful".to_string(),
+            };
+            Ok(Json(response))
+        }
+        Err(e) => {
+            event!(Level::WARN, "Error during index rebuild: {e}");
+            Err(e.into())
+        }
+    }
+}
+
+async fn handle_health() -> Json<StatusResponse> {
+    Json(StatusResponse {
+        status: "ok".to_string(),
+    })
+}
+
+// Answers without the database lock, which recovery holds until it is done
+async fn handle_ready(State(state): State<AppState>) -> (StatusCode, Json<StatusResponse>) {
+    if state.ready.load(Ordering::Acquire) {
+        (
+            StatusCode::OK,
+            Json(StatusResponse {
+                status: "ready".to_string(),
+            }),
+        )
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(StatusResponse {
+                status: "recovering".to_string(),
+            }),
+        )
+    }
+}
+
+// Stats and metrics are polled, so they fail fast instead of waiting on the database
+// lock while recovery holds it
+#[debug_handler]
+async fn handle_stats(State(state): State<AppState>) -> Result<Json<DatabaseStats>, ApiError> {
+    let span = span!(Level::TRACE, "handle_stats");
+    let _enter = span.enter();
+
+    if !state.ready.load(Ordering::Acquire) {
+        return Err(ApiError::NotReady);
+    }
+
+    let results = {
+        let vdb_guard = state.vdb.lock().await;
+
+        vdb_guard.stats()
+    };
+
+    match results {
+        Ok(stats) => Ok(Json(stats)),
+        Err(e) => {
+            event!(Level::WARN, "Error during stats: {e}");
+            Err(e.into())
+        }
+    }
+}
+
+#[debug_handler]
+async fn handle_metrics(State(state): State<AppState>) -> Result<impl IntoResponse, ApiError> {
+    if !state.ready.load(Ordering::Acquire) {
+        return Err(ApiError::NotReady);
+    }
+
+    {
+        let vdb_guard = state.vdb.lock().await;
+
+        vdb_guard.export_metrics()?;
+    }
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::encode()?,
+    ))
+}
+
+// Counts and times the requests of every matched route
+async fn track_metrics(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    metrics::HTTP_REQUEST_DURATION
+        .with_label_values(&[&route])
+        .observe(start.elapsed().as_secs_f64());
+    metrics::HTTP_REQUESTS
+        .with_label_values(&[&route, response.status().as_str()])
+        .inc();
+
+    response
+}
+
+async fn handle_not_found(uri: Uri) -> ApiError {
+    ApiError::RouteNotFound(uri.path().to_string())
 }
 
 #[tokio::main]
-async fn main() {
-    let app_config = parse_settings().unwrap();
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let app_config = match AppConfig::load(&cli.config, cli.bind_address) {
+        Ok(app_config) => app_config,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if cli.print_config {
+        return match app_config.to_toml() {
+            Ok(text) => {
+                print!("{text}");
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
 
     let subscriber = tracing_fmt()
         .with_max_level(Level::from_str(app_config.server.log_level.as_str()).unwrap())
         .finish();
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
-    let vdb: VectorDatabase =
-        VectorDatabase::new(app_config.file_path, app_config.database).unwrap();
-    let vdb_state = Arc::new(Mutex::new(vdb));
+    let vdb = match VectorDatabase::new(&app_config.file_path, app_config.database) {
+        Ok(vdb) => vdb,
+        Err(e) => {
+            eprintln!("unable to open database at {}: {e}", app_config.file_path);
+            return ExitCode::FAILURE;
+        }
+    };
+    let vdb_state = AppState {
+        vdb: Arc::new(Mutex::new(vdb)),
+        ready: Arc::new(AtomicBool::new(false)),
+        ingest_batch_size: app_config
+            .server
+            .ingest_batch_size
+            .unwrap_or(ingest::DEFAULT_INGEST_BATCH_SIZE),
+    };
+
+    // requests wait on the database lock until the recovery is done. A failed recovery
+    // never releases it, so nothing is served from a half-loaded database before the
+    // server exits
+    let recovery_state = vdb_state.clone();
+    let mut recovery_guard = recovery_state.vdb.lock_owned().await;
+    let recovery = tokio::spawn(async move {
+        match recovery_guard.recover_database().await {
+            Ok(()) => {
+                event!(Level::INFO, "Database recovered, ready to serve");
+                recovery_state.ready.store(true, Ordering::Release);
+                Ok(())
+            }
+            Err(e) => {
+                std::mem::forget(recovery_guard);
+                Err(e)
+            }
+        }
+    });
+    let recovery_failure = async move {
+        match recovery.await {
+            Ok(Ok(())) => std::future::pending().await,
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => format!("recovery task failed: {e}"),
+        }
+    };
+
+    // the gRPC API serves the same database, waiting on the same recovery
+    let grpc_service = VectorDbService::new(Arc::clone(&vdb_state.vdb)).into_server();
+    let grpc_addr = SocketAddr::new(app_config.server.bind_address, app_config.server.grpc_port);
+    let grpc_incoming = match tonic::transport::server::TcpIncoming::bind(grpc_addr) {
+        Ok(incoming) => incoming,
+        Err(e) => {
+            eprintln!("unable to listen on {grpc_addr}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("gRPC server listening on {grpc_addr}");
+    tokio::spawn(async move {
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(grpc_service)
+            .serve_with_incoming(grpc_incoming)
+            .await
+        {
+            event!(Level::ERROR, "gRPC server failed: {e}");
+        }
+    });
 
     let app = Router::new()
         .route(
@@ -162,16 +643,48 @@ async fn main() {
             &app_config.server.upsert_url_suffix,
             post(handle_vector_upsert),
         )
+        .route(
+            &app_config.server.rebuild_url_suffix,
+            post(handle_index_rebuild),
+        )
+        .route(
+            &app_config.server.recommend_url_suffix,
+            post(handle_recommend),
+        )
+        .route(&app_config.server.scroll_url_suffix, post(handle_scroll))
+        .route(&app_config.server.count_url_suffix, post(handle_count))
+        .route(&app_config.server.facet_url_suffix, post(handle_facet))
+        .route(&app_config.server.tune_url_suffix, post(handle_tune))
+        .route(&app_config.server.ingest_url_suffix, post(handle_ingest))
+        .route(HEALTH_URL, get(handle_health))
+        .route(READY_URL, get(handle_ready))
+        .route(STATS_URL, get(handle_stats))
+        .route(METRICS_URL, get(handle_metrics))
+        .route_layer(middleware::from_fn(track_metrics))
+        .fallback(handle_not_found)
         .with_state(vdb_state);
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], app_config.server.port));
+    let addr = SocketAddr::new(app_config.server.bind_address, app_config.server.port);
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("unable to listen on {addr}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
     println!("Server listening on {addr}");
 
-    let listener = tokio::net::TcpListener::bind(addr)
-        .await
-        .expect("Failed to bind TCP listener");
-
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    tokio::select! {
+        served = axum::serve(listener, app.into_make_service()) => match served {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("server failed: {e}");
+                ExitCode::FAILURE
+            }
+        },
+        e = recovery_failure => {
+            event!(Level::ERROR, "Failed to recover database: {e}");
+            ExitCode::FAILURE
+        }
+    }
 }
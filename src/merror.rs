@@ -1,5 +1,6 @@
 use axum::{
     extract::{rejection::JsonRejection, Json},
+    http::StatusCode,
     response::IntoResponse,
 };
 use serde_json::json;
@@ -22,6 +23,14 @@ pub enum DBError {
     SyncError(String),
     #[error("failed to delete data from database: {0}")]
     DeleteDataError(String),
+    // the request itself is wrong, e.g. a query of the wrong dimension
+    #[error("invalid request: {0}")]
+    ValidationError(String),
+    #[error("not found: {0}")]
+    NotFoundError(String),
+    // the request is valid but not in the current state of the database
+    #[error("conflict: {0}")]
+    ConflictError(String),
 }
 
 #[derive(Error, Debug)]
@@ -31,6 +40,8 @@ pub enum IndexError {
     InitializationError(String),
     #[error("failed to insert new data: {0}")]
     InsertionError(String),
+    #[error("failed to remove data: {0}")]
+    RemovalError(String),
     #[error("failed to do index query: {0}")]
     QueryError(String),
     #[error("got unexpected error from index: {0}")]
@@ -55,29 +66,136 @@ impl Display for DataError {
     }
 }
 
+// A config that cannot be read or is not valid, with a message meant for the operator
+#[derive(Debug, thiserror::Error)]
+pub struct SettingsError(pub String);
+
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ApiError {
     // The `#[from]` attribute generates `From<JsonRejection> for ApiError`
     // implementation. See `thiserror` docs for more information
     #[error(transparent)]
     JsonExtractorRejection(#[from] JsonRejection),
+    #[error(transparent)]
+    DatabaseError(#[from] DBError),
+    #[error("no route for {0}")]
+    RouteNotFound(String),
+    #[error("database is recovering")]
+    NotReady,
+}
+
+impl ApiError {
+    // Stable code of the error returned to clients, which should not match on messages
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::JsonExtractorRejection(_) => "invalid_body",
+            ApiError::DatabaseError(e) => match e {
+                DBError::ValidationError(_) => "invalid_argument",
+                DBError::NotFoundError(_) => "not_found",
+                DBError::ConflictError(_) => "conflict",
+                _ => "internal",
+            },
+            ApiError::RouteNotFound(_) => "not_found",
+            ApiError::NotReady => "unavailable",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::JsonExtractorRejection(json_rejection) => json_rejection.status(),
+            ApiError::DatabaseError(e) => match e {
+                DBError::ValidationError(_) => StatusCode::BAD_REQUEST,
+                DBError::NotFoundError(_) => StatusCode::NOT_FOUND,
+                DBError::ConflictError(_) => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            ApiError::RouteNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::NotReady => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
 }
 
 // We implement `IntoResponse` so ApiError can be used as a response
 impl IntoResponse for ApiError {
     fn into_response(self) -> axum::response::Response {
-        event!(Level::INFO, "request rejected: {}", self);
+        let status = self.status();
+        // requests during recovery are expected, and retried by clients
+        if status.is_server_error() && !matches!(self, ApiError::NotReady) {
+            event!(Level::ERROR, "request failed: {}", self);
+        } else {
+            event!(Level::INFO, "request rejected: {}", self);
+        }
 
-        let (status, message) = match self {
-            ApiError::JsonExtractorRejection(json_rejection) => {
-                (json_rejection.status(), json_rejection.body_text())
-            }
+        let message = match &self {
+            ApiError::JsonExtractorRejection(json_rejection) => json_rejection.body_text(),
+            _ => self.to_string(),
         };
 
         let payload = json!({
+            "code": self.code(),
             "message": message,
         });
 
         (status, Json(payload)).into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn response_of(error: ApiError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_api_error_response() {
+        let (status, body) = response_of(
+            DBError::ValidationError("query vector length 2 does not match".to_string()).into(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({
+                "code": "invalid_argument",
+                "message": "invalid request: query vector length 2 does not match",
+            })
+        );
+
+        let (status, body) =
+            response_of(DBError::NotFoundError("unknown vector field: x".to_string()).into()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "not_found");
+
+        let (status, body) =
+            response_of(DBError::ConflictError("not an HNSW index".to_string()).into()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "conflict");
+
+        let (status, body) =
+            response_of(DBError::GetError("unable to query vector data".to_string()).into()).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["code"], "internal");
+
+        let (status, body) = response_of(ApiError::RouteNotFound("/nope".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["message"], "no route for /nope");
+
+        let (status, body) = response_of(ApiError::NotReady).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "unavailable");
+    }
+}
@@ -0,0 +1,132 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge_vec, Encoder, Histogram, HistogramVec, IntCounter,
+    IntCounterVec, IntGaugeVec, TextEncoder,
+};
+
+use crate::merror::DBError;
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "vecdb_http_requests_total",
+        "HTTP requests by route and status code",
+        &["route", "status"]
+    )
+    .unwrap();
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "vecdb_http_request_duration_seconds",
+        "HTTP request latency by route",
+        &["route"]
+    )
+    .unwrap();
+    pub static ref QUERY_DURATION: Histogram = register_histogram!(
+        "vecdb_query_duration_seconds",
+        "Latency of vector database queries"
+    )
+    .unwrap();
+    pub static ref UPSERT_DURATION: Histogram = register_histogram!(
+        "vecdb_upsert_duration_seconds",
+        "Latency of vector database upserts"
+    )
+    .unwrap();
+    pub static ref UPSERTED_ROWS: IntCounter = register_int_counter!(
+        "vecdb_upserted_rows_total",
+        "Rows written by successful upserts"
+    )
+    .unwrap();
+    pub static ref INDEX_SEARCH_DURATION: Histogram = register_histogram!(
+        "vecdb_index_search_duration_seconds",
+        "Latency of single dense index searches"
+    )
+    .unwrap();
+    pub static ref INDEX_INSERT_DURATION: Histogram = register_histogram!(
+        "vecdb_index_insert_duration_seconds",
+        "Latency of dense index inserts"
+    )
+    .unwrap();
+    pub static ref FILTER_BITMAP_SIZE: Histogram = register_histogram!(
+        "vecdb_filter_bitmap_size",
+        "Number of doc ids allowed by the filters of a request",
+        exponential_buckets(1.0, 10.0, 8).unwrap()
+    )
+    .unwrap();
+    pub static ref WAL_BYTES_WRITTEN: IntCounter = register_int_counter!(
+        "vecdb_wal_written_bytes_total",
+        "Bytes appended to the write-ahead log"
+    )
+    .unwrap();
+    pub static ref WAL_RECORDS_WRITTEN: IntCounter = register_int_counter!(
+        "vecdb_wal_written_records_total",
+        "Records appended to the write-ahead log"
+    )
+    .unwrap();
+
+    // the ones below are read from the database when scraped
+    pub static ref DOCS: IntGaugeVec = register_int_gauge_vec!(
+        "vecdb_docs",
+        "Number of stored docs, each holding one vector per vector field",
+        &["collection"]
+    )
+    .unwrap();
+    pub static ref STORAGE_PROPERTIES: IntGaugeVec = register_int_gauge_vec!(
+        "vecdb_storage_property",
+        "RocksDB internal statistics",
+        &["collection", "property"]
+    )
+    .unwrap();
+    pub static ref SHADOW_SAMPLES: IntCounterVec = register_int_counter_vec!(
+        "vecdb_shadow_samples_total",
+        "Searches compared with an exact scan in shadow mode",
+        &["collection"]
+    )
+    .unwrap();
+    // recall over a time window is the ratio of the rates of the two counters below
+    pub static ref SHADOW_FOUND: IntCounterVec = register_int_counter_vec!(
+        "vecdb_shadow_found_total",
+        "Exact top k hits of the shadow scans also returned by the index",
+        &["collection"]
+    )
+    .unwrap();
+    pub static ref SHADOW_EXPECTED: IntCounterVec = register_int_counter_vec!(
+        "vecdb_shadow_expected_total",
+        "Exact top k hits of the shadow scans",
+        &["collection"]
+    )
+    .unwrap();
+}
+
+// Moves a counter mirroring a cumulative value kept elsewhere up to that value
+pub fn sync_counter(counter: &IntCounter, value: u64) {
+    let current = counter.get();
+    if value > current {
+        counter.inc_by(value - current);
+    }
+}
+
+// Renders all the registered metrics in the Prometheus text format
+pub fn encode() -> Result<String, DBError> {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(|e| DBError::GetError(format!("unable to encode metrics: {e}")))?;
+
+    String::from_utf8(buffer)
+        .map_err(|e| DBError::GetError(format!("unable to encode metrics: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        WAL_BYTES_WRITTEN.inc_by(10);
+        sync_counter(&SHADOW_SAMPLES.with_label_values(&["test_encode"]), 3);
+        sync_counter(&SHADOW_SAMPLES.with_label_values(&["test_encode"]), 2);
+
+        let text = encode().unwrap();
+        assert!(text.contains("vecdb_wal_written_bytes_total"));
+        assert!(text.contains("vecdb_shadow_samples_total{collection=\"test_encode\"} 3"));
+    }
+}
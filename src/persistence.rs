@@ -1,5 +1,6 @@
 use crate::filter::IntFilterIndex;
 use crate::merror::{DataError, FileError};
+use crate::metrics;
 use crate::scalar::{ScalarStorage, NAMESPACE_WALS};
 use crate::vecdb::{VdbUpsertArgs, VectorDatabase};
 use serde::{Deserialize, Serialize};
@@ -99,13 +100,22 @@ fn new_persistence(
         .unwrap_or(0);
 
     let p: &Path = Path::new(wal_file_path);
-    if !p.exists() {
-        fs::create_dir_all(p)
+    if let Some(parent) = p.parent() {
+        fs::create_dir_all(parent)
             .map_err(|e| FileError(format!("Failed to create WAL log directory: {}", e)))?;
     }
 
-    let wal_file_writer =
-        File::open(p).map_err(|e| FileError(format!("Failed to init WAL log file: {}", e)))?;
+    // earlier versions created an empty directory in place of the log file
+    if p.is_dir() {
+        fs::remove_dir(p)
+            .map_err(|e| FileError(format!("Failed to remove WAL log directory: {}", e)))?;
+    }
+
+    let wal_file_writer = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(p)
+        .map_err(|e| FileError(format!("Failed to init WAL log file: {}", e)))?;
 
     Ok(Persistence {
         counter: AtomicU64::new(last_log_id),
@@ -160,6 +170,9 @@ impl Persistence {
         writeln!(self.wal_writer, "{}", json_record)
             .map_err(|e| FileError(format!("Failed to write operation: {e}")))?;
 
+        metrics::WAL_RECORDS_WRITTEN.inc();
+        metrics::WAL_BYTES_WRITTEN.inc_by(json_record.len() as u64 + 1);
+
         Ok(())
     }
 
@@ -199,6 +212,7 @@ pub async fn apply_wal_record(
 ///
 /// # Example
 /// ```rust
+/// # use vecdb_rs::persistence::RollbackGuard;
 /// let guard = RollbackGuard::new(|| {
 ///     // rollback logic here
 /// });
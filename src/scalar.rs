@@ -1,5 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use crate::index::SparseVector;
 use crate::merror::DBError;
 use rocksdb::Options;
 use serde_json::Value;
@@ -7,10 +8,29 @@ use std::path::Path;
 use std::sync::Mutex;
 
 type Mdb = rocksdb::DBWithThreadMode<rocksdb::MultiThreaded>;
+pub type VectorIter<'a> = Box<dyn Iterator<Item = Result<(u64, Vec<f32>), DBError>> + 'a>;
+pub type TokenParentIter<'a> = Box<dyn Iterator<Item = Result<(u64, u64), DBError>> + 'a>;
+pub type SparseVectorIter<'a> = Box<dyn Iterator<Item = Result<(u64, SparseVector), DBError>> + 'a>;
+pub type DocIter<'a> =
+    Box<dyn Iterator<Item = Result<(u64, HashMap<String, Value>), DBError>> + 'a>;
 
 const KEY_ID_MAX: &str = "__id_max__";
+const KEY_COUNT: &str = "__count__";
 pub const NAMESPACE_DOCS: &str = "docs";
 pub const NAMESPACE_WALS: &str = "wals";
+pub const NAMESPACE_VECTORS: &str = "vectors";
+pub const NAMESPACE_SPARSE_VECTORS: &str = "sparse";
+pub const NAMESPACE_FIELDS: &str = "fields";
+pub const NAMESPACE_TOKENS: &str = "tokens";
+pub const NAMESPACE_PARAMS: &str = "params";
+// RocksDB properties reported by `properties`
+const STORAGE_PROPERTIES: [&str; 5] = [
+    "rocksdb.estimate-num-keys",
+    "rocksdb.estimate-live-data-size",
+    "rocksdb.total-sst-files-size",
+    "rocksdb.cur-size-all-mem-tables",
+    "rocksdb.block-cache-usage",
+];
 
 pub trait ScalarStorage: Sync + Send {
     fn put(&self, key: &[u8], values: &[u8]) -> Result<(), DBError>;
@@ -31,10 +51,187 @@ pub trait ScalarStorage: Sync + Send {
 
     fn multi_get_value(&self, indices: &[u64]) -> Result<Vec<HashMap<String, Value>>, DBError>;
 
+    // Iterates over all stored docs in id order
+    fn doc_iter(&self) -> DocIter<'_> {
+        self.doc_iter_from(0)
+    }
+
+    // Iterates over the stored docs with an id of at least `start`, in id order
+    fn doc_iter_from(&self, start: u64) -> DocIter<'_>;
+
+    // Raw vectors are kept next to the docs so that indexes can be rebuilt from them
+    fn put_vector(&self, id: u64, vector: &[f32]) -> Result<(), DBError> {
+        self.put(&vector_key(id), &encode_vector(vector))
+    }
+
+    fn multi_get_vectors(&self, indices: &[u64]) -> Result<Vec<Option<Vec<f32>>>, DBError>;
+
+    // Iterates over all stored raw vectors in id order
+    fn vector_iter(&self) -> VectorIter<'_>;
+
+    // Raw vectors of the named vector fields, one namespace per field
+    fn put_field_vector(&self, field: &str, id: u64, vector: &[f32]) -> Result<(), DBError> {
+        self.put(&field_vector_key(field, id), &encode_vector(vector))
+    }
+
+    fn multi_get_field_vectors(
+        &self,
+        field: &str,
+        indices: &[u64],
+    ) -> Result<Vec<Option<Vec<f32>>>, DBError>;
+
+    fn field_vector_iter(&self, field: &str) -> VectorIter<'_>;
+
+    // Owner doc of each token vector of a multi-vector field
+    fn put_token_parent(&self, field: &str, token: u64, parent: u64) -> Result<(), DBError> {
+        self.put(&token_key(field, token), &parent.to_be_bytes())
+    }
+
+    fn token_parent_iter(&self, field: &str) -> TokenParentIter<'_>;
+
+    fn put_sparse_vector(&self, id: u64, vector: &SparseVector) -> Result<(), DBError> {
+        self.put(&sparse_vector_key(id), &encode_sparse_vector(vector))
+    }
+
+    fn multi_get_sparse_vectors(
+        &self,
+        indices: &[u64],
+    ) -> Result<Vec<Option<SparseVector>>, DBError>;
+
+    // Iterates over all stored sparse vectors in id order
+    fn sparse_vector_iter(&self) -> SparseVectorIter<'_>;
+
     // Generates a list of unique IDs starting from the last ID used
     fn gen_incr_ids(&self, namespace: &str, num: usize) -> Result<Vec<u64>, DBError>;
 
-    fn to_iter(&self) -> rocksdb::DBIteratorWithThreadMode<Mdb>;
+    // Marks IDs chosen by the caller as used, later generated IDs start after them
+    fn reserve_ids(&self, namespace: &str, ids: &[u64]) -> Result<(), DBError>;
+
+    // Number of live IDs in the namespace: the ones counted through `incr_count` once
+    // written, minus the ones given back through `decr_count`
+    fn count(&self, namespace: &str) -> Result<u64, DBError>;
+
+    fn incr_count(&self, namespace: &str, num: usize) -> Result<(), DBError>;
+
+    fn decr_count(&self, namespace: &str, num: usize) -> Result<(), DBError>;
+
+    // Deletes docs with their raw vectors in the default, sparse and given vector
+    // fields, multi-vector ones included, along with the owner mappings of the
+    // given tokens, in one batch
+    fn delete_docs(
+        &self,
+        ids: &[u64],
+        fields: &[String],
+        tokens: &[(String, Vec<u64>)],
+    ) -> Result<(), DBError>;
+
+    // Statistics of the underlying storage engine, by property name
+    fn properties(&self) -> Result<BTreeMap<String, u64>, DBError>;
+
+    fn to_iter(&self) -> rocksdb::DBIteratorWithThreadMode<'_, Mdb>;
+}
+
+fn vector_key(id: u64) -> Vec<u8> {
+    let mut key = NAMESPACE_VECTORS.as_bytes().to_vec();
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn field_prefix(field: &str) -> Vec<u8> {
+    format!("{NAMESPACE_FIELDS}/{field}/").into_bytes()
+}
+
+fn field_vector_key(field: &str, id: u64) -> Vec<u8> {
+    let mut key = field_prefix(field);
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn token_prefix(field: &str) -> Vec<u8> {
+    format!("{NAMESPACE_TOKENS}/{field}/").into_bytes()
+}
+
+fn token_key(field: &str, token: u64) -> Vec<u8> {
+    let mut key = token_prefix(field);
+    key.extend_from_slice(&token.to_be_bytes());
+    key
+}
+
+// Key of the tuned ef_search of a vector field, the default field has no name
+pub fn ef_search_key(field: Option<&str>) -> Vec<u8> {
+    format!("{NAMESPACE_PARAMS}/ef_search/{}", field.unwrap_or_default()).into_bytes()
+}
+
+// Key of the default index params set by the last rebuild
+pub fn rebuild_params_key() -> Vec<u8> {
+    format!("{NAMESPACE_PARAMS}/rebuild").into_bytes()
+}
+
+// Namespace of the id counter of token vectors, kept out of the token prefix
+pub fn token_id_namespace(field: &str) -> String {
+    format!("{NAMESPACE_TOKENS}:{field}")
+}
+
+fn decode_id(bytes: &[u8]) -> Result<u64, DBError> {
+    let id_bytes: [u8; 8] = bytes
+        .try_into()
+        .map_err(|e| DBError::GetError(format!("failed to convert bytes as u64: {e:?}")))?;
+
+    Ok(u64::from_be_bytes(id_bytes))
+}
+
+fn sparse_vector_key(id: u64) -> Vec<u8> {
+    let mut key = NAMESPACE_SPARSE_VECTORS.as_bytes().to_vec();
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+// layout: indices as u32 LE followed by values as f32 LE, both of the same length
+fn encode_sparse_vector(vector: &SparseVector) -> Vec<u8> {
+    vector
+        .indices
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .chain(encode_vector(&vector.values))
+        .collect()
+}
+
+fn decode_sparse_vector(bytes: &[u8]) -> Result<SparseVector, DBError> {
+    if bytes.len() % 8 != 0 {
+        return Err(DBError::GetError(format!(
+            "invalid sparse vector length in bytes: {}",
+            bytes.len()
+        )));
+    }
+
+    let (index_bytes, value_bytes) = bytes.split_at(bytes.len() / 2);
+    let indices = index_bytes
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+
+    Ok(SparseVector {
+        indices,
+        values: decode_vector(value_bytes)?,
+    })
+}
+
+fn encode_vector(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn decode_vector(bytes: &[u8]) -> Result<Vec<f32>, DBError> {
+    if bytes.len() % 4 != 0 {
+        return Err(DBError::GetError(format!(
+            "invalid raw vector length in bytes: {}",
+            bytes.len()
+        )));
+    }
+
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
 }
 
 pub fn new_scalar_storage<P: AsRef<Path>>(path: P) -> Result<impl ScalarStorage, DBError> {
@@ -101,6 +298,82 @@ impl ScalarStorage for MultiThreadRocksDB {
         Ok(result)
     }
 
+    fn doc_iter_from(&self, start: u64) -> DocIter<'_> {
+        // doc keys are the big endian ids, they sort before every namespaced key
+        let start_key = start.to_be_bytes();
+        let iter = self
+            .db
+            .iterator(rocksdb::IteratorMode::From(
+                &start_key,
+                rocksdb::Direction::Forward,
+            ))
+            .take_while(|item| match item {
+                Ok((key, _)) => key.len() == 8,
+                Err(_) => true,
+            })
+            .map(|item| {
+                let (key, value) = item.map_err(|e| DBError::GetError(e.to_string()))?;
+                let id_bytes: [u8; 8] = key.as_ref().try_into().map_err(|e| {
+                    DBError::GetError(format!("failed to convert doc key as u64: {e:?}"))
+                })?;
+                let doc = serde_json::from_slice::<HashMap<String, Value>>(&value)
+                    .map_err(|e| DBError::GetError(e.to_string()))?;
+
+                Ok((u64::from_be_bytes(id_bytes), doc))
+            });
+
+        Box::new(iter)
+    }
+
+    fn multi_get_vectors(&self, indices: &[u64]) -> Result<Vec<Option<Vec<f32>>>, DBError> {
+        let keys = indices.iter().map(|i| vector_key(*i));
+
+        multi_get_decoded(&self.db, keys, decode_vector)
+    }
+
+    fn vector_iter(&self) -> VectorIter<'_> {
+        Box::new(namespace_iter(
+            &self.db,
+            NAMESPACE_VECTORS.as_bytes().to_vec(),
+            decode_vector,
+        ))
+    }
+
+    fn multi_get_field_vectors(
+        &self,
+        field: &str,
+        indices: &[u64],
+    ) -> Result<Vec<Option<Vec<f32>>>, DBError> {
+        let keys = indices.iter().map(|i| field_vector_key(field, *i));
+
+        multi_get_decoded(&self.db, keys, decode_vector)
+    }
+
+    fn field_vector_iter(&self, field: &str) -> VectorIter<'_> {
+        Box::new(namespace_iter(&self.db, field_prefix(field), decode_vector))
+    }
+
+    fn token_parent_iter(&self, field: &str) -> TokenParentIter<'_> {
+        Box::new(namespace_iter(&self.db, token_prefix(field), decode_id))
+    }
+
+    fn multi_get_sparse_vectors(
+        &self,
+        indices: &[u64],
+    ) -> Result<Vec<Option<SparseVector>>, DBError> {
+        let keys = indices.iter().map(|i| sparse_vector_key(*i));
+
+        multi_get_decoded(&self.db, keys, decode_sparse_vector)
+    }
+
+    fn sparse_vector_iter(&self) -> SparseVectorIter<'_> {
+        Box::new(namespace_iter(
+            &self.db,
+            NAMESPACE_SPARSE_VECTORS.as_bytes().to_vec(),
+            decode_sparse_vector,
+        ))
+    }
+
     fn gen_incr_ids(&self, namespace: &str, num: usize) -> Result<Vec<u64>, DBError> {
         let _guard = self
             .id_mutex
@@ -110,33 +383,206 @@ impl ScalarStorage for MultiThreadRocksDB {
         gen_incr_ids(&self.db, namespace, num)
     }
 
-    fn to_iter(&self) -> rocksdb::DBIteratorWithThreadMode<Mdb> {
+    fn reserve_ids(&self, namespace: &str, ids: &[u64]) -> Result<(), DBError> {
+        let _guard = self
+            .id_mutex
+            .lock()
+            .map_err(|e| DBError::GetError(format!("failed to acquire lock: {e:?}",)))?;
+
+        let max_id_key = format!("{namespace}{KEY_ID_MAX}");
+        let max_id = read_u64(&self.db, &max_id_key)?.unwrap_or(0);
+        let count = read_count(&self.db, namespace)?;
+
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put(
+            max_id_key.as_bytes(),
+            ids.iter().copied().fold(max_id, u64::max).to_be_bytes(),
+        );
+        batch.put(
+            format!("{namespace}{KEY_COUNT}").as_bytes(),
+            count.to_be_bytes(),
+        );
+
+        self.db
+            .write(batch)
+            .map_err(|e| DBError::PutError(format!("failed to reserve ids: {e:?}")))
+    }
+
+    fn count(&self, namespace: &str) -> Result<u64, DBError> {
+        read_count(&self.db, namespace)
+    }
+
+    fn incr_count(&self, namespace: &str, num: usize) -> Result<(), DBError> {
+        let _guard = self
+            .id_mutex
+            .lock()
+            .map_err(|e| DBError::GetError(format!("failed to acquire lock: {e:?}",)))?;
+
+        let count = read_count(&self.db, namespace)? + num as u64;
+        let count_key = format!("{namespace}{KEY_COUNT}");
+
+        self.db
+            .put(count_key.as_bytes(), count.to_be_bytes())
+            .map_err(|e| DBError::PutError(format!("failed to update count: {e:?}")))
+    }
+
+    fn decr_count(&self, namespace: &str, num: usize) -> Result<(), DBError> {
+        let _guard = self
+            .id_mutex
+            .lock()
+            .map_err(|e| DBError::GetError(format!("failed to acquire lock: {e:?}",)))?;
+
+        let count = read_count(&self.db, namespace)?.saturating_sub(num as u64);
+        let count_key = format!("{namespace}{KEY_COUNT}");
+
+        self.db
+            .put(count_key.as_bytes(), count.to_be_bytes())
+            .map_err(|e| DBError::PutError(format!("failed to update count: {e:?}")))
+    }
+
+    fn delete_docs(
+        &self,
+        ids: &[u64],
+        fields: &[String],
+        tokens: &[(String, Vec<u64>)],
+    ) -> Result<(), DBError> {
+        let mut batch = rocksdb::WriteBatch::default();
+
+        for id in ids {
+            batch.delete(id.to_be_bytes());
+            batch.delete(vector_key(*id));
+            batch.delete(sparse_vector_key(*id));
+            for field in fields {
+                batch.delete(field_vector_key(field, *id));
+            }
+        }
+
+        // the vectors of multi-vector fields are stored under the doc id, not per token
+        for (field, field_tokens) in tokens {
+            for token in field_tokens {
+                batch.delete(token_key(field, *token));
+            }
+        }
+
+        self.db
+            .write(batch)
+            .map_err(|e| DBError::DeleteDataError(format!("failed to delete docs: {e:?}")))
+    }
+
+    fn properties(&self) -> Result<BTreeMap<String, u64>, DBError> {
+        let mut properties = BTreeMap::new();
+
+        for name in STORAGE_PROPERTIES {
+            let value = self
+                .db
+                .property_int_value(name)
+                .map_err(|e| DBError::GetError(format!("failed to read property {name}: {e}")))?;
+
+            if let Some(value) = value {
+                properties.insert(name.to_string(), value);
+            }
+        }
+
+        Ok(properties)
+    }
+
+    fn to_iter(&self) -> rocksdb::DBIteratorWithThreadMode<'_, Mdb> {
         self.db.iterator(rocksdb::IteratorMode::Start)
     }
 }
 
+fn multi_get_decoded<T>(
+    db: &Mdb,
+    keys: impl IntoIterator<Item = Vec<u8>>,
+    decode: fn(&[u8]) -> Result<T, DBError>,
+) -> Result<Vec<Option<T>>, DBError> {
+    db.multi_get(keys)
+        .into_iter()
+        .map(|res| match res {
+            Ok(Some(bytes)) => decode(&bytes).map(Some),
+            Ok(None) => Ok(None),
+            Err(e) => Err(DBError::GetError(e.to_string())),
+        })
+        .collect()
+}
+
+// Walks the keys `prefix` + big endian id in id order, decoding each value
+fn namespace_iter<'a, T: 'a>(
+    db: &'a Mdb,
+    prefix: Vec<u8>,
+    decode: fn(&[u8]) -> Result<T, DBError>,
+) -> impl Iterator<Item = Result<(u64, T), DBError>> + 'a {
+    let prefix_len = prefix.len();
+
+    db.iterator(rocksdb::IteratorMode::From(
+        &prefix,
+        rocksdb::Direction::Forward,
+    ))
+    .take_while(move |item| match item {
+        Ok((key, _)) => key.starts_with(&prefix),
+        Err(_) => true,
+    })
+    .map(move |item| {
+        let (key, value) = item.map_err(|e| DBError::GetError(e.to_string()))?;
+        let id_bytes: [u8; 8] = key[prefix_len..].try_into().map_err(|e| {
+            DBError::GetError(format!("failed to convert vector key as u64: {e:?}"))
+        })?;
+
+        Ok((u64::from_be_bytes(id_bytes), decode(&value)?))
+    })
+}
+
+fn read_u64<T: rocksdb::ThreadMode>(
+    db: &rocksdb::DBWithThreadMode<T>,
+    key: &str,
+) -> Result<Option<u64>, DBError> {
+    let bytes = db
+        .get(key.as_bytes())
+        .map_err(|e| DBError::GetError(e.to_string()))?;
+
+    bytes
+        .map(|bytes| {
+            Ok(u64::from_be_bytes(bytes.try_into().map_err(|e| {
+                DBError::GetError(format!("failed to convert {key} as u64: {e:?}"))
+            })?))
+        })
+        .transpose()
+}
+
+fn read_count<T: rocksdb::ThreadMode>(
+    db: &rocksdb::DBWithThreadMode<T>,
+    namespace: &str,
+) -> Result<u64, DBError> {
+    match read_u64(db, &format!("{namespace}{KEY_COUNT}"))? {
+        Some(count) => Ok(count),
+        // stores written before the count existed never gave IDs back
+        None => Ok(read_u64(db, &format!("{namespace}{KEY_ID_MAX}"))?.unwrap_or(0)),
+    }
+}
+
 fn gen_incr_ids<T: rocksdb::ThreadMode>(
     db: &rocksdb::DBWithThreadMode<T>,
     namespace: &str,
     num: usize,
 ) -> Result<Vec<u64>, DBError> {
-    let max_id_as_bytes = db
-        .get(format!("{namespace}{KEY_ID_MAX}").as_bytes())
-        .map_err(|e| DBError::GetError(e.to_string()))?;
-
-    let max_id: u64 =
-        match max_id_as_bytes {
-            Some(bytes) => u64::from_be_bytes(bytes.try_into().map_err(|e| {
-                DBError::GetError(format!("failed to convert incr ID as u64: {e:?}"))
-            })?),
-            None => 0,
-        };
+    let max_id_key = format!("{namespace}{KEY_ID_MAX}");
+    let max_id = read_u64(db, &max_id_key)?.unwrap_or(0);
+    let count = read_count(db, namespace)?;
 
     let new_max_id = max_id + num as u64;
 
     let ids: Vec<u64> = (max_id + 1..new_max_id + 1).collect::<Vec<u64>>();
 
-    db.put(KEY_ID_MAX.as_bytes(), new_max_id.to_be_bytes())
+    // the ids are only counted once written, but stores without a count read it from the
+    // id counter, so the current count is kept before the counter moves
+    let mut batch = rocksdb::WriteBatch::default();
+    batch.put(max_id_key.as_bytes(), new_max_id.to_be_bytes());
+    batch.put(
+        format!("{namespace}{KEY_COUNT}").as_bytes(),
+        count.to_be_bytes(),
+    );
+
+    db.write(batch)
         .map_err(|e| DBError::PutError(format!("failed to insert new generated max id: {e:?}")))?;
 
     Ok(ids)
@@ -146,12 +592,19 @@ fn gen_incr_ids<T: rocksdb::ThreadMode>(
 pub fn debug_print_scalar_db(ss: &dyn ScalarStorage) -> Result<(), DBError> {
     let iter = ss.to_iter();
 
-    let special: [u8; 10] = [95, 95, 105, 100, 95, 109, 97, 120, 95, 95];
-
     for data in iter {
         let data_pair: (Box<[u8]>, Box<[u8]>) = data.unwrap();
 
-        if data_pair.0.len() == special.len() {
+        if data_pair.0.starts_with(NAMESPACE_VECTORS.as_bytes())
+            || data_pair.0.starts_with(NAMESPACE_SPARSE_VECTORS.as_bytes())
+            || data_pair.0.starts_with(NAMESPACE_FIELDS.as_bytes())
+            || data_pair.0.starts_with(NAMESPACE_TOKENS.as_bytes())
+            || data_pair.0.starts_with(NAMESPACE_PARAMS.as_bytes())
+        {
+            continue;
+        }
+
+        if data_pair.0.ends_with(KEY_ID_MAX.as_bytes()) {
             println!(
                 "max id: {:?}",
                 u64::from_be_bytes(data_pair.1.to_vec().try_into().unwrap())
@@ -160,6 +613,15 @@ pub fn debug_print_scalar_db(ss: &dyn ScalarStorage) -> Result<(), DBError> {
             continue;
         }
 
+        if data_pair.0.ends_with(KEY_COUNT.as_bytes()) {
+            println!(
+                "count: {:?}",
+                u64::from_be_bytes(data_pair.1.to_vec().try_into().unwrap())
+            );
+
+            continue;
+        }
+
         let key_temp: Result<[u8; 8], _> = data_pair.0.to_vec().try_into();
         let mut key: u64 = 0;
 
@@ -224,6 +686,27 @@ mod tests {
         assert_eq!(retrieved_value.len(), 2);
         assert_eq!(retrieved_value[0].get("msg").unwrap(), msg1);
         assert_eq!(retrieved_value[1].get("msg").unwrap(), msg2);
+
+        db.put_vector(key1, &[0.1]).unwrap();
+        db.gen_incr_ids(NAMESPACE_DOCS, 1).unwrap();
+        let docs = db
+            .doc_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("failed to iterate docs");
+        assert_eq!(
+            docs.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![key1, key2]
+        );
+        assert_eq!(docs[1].1.get("msg").unwrap(), msg2);
+
+        let docs = db
+            .doc_iter_from(key1 + 1)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("failed to iterate docs");
+        assert_eq!(
+            docs.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![key2]
+        );
     }
 
     fn test_db_get_value(db: &mut impl ScalarStorage) {
@@ -244,6 +727,104 @@ mod tests {
         assert_eq!(retrieved_value, value);
     }
 
+    fn test_db_vectors(db: &mut impl ScalarStorage) {
+        db.put_vector(2, &[0.4, 0.5, 0.6]).unwrap();
+        db.put_vector(1, &[0.1, 0.2, 0.3]).unwrap();
+
+        let vectors = db
+            .multi_get_vectors(&[1, 2, 3])
+            .expect("failed to get vectors");
+        assert_eq!(vectors[0], Some(vec![0.1, 0.2, 0.3]));
+        assert_eq!(vectors[1], Some(vec![0.4, 0.5, 0.6]));
+        assert_eq!(vectors[2], None);
+
+        let stored = db
+            .vector_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("failed to iterate vectors");
+        assert_eq!(
+            stored,
+            vec![(1, vec![0.1, 0.2, 0.3]), (2, vec![0.4, 0.5, 0.6])]
+        );
+
+        let sparse = SparseVector {
+            indices: vec![3, 30000],
+            values: vec![0.5, 1.5],
+        };
+        db.put_sparse_vector(2, &sparse).unwrap();
+        assert_eq!(
+            db.multi_get_sparse_vectors(&[1, 2]).unwrap(),
+            vec![None, Some(sparse.clone())]
+        );
+        let stored = db
+            .sparse_vector_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("failed to iterate sparse vectors");
+        assert_eq!(stored, vec![(2, sparse)]);
+
+        db.put_field_vector("title", 1, &[1.0, 2.0]).unwrap();
+        db.put_field_vector("title", 2, &[3.0, 4.0]).unwrap();
+        db.put_field_vector("title_body", 1, &[5.0]).unwrap();
+        assert_eq!(
+            db.multi_get_field_vectors("title", &[2, 3]).unwrap(),
+            vec![Some(vec![3.0, 4.0]), None]
+        );
+        let stored = db
+            .field_vector_iter("title")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("failed to iterate field vectors");
+        assert_eq!(stored, vec![(1, vec![1.0, 2.0]), (2, vec![3.0, 4.0])]);
+
+        let tokens = db.gen_incr_ids(&token_id_namespace("title"), 3).unwrap();
+        for token in &tokens {
+            db.put_token_parent("title", *token, 7).unwrap();
+        }
+        let stored = db
+            .token_parent_iter("title")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("failed to iterate token parents");
+        assert_eq!(stored, vec![(1, 7), (2, 7), (3, 7)]);
+    }
+
+    #[test]
+    fn test_gen_incr_ids() {
+        let path = setup(format!("incr_ids_{}", Uuid::new_v4()).as_str());
+
+        let db = new_scalar_storage(&path).unwrap();
+
+        assert_eq!(db.gen_incr_ids(NAMESPACE_DOCS, 2).unwrap(), vec![1, 2]);
+        assert_eq!(db.gen_incr_ids(NAMESPACE_DOCS, 3).unwrap(), vec![3, 4, 5]);
+        // allocated ids are not counted until written
+        assert_eq!(db.count(NAMESPACE_DOCS).unwrap(), 0);
+        db.incr_count(NAMESPACE_DOCS, 5).unwrap();
+        assert_eq!(db.count(NAMESPACE_DOCS).unwrap(), 5);
+
+        db.decr_count(NAMESPACE_DOCS, 2).unwrap();
+        assert_eq!(db.count(NAMESPACE_DOCS).unwrap(), 3);
+        assert_eq!(db.gen_incr_ids(NAMESPACE_DOCS, 1).unwrap(), vec![6]);
+        db.reserve_ids(NAMESPACE_DOCS, &[10]).unwrap();
+        assert_eq!(db.count(NAMESPACE_DOCS).unwrap(), 3);
+        assert_eq!(db.gen_incr_ids(NAMESPACE_DOCS, 1).unwrap(), vec![11]);
+        assert_eq!(db.count(NAMESPACE_WALS).unwrap(), 0);
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_debug_print_scalar_db() {
+        let path = setup(format!("debug_print_{}", Uuid::new_v4()).as_str());
+
+        let db = new_scalar_storage(&path).unwrap();
+        db.put(&1u64.to_be_bytes(), br#"{"id": 1}"#).unwrap();
+        // not UTF-8
+        db.put(&ef_search_key(None), &200u32.to_be_bytes()).unwrap();
+
+        debug_print_scalar_db(&db).unwrap();
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+
     #[test]
     fn test_single_thread_rocksdb() {
         let path = setup(format!("single_thread_{}", Uuid::new_v4()).as_str());
@@ -252,6 +833,7 @@ mod tests {
 
         test_db_multi_get_value(&mut db);
         test_db_get_value(&mut db);
+        test_db_vectors(&mut db);
 
         fs::remove_dir_all(&path).unwrap();
     }
@@ -264,6 +846,7 @@ mod tests {
 
         test_db_multi_get_value(&mut db);
         test_db_get_value(&mut db);
+        test_db_vectors(&mut db);
 
         fs::remove_dir_all(&path).unwrap();
     }
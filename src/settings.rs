@@ -0,0 +1,340 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use tracing::Level;
+
+use crate::ingest::MAX_INGEST_BATCH_SIZE;
+use crate::merror::SettingsError;
+use crate::vecdb::DatabaseParams;
+
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+// VECDB_FILE_PATH, VECDB_SERVER__PORT, VECDB_DATABASE__DIM and so on
+pub const ENV_PREFIX: &str = "VECDB";
+const ENV_SEPARATOR: &str = "__";
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1";
+const DEFAULT_GRPC_PORT: u16 = 7001;
+
+// routes served whatever the config
+pub const HEALTH_URL: &str = "/healthz";
+pub const READY_URL: &str = "/readyz";
+pub const STATS_URL: &str = "/stats";
+pub const METRICS_URL: &str = "/metrics";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AppConfig {
+    pub file_path: String,
+    pub database: DatabaseParams,
+    pub server: ServerConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ServerConfig {
+    pub search_url_suffix: String,
+    pub upsert_url_suffix: String,
+    // the settings added after the first release have defaults, so that older config
+    // files keep loading
+    #[serde(default = "default_rebuild_url_suffix")]
+    pub rebuild_url_suffix: String,
+    #[serde(default = "default_recommend_url_suffix")]
+    pub recommend_url_suffix: String,
+    #[serde(default = "default_scroll_url_suffix")]
+    pub scroll_url_suffix: String,
+    #[serde(default = "default_count_url_suffix")]
+    pub count_url_suffix: String,
+    #[serde(default = "default_facet_url_suffix")]
+    pub facet_url_suffix: String,
+    #[serde(default = "default_tune_url_suffix")]
+    pub tune_url_suffix: String,
+    #[serde(default = "default_ingest_url_suffix")]
+    pub ingest_url_suffix: String,
+    // rows of a streamed ingest upserted at once
+    pub ingest_batch_size: Option<usize>,
+    // address both the HTTP and the gRPC servers listen on
+    pub bind_address: IpAddr,
+    pub port: u16,
+    #[serde(default = "default_grpc_port")]
+    pub grpc_port: u16,
+    pub log_level: String,
+}
+
+fn default_rebuild_url_suffix() -> String {
+    "/rebuild".to_string()
+}
+
+fn default_recommend_url_suffix() -> String {
+    "/recommend".to_string()
+}
+
+fn default_scroll_url_suffix() -> String {
+    "/scroll".to_string()
+}
+
+fn default_count_url_suffix() -> String {
+    "/count".to_string()
+}
+
+fn default_facet_url_suffix() -> String {
+    "/facet".to_string()
+}
+
+fn default_tune_url_suffix() -> String {
+    "/tune".to_string()
+}
+
+fn default_ingest_url_suffix() -> String {
+    "/ingest".to_string()
+}
+
+fn default_grpc_port() -> u16 {
+    DEFAULT_GRPC_PORT
+}
+
+impl ServerConfig {
+    fn url_suffixes(&self) -> [(&'static str, &str); 9] {
+        [
+            ("search_url_suffix", &self.search_url_suffix),
+            ("upsert_url_suffix", &self.upsert_url_suffix),
+            ("rebuild_url_suffix", &self.rebuild_url_suffix),
+            ("recommend_url_suffix", &self.recommend_url_suffix),
+            ("scroll_url_suffix", &self.scroll_url_suffix),
+            ("count_url_suffix", &self.count_url_suffix),
+            ("facet_url_suffix", &self.facet_url_suffix),
+            ("tune_url_suffix", &self.tune_url_suffix),
+            ("ingest_url_suffix", &self.ingest_url_suffix),
+        ]
+    }
+}
+
+impl AppConfig {
+    // Reads the config file, then the VECDB_* environment variables over it, then the
+    // bind address given on the command line over both
+    pub fn load(path: &Path, bind_address: Option<IpAddr>) -> Result<Self, SettingsError> {
+        let settings = config::Config::builder()
+            .set_default("server.bind_address", DEFAULT_BIND_ADDRESS)
+            .and_then(|builder| {
+                builder
+                    .add_source(config::File::from(path))
+                    .add_source(
+                        config::Environment::with_prefix(ENV_PREFIX)
+                            .prefix_separator("_")
+                            .separator(ENV_SEPARATOR)
+                            .try_parsing(true),
+                    )
+                    .set_override_option(
+                        "server.bind_address",
+                        bind_address.map(|address| address.to_string()),
+                    )
+            })
+            .and_then(|builder| builder.build())
+            .map_err(|e| SettingsError(format!("unable to read {}: {e}", path.display())))?;
+
+        let app_config: AppConfig = settings
+            .try_deserialize()
+            .map_err(|e| SettingsError(format!("invalid config in {}: {e}", path.display())))?;
+        app_config.validate()?;
+
+        Ok(app_config)
+    }
+
+    // Catches the mistakes that would otherwise only fail once the server is running
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let mut errors = vec![];
+
+        if self.file_path.trim().is_empty() {
+            errors.push("file_path must not be empty".to_string());
+        }
+
+        if self.database.dim == 0 {
+            errors.push("database.dim must be positive".to_string());
+        }
+
+        if let Some(rate) = self.database.shadow_sample_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                errors.push(format!(
+                    "database.shadow_sample_rate {rate} is not between 0 and 1"
+                ));
+            }
+        }
+
+        let server = &self.server;
+        if Level::from_str(&server.log_level).is_err() {
+            errors.push(format!(
+                "server.log_level {:?} is not one of trace, debug, info, warn or error",
+                server.log_level
+            ));
+        }
+
+        if server.port == 0 || server.grpc_port == 0 {
+            errors.push("server.port and server.grpc_port must be positive".to_string());
+        } else if server.port == server.grpc_port {
+            errors.push(format!(
+                "server.port and server.grpc_port are both {}",
+                server.port
+            ));
+        }
+
+        if let Some(batch_size) = server.ingest_batch_size {
+            if batch_size == 0 || batch_size > MAX_INGEST_BATCH_SIZE {
+                errors.push(format!(
+                    "server.ingest_batch_size {batch_size} is not between 1 and {MAX_INGEST_BATCH_SIZE}"
+                ));
+            }
+        }
+
+        let mut urls = BTreeSet::from([HEALTH_URL, READY_URL, STATS_URL, METRICS_URL]);
+        for (name, url) in server.url_suffixes() {
+            if !url.starts_with('/') {
+                errors.push(format!("server.{name} {url:?} must start with /"));
+            } else if !urls.insert(url) {
+                errors.push(format!("server.{name} {url:?} is already in use"));
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(SettingsError(format!(
+                "invalid config:\n  {}",
+                errors.join("\n  ")
+            ))),
+        }
+    }
+
+    // The effective config in the format of the config file
+    pub fn to_toml(&self) -> Result<String, SettingsError> {
+        toml::to_string_pretty(self)
+            .map_err(|e| SettingsError(format!("unable to print config: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+file_path = "./testdata"
+
+[database]
+dim = 128
+metric_type = "L2"
+index_type = "Flat"
+version = "0.1.0"
+
+[server]
+search_url_suffix = "/search"
+upsert_url_suffix = "/upsert"
+rebuild_url_suffix = "/rebuild"
+recommend_url_suffix = "/recommend"
+scroll_url_suffix = "/scroll"
+count_url_suffix = "/count"
+facet_url_suffix = "/facet"
+tune_url_suffix = "/tune"
+ingest_url_suffix = "/ingest"
+port = 7000
+grpc_port = 7001
+log_level = "debug"
+"#;
+
+    fn write_config(content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("vecdb-config-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    // a single test, since the environment is shared by the tests running in parallel
+    #[test]
+    fn test_load() {
+        let path = write_config(CONFIG);
+
+        let app_config = AppConfig::load(&path, None).unwrap();
+        assert_eq!(app_config.server.bind_address.to_string(), "127.0.0.1");
+        assert_eq!(app_config.database.dim, 128);
+
+        // the environment overrides the file, the command line overrides both
+        std::env::set_var("VECDB_SERVER__PORT", "8000");
+        std::env::set_var("VECDB_SERVER__BIND_ADDRESS", "10.0.0.1");
+        std::env::set_var("VECDB_DATABASE__DIM", "64");
+        let app_config = AppConfig::load(&path, Some("0.0.0.0".parse().unwrap()));
+        std::env::remove_var("VECDB_SERVER__PORT");
+        std::env::remove_var("VECDB_SERVER__BIND_ADDRESS");
+        std::env::remove_var("VECDB_DATABASE__DIM");
+
+        let app_config = app_config.unwrap();
+        assert_eq!(app_config.server.port, 8000);
+        assert_eq!(app_config.server.bind_address.to_string(), "0.0.0.0");
+        assert_eq!(app_config.database.dim, 64);
+
+        // what is printed loads back to the same config
+        let printed = write_config(&app_config.to_toml().unwrap());
+        let reloaded = AppConfig::load(&printed, None).unwrap();
+        assert_eq!(reloaded.to_toml().unwrap(), app_config.to_toml().unwrap());
+
+        let missing = AppConfig::load(Path::new("/nonexistent/config.toml"), None);
+        assert!(missing.unwrap_err().0.contains("/nonexistent/config.toml"));
+
+        let invalid = write_config(&CONFIG.replace("port = 7000", "port = \"http\""));
+        assert!(AppConfig::load(&invalid, None)
+            .unwrap_err()
+            .0
+            .contains("invalid config"));
+
+        for path in [path, printed, invalid] {
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let app_config: AppConfig =
+            toml::from_str(&format!("{CONFIG}bind_address = \"127.0.0.1\"\n")).unwrap();
+
+        let mut invalid = app_config.clone();
+        invalid.database.dim = 0;
+        invalid.server.log_level = "loud".to_string();
+        invalid.server.grpc_port = invalid.server.port;
+        invalid.server.ingest_batch_size = Some(0);
+        invalid.server.count_url_suffix = "/search".to_string();
+        invalid.server.facet_url_suffix = "facet".to_string();
+        invalid.server.tune_url_suffix = "/metrics".to_string();
+
+        let message = invalid.validate().unwrap_err().0;
+        for expected in [
+            "database.dim",
+            "server.log_level \"loud\"",
+            "are both 7000",
+            "server.ingest_batch_size 0",
+            "server.count_url_suffix \"/search\" is already in use",
+            "server.facet_url_suffix \"facet\" must start with /",
+            "server.tune_url_suffix \"/metrics\" is already in use",
+        ] {
+            assert!(message.contains(expected), "{expected} not in {message}");
+        }
+
+        assert!(app_config.validate().is_ok());
+
+        // config files written before the later settings were added still load
+        let old: AppConfig = toml::from_str(
+            r#"
+file_path = "./testdata"
+
+[database]
+dim = 128
+metric_type = "L2"
+index_type = "Flat"
+version = "0.1.0"
+
+[server]
+search_url_suffix = "/search"
+upsert_url_suffix = "/upsert"
+bind_address = "127.0.0.1"
+port = 7000
+log_level = "debug"
+"#,
+        )
+        .unwrap();
+        assert_eq!(old.server.grpc_port, 7001);
+        assert_eq!(old.server.ingest_url_suffix, "/ingest");
+        assert!(old.validate().is_ok());
+    }
+}
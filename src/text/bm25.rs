@@ -0,0 +1,193 @@
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::tokenize;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Bm25Params {
+    // term frequency saturation
+    pub k1: f32,
+    // document length normalization, 0 disables it
+    pub b: f32,
+}
+
+impl Default for Bm25Params {
+    fn default() -> Self {
+        Self { k1: 1.2, b: 0.75 }
+    }
+}
+
+/// In-memory inverted index scoring documents with Okapi BM25.
+pub struct Bm25Index {
+    params: Bm25Params,
+    // term -> doc id -> term frequency
+    postings: HashMap<String, HashMap<u64, u32>>,
+    // doc id -> distinct terms of the doc, used for removal
+    doc_terms: HashMap<u64, Vec<String>>,
+    doc_lengths: HashMap<u64, u32>,
+    total_length: u64,
+}
+
+impl Bm25Index {
+    pub fn new(params: Bm25Params) -> Self {
+        Self {
+            params,
+            postings: HashMap::new(),
+            doc_terms: HashMap::new(),
+            doc_lengths: HashMap::new(),
+            total_length: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.doc_lengths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.doc_lengths.is_empty()
+    }
+
+    pub fn insert(&mut self, id: u64, text: &str) {
+        self.remove(id);
+
+        let terms = tokenize(text);
+        if terms.is_empty() {
+            return;
+        }
+
+        let mut term_freqs: HashMap<String, u32> = HashMap::new();
+        for term in &terms {
+            *term_freqs.entry(term.clone()).or_default() += 1;
+        }
+
+        self.doc_lengths.insert(id, terms.len() as u32);
+        self.total_length += terms.len() as u64;
+        self.doc_terms
+            .insert(id, term_freqs.keys().cloned().collect());
+
+        for (term, freq) in term_freqs {
+            self.postings.entry(term).or_default().insert(id, freq);
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        let Some(terms) = self.doc_terms.remove(&id) else {
+            return;
+        };
+
+        for term in terms {
+            if let Some(docs) = self.postings.get_mut(&term) {
+                docs.remove(&id);
+                if docs.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+
+        if let Some(length) = self.doc_lengths.remove(&id) {
+            self.total_length -= length as u64;
+        }
+    }
+
+    /// Returns up to `k` `(id, score)` pairs sorted by descending score,
+    /// only docs contained in `filter` are considered when it is given.
+    pub fn search(&self, query: &str, k: usize, filter: Option<&RoaringBitmap>) -> Vec<(u64, f32)> {
+        if k == 0 || self.doc_lengths.is_empty() {
+            return vec![];
+        }
+
+        let mut query_terms = tokenize(query);
+        query_terms.sort();
+        query_terms.dedup();
+
+        let num_docs = self.doc_lengths.len() as f32;
+        let avg_length = self.total_length as f32 / num_docs;
+        let Bm25Params { k1, b } = self.params;
+
+        let mut scores: HashMap<u64, f32> = HashMap::new();
+        for term in &query_terms {
+            let Some(docs) = self.postings.get(term) else {
+                continue;
+            };
+
+            let doc_freq = docs.len() as f32;
+            let idf = (1.0 + (num_docs - doc_freq + 0.5) / (doc_freq + 0.5)).ln();
+
+            for (id, freq) in docs {
+                if filter.is_some_and(|f| !f.contains(*id as u32)) {
+                    continue;
+                }
+
+                let freq = *freq as f32;
+                let length = self.doc_lengths[id] as f32;
+                let norm = k1 * (1.0 - b + b * length / avg_length);
+
+                *scores.entry(*id).or_default() += idf * freq * (k1 + 1.0) / (freq + norm);
+            }
+        }
+
+        let mut hits = scores.into_iter().collect::<Vec<_>>();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits.truncate(k);
+
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_index() -> Bm25Index {
+        let mut index = Bm25Index::new(Bm25Params::default());
+        index.insert(1, "the quick brown fox");
+        index.insert(2, "the lazy dog sleeps all day");
+        index.insert(3, "a quick brown dog jumps over the quick fox");
+        index
+    }
+
+    #[test]
+    fn test_search() {
+        let index = create_test_index();
+
+        let hits = index.search("quick fox", 10, None);
+        let ids = hits.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 3]);
+        assert!(hits[0].1 > hits[1].1);
+
+        assert_eq!(index.search("dog", 1, None).len(), 1);
+        assert!(index.search("cat", 10, None).is_empty());
+    }
+
+    #[test]
+    fn test_search_with_filter() {
+        let index = create_test_index();
+
+        let filter = RoaringBitmap::from_iter([2u32, 3]);
+        let hits = index.search("quick dog", 10, Some(&filter));
+        let ids = hits.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 2]);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut index = create_test_index();
+
+        index.remove(1);
+        index.remove(42);
+        assert_eq!(index.len(), 2);
+
+        let ids = index
+            .search("fox", 10, None)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![3]);
+
+        // re-inserting replaces the previous content
+        index.insert(3, "sleepy cat");
+        assert!(index.search("fox", 10, None).is_empty());
+        assert_eq!(index.search("cat", 10, None)[0].0, 3);
+    }
+}
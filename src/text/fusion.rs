@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum FusionMethod {
+    // reciprocal rank fusion, a larger `k` flattens the weight of the top ranks
+    Rrf { k: f32 },
+    // min-max normalized scores blended with `vector_weight` given to the dense
+    // vector side, the other rankings share the remaining weight evenly
+    Weighted { vector_weight: f32 },
+}
+
+impl Default for FusionMethod {
+    fn default() -> Self {
+        FusionMethod::Rrf { k: 60.0 }
+    }
+}
+
+fn normalize(hits: &[(u64, f32)]) -> HashMap<u64, f32> {
+    let min = hits.iter().map(|(_, s)| *s).fold(f32::INFINITY, f32::min);
+    let max = hits
+        .iter()
+        .map(|(_, s)| *s)
+        .fold(f32::NEG_INFINITY, f32::max);
+    let range = max - min;
+
+    hits.iter()
+        .map(|(id, score)| {
+            let normalized = if range > 0.0 {
+                (score - min) / range
+            } else {
+                1.0
+            };
+            (*id, normalized)
+        })
+        .collect()
+}
+
+/// Merges the dense vector ranking with the other rankings (keyword, sparse),
+/// all given best-first as `(id, score)` pairs where a higher score is better.
+/// Returns at most `k` fused `(id, score)` pairs sorted by descending fused score.
+pub fn fuse(
+    vector_hits: &[(u64, f32)],
+    other_hits: &[Vec<(u64, f32)>],
+    method: FusionMethod,
+    k: usize,
+) -> Vec<(u64, f32)> {
+    let mut fused: HashMap<u64, f32> = HashMap::new();
+
+    match method {
+        FusionMethod::Rrf { k: rank_constant } => {
+            for hits in std::iter::once(vector_hits).chain(other_hits.iter().map(|h| h.as_slice()))
+            {
+                for (rank, (id, _)) in hits.iter().enumerate() {
+                    *fused.entry(*id).or_default() += 1.0 / (rank_constant + rank as f32 + 1.0);
+                }
+            }
+        }
+        FusionMethod::Weighted { vector_weight } => {
+            let weight = vector_weight.clamp(0.0, 1.0);
+            let other_weight = (1.0 - weight) / other_hits.len().max(1) as f32;
+
+            for (id, score) in normalize(vector_hits) {
+                *fused.entry(id).or_default() += weight * score;
+            }
+            for hits in other_hits {
+                for (id, score) in normalize(hits) {
+                    *fused.entry(id).or_default() += other_weight * score;
+                }
+            }
+        }
+    }
+
+    let mut hits = fused.into_iter().collect::<Vec<_>>();
+    hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    hits.truncate(k);
+
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(hits: &[(u64, f32)]) -> Vec<u64> {
+        hits.iter().map(|(id, _)| *id).collect()
+    }
+
+    #[test]
+    fn test_rrf() {
+        let vector_hits = [(1, -0.1), (2, -0.2), (3, -0.3)];
+        let text_hits = vec![(3, 5.0), (2, 4.0), (4, 1.0)];
+
+        let fused = fuse(&vector_hits, &[text_hits], FusionMethod::default(), 3);
+        // docs found by both sides lead, ties in rank sum favor the best single rank
+        assert_eq!(ids(&fused), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_weighted() {
+        let vector_hits = [(1, 0.9), (2, 0.5), (3, 0.1)];
+        let text_hits = vec![vec![(3, 10.0), (1, 2.0)]];
+
+        let fused = fuse(
+            &vector_hits,
+            &text_hits,
+            FusionMethod::Weighted { vector_weight: 1.0 },
+            10,
+        );
+        assert_eq!(ids(&fused), vec![1, 2, 3]);
+
+        let fused = fuse(
+            &vector_hits,
+            &text_hits,
+            FusionMethod::Weighted { vector_weight: 0.0 },
+            1,
+        );
+        assert_eq!(ids(&fused), vec![3]);
+
+        let fused = fuse(
+            &vector_hits,
+            &text_hits,
+            FusionMethod::Weighted { vector_weight: 0.5 },
+            10,
+        );
+        assert_eq!(ids(&fused), vec![1, 3, 2]);
+
+        // the other rankings share the weight left by the vector side
+        let other_hits = vec![text_hits[0].clone(), vec![(2, 3.0), (3, 1.0)]];
+        let fused = fuse(
+            &vector_hits,
+            &other_hits,
+            FusionMethod::Weighted { vector_weight: 0.0 },
+            10,
+        );
+        assert_eq!(ids(&fused), vec![2, 3, 1]);
+    }
+}
@@ -0,0 +1,27 @@
+mod bm25;
+mod fusion;
+
+pub use bm25::{Bm25Index, Bm25Params};
+pub use fusion::{fuse, FusionMethod};
+
+/// Splits text into lowercase alphanumeric terms.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Hello, World! rust-lang 2024"),
+            vec!["hello", "world", "rust", "lang", "2024"]
+        );
+        assert!(tokenize("  ,.; ").is_empty());
+    }
+}
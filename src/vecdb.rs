@@ -1,18 +1,23 @@
 use ndarray::Array;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
 use std::vec;
+use tokio::sync::Semaphore;
 use tokio::task;
 
+use roaring::RoaringBitmap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use tracing::{event, Level};
 
 use crate::filter::{IdFilter, IntFilterIndex, IntFilterInput};
 use crate::merror::DBError;
+use crate::metrics;
 use crate::persistence::{apply_wal_record, Persistence};
-use crate::scalar::{new_scalar_storage, ScalarStorage};
+use crate::scalar::{new_scalar_storage, ScalarStorage, VectorIter};
+use crate::text::{fuse, Bm25Index, Bm25Params, FusionMethod};
 use crate::{index::*, scalar};
 
 pub type DocMap = HashMap<String, Value>;
@@ -21,15 +26,43 @@ const SCALAR_DB_FILE_SUFFIX: &str = "scalar.db";
 const INDEX_FILE_SUFFIX: &str = "index.bin";
 const FILTER_FILE_SUFFIX: &str = "filter.bin";
 const WAL_FILE_SUFFIX: &str = "vdb.log";
+// directory the indexes are rebuilt in, before their files are moved over the live ones
+const REBUILD_DIR: &str = "rebuild";
+const REBUILD_BATCH_SIZE: usize = 1024;
+// nearest token vectors fetched per query vector and per requested result
+const MULTI_VECTOR_CANDIDATE_FACTOR: usize = 4;
+// candidates re-ranked per requested result when the MMR pool size is not given
+const MMR_CANDIDATE_FACTOR: usize = 4;
+// filters letting through less than this share of the docs are searched by exact scan
+const DEFAULT_BRUTE_FORCE_SELECTIVITY: f32 = 0.05;
+// defaults of the ef_search tuning
+const DEFAULT_TUNE_RECALL_TARGET: f32 = 0.95;
+const DEFAULT_TUNE_SAMPLE_SIZE: usize = 100;
+const MAX_TUNE_EF_SEARCH: u32 = 4096;
+// values returned per facet field when no limit is given
+const DEFAULT_FACET_LIMIT: usize = 10;
+// grouped searches stop over-fetching once this many hits per requested one are ranked
+const GROUP_BY_MAX_FETCH_FACTOR: usize = 16;
+// shadow scans running at once, the samples beyond are skipped
+const MAX_SHADOW_SCANS: usize = 2;
 
 pub struct VectorDatabase {
     params: DatabaseParams,
+    db_path: PathBuf,
 
     scalar_storage: Arc<dyn ScalarStorage>,
     vector_index: Arc<Mutex<dyn Index + Send>>,
+    sparse_index: Arc<Mutex<dyn Index + Send>>,
+    named_indexes: HashMap<String, Arc<Mutex<dyn Index + Send>>>,
+    multi_vector_maps: HashMap<String, RwLock<MultiVectorMap>>,
     filter_index: RwLock<IntFilterIndex>,
+    text_index: RwLock<Bm25Index>,
 
     persistence: Arc<Persistence>,
+    shadow_recall: Arc<ShadowRecall>,
+    shadow_scans: Arc<Semaphore>,
+    // deleted docs still held by indexes that cannot remove vectors, kept out of their results
+    deleted_ids: RwLock<RoaringBitmap>,
 }
 
 unsafe impl Sync for VectorDatabase {}
@@ -40,9 +73,39 @@ pub struct DatabaseParams {
     pub metric_type: MetricType,
     pub index_type: IndexType,
     pub hnsw_params: Option<HnswIndexOption>,
+    pub vamana_params: Option<VamanaIndexOption>,
+    // doc fields indexed for keyword search, string or array of strings values
+    pub text_fields: Option<Vec<String>>,
+    pub bm25_params: Option<Bm25Params>,
+    // named vector fields stored next to the default one described above
+    pub vector_fields: Option<Vec<VectorFieldParams>>,
+    // share of the docs under which a filtered search scans the stored vectors of
+    // the allowed ids instead of the index, which may miss hits for selective filters
+    pub brute_force_selectivity: Option<f32>,
+    // share of the approximate dense searches compared in the background with an
+    // exact scan of the stored vectors to measure the recall of the index
+    pub shadow_sample_rate: Option<f32>,
     pub version: String,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VectorFieldParams {
+    pub name: String,
+    pub dim: u32,
+    pub metric_type: MetricType,
+    pub index_type: IndexType,
+    pub hnsw_params: Option<HnswIndexOption>,
+    pub vamana_params: Option<VamanaIndexOption>,
+    // every doc owns a variable number of vectors, indexed one by one and scored by MaxSim
+    pub multi_vector: Option<bool>,
+}
+
+impl VectorFieldParams {
+    pub fn is_multi_vector(&self) -> bool {
+        self.multi_vector.unwrap_or(false)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct VdbUpsertArgs {
     pub vectors: VectorArgs,
@@ -57,18 +120,234 @@ pub struct VectorArgs {
     pub flat_data: Vec<f32>,
     pub data_row: usize,
     pub data_dim: usize,
+    // optional sparse vector of each row, searched through its own inverted index
+    pub sparse_data: Option<Vec<SparseVector>>,
+    // flat data of named vector fields, any subset of the schema. The default
+    // field may be skipped by leaving `flat_data` empty when this is given
+    pub named_data: Option<HashMap<String, Vec<f32>>>,
+    // flat vectors of each row for multi-vector fields, rows may hold any number of vectors
+    pub multi_data: Option<HashMap<String, Vec<Vec<f32>>>>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct VdbSearchArgs {
     pub query: Vec<f32>,
     pub k: usize,
     pub filter_inputs: Option<Vec<IntFilterInput>>,
 
     pub hnsw_params: Option<HnswSearchOption>,
+    pub vamana_params: Option<VamanaSearchOption>,
+    // return the stored raw vector of each hit under the "vector" key
+    pub with_vectors: Option<bool>,
+
+    // keyword query over the text fields, its BM25 ranking is fused with the
+    // vector ranking and the fused score is returned under the "score" key.
+    // `query` may be left empty to search without the dense vector
+    pub text_query: Option<String>,
+    // sparse query vector, its dot product ranking is fused the same way
+    pub sparse_query: Option<SparseVector>,
+    // named vector field `query` is searched against, the default field when empty
+    pub vector_field: Option<String>,
+    // query vectors of other named fields, their rankings are fused as well
+    pub named_queries: Option<HashMap<String, Vec<f32>>>,
+    // flat query vectors of multi-vector fields, candidates are re-scored by exact MaxSim
+    pub multi_queries: Option<HashMap<String, Vec<f32>>>,
+    pub fusion: Option<FusionMethod>,
+    // re-ranks the top hits for diversity using the stored vectors of `vector_field`
+    pub mmr: Option<MmrParams>,
+    // groups the hits by an attribute, `k` is ignored in favour of the group limits
+    pub group_by: Option<GroupByParams>,
+
+    // number of top hits skipped before the returned page
+    pub offset: Option<usize>,
+    // `next_cursor` of the previous page, the page starts right after its last hit
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SearchPage {
+    pub results: Vec<DocMap>,
+    // set when the page is full, passed as `cursor` to fetch the next page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GroupByParams {
+    // integer attribute the hits are grouped by, hits without it are skipped
+    pub field: String,
+    // max number of hits returned per group
+    pub group_size: usize,
+    // max number of groups returned
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SearchGroup {
+    pub group: i64,
+    // score of the best hit in the group
+    pub score: f32,
+    pub hits: Vec<DocMap>,
+}
+
+// Final ranking of a query, best first
+struct RankedHits {
+    labels: Vec<u64>,
+    scores: Vec<f32>,
+    // plain dense searches do not report their scores under the "score" key
+    with_scores: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecommendStrategy {
+    // searches once with `avg(positive) + (avg(positive) - avg(negative))`
+    #[default]
+    AverageVector,
+    // searches around every positive example and ranks the candidates by their
+    // best positive similarity minus their best negative similarity
+    BestScore,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct VdbRecommendArgs {
+    // ids of stored docs the results should look like
+    pub positive: Vec<u64>,
+    // ids of stored docs the results should not look like
+    pub negative: Option<Vec<u64>>,
+    pub k: usize,
+    pub strategy: Option<RecommendStrategy>,
+    // named vector field the examples are compared on, the default field when empty
+    pub vector_field: Option<String>,
+    pub filter_inputs: Option<Vec<IntFilterInput>>,
+
+    pub hnsw_params: Option<HnswSearchOption>,
+    pub vamana_params: Option<VamanaSearchOption>,
+    pub with_vectors: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct VdbScrollArgs {
+    // max number of docs per page
+    pub limit: usize,
+    // `next_cursor` of the previous page, the first page when empty
+    pub cursor: Option<u64>,
+    pub filter_inputs: Option<Vec<IntFilterInput>>,
+    pub with_vectors: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScrollPage {
+    pub results: Vec<DocMap>,
+    // set while more docs may follow, passed as `cursor` to fetch the next page
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct VdbCountArgs {
+    pub filter_inputs: Option<Vec<IntFilterInput>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CountResult {
+    // docs matching the filters, all docs when no filter is given
+    pub count: u64,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct VdbFacetArgs {
+    // integer attributes whose values are counted
+    pub fields: Vec<String>,
+    pub filter_inputs: Option<Vec<IntFilterInput>>,
+    // max number of values returned per field, the most frequent ones first
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct FacetCount {
+    pub value: i64,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct VdbTuneArgs {
+    // number of results the recall is measured at
+    pub k: usize,
+    pub recall_target: Option<f32>,
+    // number of stored vectors used as queries
+    pub sample_size: Option<usize>,
+    // named vector field whose HNSW index is tuned, the default field when empty
+    pub vector_field: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TuneResult {
+    pub ef_search: u32,
+    // recall measured with `ef_search`, below the target if it was never reached
+    pub recall: f32,
+}
+
+// Counters of the shadow comparisons of live searches with exact scans
+#[derive(Debug, Default)]
+struct ShadowRecall {
+    // approximate dense searches seen, sampled or not
+    searches: AtomicU64,
+    samples: AtomicU64,
+    // exact top k hits also returned by the index, out of all exact top k hits
+    found: AtomicU64,
+    expected: AtomicU64,
+}
+
+impl ShadowRecall {
+    // Spreads the samples evenly instead of drawing them at random
+    fn sample(&self, rate: f32) -> bool {
+        let rate = rate.clamp(0.0, 1.0) as f64;
+        let n = self.searches.fetch_add(1, Ordering::Relaxed) as f64;
+
+        ((n + 1.0) * rate).floor() > (n * rate).floor()
+    }
+
+    fn record(&self, found: usize, expected: usize) {
+        self.samples.fetch_add(1, Ordering::Relaxed);
+        self.found.fetch_add(found as u64, Ordering::Relaxed);
+        self.expected.fetch_add(expected as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ShadowRecallStats {
+    pub samples: u64,
+    pub found: u64,
+    pub expected: u64,
+    // found / expected since startup, none before the first non-empty sample
+    pub recall: Option<f32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DatabaseStats {
+    pub doc_count: u64,
+    pub params: DatabaseParams,
+    // raw vectors held in memory by the non-persistent dense indexes, graph links
+    // and multi-vector fields left out
+    pub memory_estimate_bytes: u64,
+    // id of the last record written to the WAL
+    pub wal_log_id: u64,
+    pub storage: BTreeMap<String, u64>,
+    pub shadow_recall: ShadowRecallStats,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct VdbRebuildArgs {
+    pub index_type: Option<IndexType>,
+    pub metric_type: Option<MetricType>,
+    pub hnsw_params: Option<HnswIndexOption>,
+    pub vamana_params: Option<VamanaIndexOption>,
 }
 
-fn new_index(index_params: DatabaseParams) -> Result<Arc<Mutex<dyn Index + Send>>, DBError> {
+pub fn new_index(
+    index_params: DatabaseParams,
+    index_path: &Path,
+) -> Result<Arc<Mutex<dyn Index + Send>>, DBError> {
     let index: Arc<Mutex<dyn Index + Send>> = match index_params.index_type {
         IndexType::Flat => {
             // Create a flat index
@@ -89,665 +368,4863 @@ fn new_index(index_params: DatabaseParams) -> Result<Arc<Mutex<dyn Index + Send>
                 .map_err(|e| DBError::CreateError(format!("unable to create vector index: {e}")))?,
             ))
         }
+        IndexType::MmapFlat => {
+            // Create a flat index backed by a memory-mapped file
+            Arc::new(Mutex::new(
+                MmapFlatIndex::open(index_path, index_params.dim, index_params.metric_type)
+                    .map_err(|e| {
+                        DBError::CreateError(format!("unable to create vector index: {e}"))
+                    })?,
+            ))
+        }
+        IndexType::Vamana => {
+            // Open the on-disk graph, an empty one is created on first use
+            Arc::new(Mutex::new(
+                VamanaIndex::open_or_create(
+                    index_path,
+                    index_params.dim,
+                    index_params.metric_type,
+                    index_params.vamana_params,
+                )
+                .map_err(|e| DBError::CreateError(format!("unable to create vector index: {e}")))?,
+            ))
+        }
     };
 
     Ok(index)
 }
 
-impl VectorDatabase {
-    pub fn new<D: AsRef<Path>>(db_path: D, db_params: DatabaseParams) -> Result<Self, DBError> {
-        let db_params_copy = db_params.clone();
+// Feeds the raw vectors read from the scalar storage into the given index,
+// returns the number of vectors inserted
+fn load_index_from_storage(
+    index: &Mutex<dyn Index + Send>,
+    vectors: VectorIter<'_>,
+    dim: usize,
+) -> Result<usize, DBError> {
+    let mut index_guard = index
+        .lock()
+        .map_err(|e| DBError::CreateError(format!("failed to lock vector index: {e}")))?;
 
-        let scalar_db_path = PathBuf::new().join(&db_path).join(SCALAR_DB_FILE_SUFFIX);
-        let scalar_storage = Arc::new(new_scalar_storage(scalar_db_path)?);
-        let vector_index: Arc<Mutex<dyn Index + Send>> = new_index(db_params)?;
-        let filter_index = RwLock::new(IntFilterIndex::new());
+    let mut total = 0;
+    let mut labels: Vec<u64> = Vec::with_capacity(REBUILD_BATCH_SIZE);
+    let mut flat_data: Vec<f32> = Vec::with_capacity(REBUILD_BATCH_SIZE * dim);
 
-        let persistence_path = PathBuf::new().join(&db_path).join(WAL_FILE_SUFFIX);
-        let persistence_path_str = persistence_path.to_str().ok_or(DBError::CreateError(
-            "Failed to convert persistence path to str".into(),
-        ))?;
-        let persistence = Arc::new(
-            Persistence::new(
-                persistence_path_str,
-                &db_params_copy.version,
-                scalar_storage.as_ref(),
-            )
-            .map_err(|e| {
-                DBError::CreateError(format!("unable to create persistence layer: {e}"))
-            })?,
-        );
+    let mut flush = |labels: &mut Vec<u64>, flat_data: &mut Vec<f32>| -> Result<(), DBError> {
+        if labels.is_empty() {
+            return Ok(());
+        }
 
-        Ok(Self {
-            params: db_params_copy,
-            scalar_storage,
-            vector_index,
-            filter_index,
-            persistence,
-        })
-    }
+        let data =
+            Array::from_shape_vec((labels.len(), dim), std::mem::take(flat_data)).map_err(|e| {
+                DBError::CreateError(format!("unable to create array from stored vectors: {e}"))
+            })?;
 
-    pub async fn upsert(&mut self, args: VdbUpsertArgs) -> Result<(), DBError> {
-        let (mismatch_field, mismatch_value, expect_value) = args.validate();
+        index_guard
+            .insert(&InsertParams::new(&data, labels))
+            .map_err(|e| DBError::CreateError(format!("unable to rebuild vector index: {e}")))?;
 
-        if !mismatch_field.is_empty() {
-            event!(
-                Level::ERROR,
-                "unexpected length of field {}: {}, expected length is {}",
-                mismatch_field,
-                mismatch_value,
-                expect_value,
-            );
+        total += labels.len();
+        labels.clear();
 
-            return Err(DBError::PutError(format!(
-                "unexpected length of field {mismatch_field}: {mismatch_value}, expected length is {expect_value}",
+        Ok(())
+    };
+
+    for item in vectors {
+        let (id, vector) = item?;
+
+        if vector.len() != dim {
+            return Err(DBError::CreateError(format!(
+                "stored vector {id} has dimension {}, expected {dim}",
+                vector.len()
             )));
         }
 
-        let ids: Arc<Vec<u64>> = Arc::new(
-            self.scalar_storage
-                .gen_incr_ids(scalar::NAMESPACE_DOCS, args.vectors.data_row)?,
-        );
-
-        event!(Level::DEBUG, "upsert vector data with ids: {:?}", ids);
+        labels.push(id);
+        flat_data.extend_from_slice(&vector);
 
-        // process attributes
-        let mut attributes: Arc<Vec<HashMap<String, Value>>> = Arc::new(
-            args.attributes
-                .into_iter()
-                .map(|e| match e {
-                    Some(attr) => attr,
-                    None => HashMap::new(),
-                })
-                .collect::<Vec<_>>(),
-        );
-        if attributes.is_empty() {
-            attributes = Arc::new(vec![HashMap::new(); args.vectors.data_row]);
+        if labels.len() >= REBUILD_BATCH_SIZE {
+            flush(&mut labels, &mut flat_data)?;
         }
+    }
+    flush(&mut labels, &mut flat_data)?;
 
-        for ((i, doc), attr) in args.docs.iter().enumerate().zip(attributes.iter()) {
-            let mut doc_map = match doc {
-                Some(m) => m.clone().to_owned(),
-                None => HashMap::new(),
-            };
+    Ok(total)
+}
 
-            self.insert_doc(&mut doc_map, &attr, ids[i]).await?;
+// Feeds every sparse vector kept in the scalar storage into the sparse index
+fn load_sparse_index_from_storage(
+    index: &Mutex<dyn Index + Send>,
+    scalar_storage: &dyn ScalarStorage,
+) -> Result<usize, DBError> {
+    let mut index_guard = index
+        .lock()
+        .map_err(|e| DBError::CreateError(format!("failed to lock sparse index: {e}")))?;
 
-            if !attr.is_empty() {
-                self.insert_attribute(&attr, ids[i]).await?;
-            }
+    let empty = Array::zeros((0, 0));
+    let mut total = 0;
+    let mut labels: Vec<u64> = Vec::with_capacity(REBUILD_BATCH_SIZE);
+    let mut sparse_data: Vec<SparseVector> = Vec::with_capacity(REBUILD_BATCH_SIZE);
+
+    let mut iter = scalar_storage.sparse_vector_iter().peekable();
+    while iter.peek().is_some() {
+        for item in iter.by_ref().take(REBUILD_BATCH_SIZE) {
+            let (id, vector) = item?;
+            labels.push(id);
+            sparse_data.push(vector);
         }
 
-        if let Err(e) = self
-            .insert_vectors(ids.as_ref().clone(), &args.vectors, args.hnsw_params)
-            .await
-        {
-            event!(Level::ERROR, "Failed to insert vectors: {e}");
+        index_guard
+            .insert(&InsertParams::new(&empty, &labels).with_sparse(&sparse_data))
+            .map_err(|e| DBError::CreateError(format!("unable to rebuild sparse index: {e}")))?;
 
-            self.revert_attributes(&attributes, &ids);
+        total += labels.len();
+        labels.clear();
+        sparse_data.clear();
+    }
 
-            return Err(e);
-        }
+    Ok(total)
+}
 
-        Ok(())
+// Builds a brand new index at `index_path` out of the given stored vectors,
+// returns the index and the number of vectors it holds
+fn build_index_from_storage(
+    index_params: DatabaseParams,
+    index_path: &Path,
+    vectors: VectorIter<'_>,
+) -> Result<(Arc<Mutex<dyn Index + Send>>, usize), DBError> {
+    let dim = index_params.dim as usize;
+
+    if index_params.index_type != IndexType::Vamana {
+        let index = new_index(index_params, index_path)?;
+        let total = load_index_from_storage(index.as_ref(), vectors, dim)?;
+
+        return Ok((index, total));
     }
 
-    async fn insert_vectors(
-        &mut self,
-        ids: Vec<u64>,
-        args: &VectorArgs,
-        hnsw_params: Option<HnswParams>,
-    ) -> Result<(), DBError> {
-        let insert_data =
-            Array::from_shape_vec((args.data_row, args.data_dim), args.flat_data.clone()).map_err(
-                |e| DBError::PutError(format!("unable to create array from flat data: {e}")),
-            )?;
+    // the vamana graph is built in one go out of every vector, streamed to disk first
+    let index = VamanaIndex::build_from_iter(
+        index_path,
+        index_params.dim,
+        index_params.metric_type,
+        index_params.vamana_params,
+        vectors,
+    )
+    .map_err(|e| DBError::CreateError(format!("unable to build vector index: {e}")))?;
+    let total = index.count();
 
-        let vector_index_writer = Arc::clone(&self.vector_index);
+    Ok((Arc::new(Mutex::new(index)), total))
+}
 
-        let res_async_insert = task::spawn_blocking(move || {
-            let index_insert_params = InsertParams {
-                data: &insert_data,
-                labels: &ids,
-                hnsw_params,
+// Splits the stored vectors of a multi-vector field into its token vectors, labelled
+// with the token ids recorded in `multi_vector_map`
+fn token_vector_iter<'a>(
+    scalar_storage: &'a dyn ScalarStorage,
+    field: &str,
+    multi_vector_map: &'a MultiVectorMap,
+    dim: usize,
+) -> VectorIter<'a> {
+    Box::new(scalar_storage.field_vector_iter(field).flat_map(
+        move |item| -> Vec<Result<(u64, Vec<f32>), DBError>> {
+            let (id, vectors) = match item {
+                Ok(item) => item,
+                Err(e) => return vec![Err(e)],
             };
+            let tokens = multi_vector_map.tokens_of(id);
 
-            vector_index_writer
-                .lock()
-                .unwrap()
-                .insert(&index_insert_params)
-                .map_err(|e| DBError::PutError(format!("unable to upsert vector data: {e}")))
-        })
-        .await;
+            if tokens.len() * dim != vectors.len() {
+                return vec![Err(DBError::CreateError(format!(
+                    "stored vectors of doc {id} do not match its {} token vectors",
+                    tokens.len()
+                )))];
+            }
 
-        res_async_insert.map_err(|e| {
-            DBError::PutError(format!(
-                "error while inserting vector database asynchronously: {e}",
-            ))
-        })??;
+            tokens
+                .iter()
+                .zip(vectors.chunks_exact(dim))
+                .map(|(token, vector)| Ok((*token, vector.to_vec())))
+                .collect()
+        },
+    ))
+}
 
-        Ok(())
+// Number of vectors stored for a field, counting each token of multi-vector fields
+fn stored_vector_count(
+    scalar_storage: &dyn ScalarStorage,
+    field: Option<&str>,
+    is_multi_vector: bool,
+) -> Result<usize, DBError> {
+    match (field, is_multi_vector) {
+        (Some(name), true) => scalar_storage
+            .token_parent_iter(name)
+            .try_fold(0, |count, item| item.map(|_| count + 1)),
+        (Some(name), false) => scalar_storage
+            .field_vector_iter(name)
+            .try_fold(0, |count, item| item.map(|_| count + 1)),
+        (None, _) => scalar_storage
+            .vector_iter()
+            .try_fold(0, |count, item| item.map(|_| count + 1)),
     }
+}
 
-    async fn insert_doc(
-        &mut self,
-        doc: &mut DocMap,
-        attributes: &HashMap<String, Value>,
-        id: u64,
-    ) -> Result<(), DBError> {
-        doc.insert("id".to_string(), Value::Number(id.into()));
-        doc.insert(
-            "attributes".to_string(),
-            serde_json::to_value(attributes).unwrap(),
-        );
+// Name of the index files of a vector field, the default field has no name
+fn index_file_name(field: Option<&str>) -> String {
+    match field {
+        Some(name) => format!("{name}.{INDEX_FILE_SUFFIX}"),
+        None => INDEX_FILE_SUFFIX.to_string(),
+    }
+}
 
-        let doc_bytes = serde_json::to_vec(&doc)
-            .map_err(|e| DBError::PutError(format!("unable to serialize doc data: {e}")))?;
+// Lists the index files in `db_path` whose name starts with `prefix`, together
+// with the rest of their name, e.g. the `.fresh` companion of a vamana index
+fn list_index_files(db_path: &Path, prefix: &str) -> Result<Vec<(PathBuf, String)>, DBError> {
+    let entries = std::fs::read_dir(db_path)
+        .map_err(|e| DBError::CreateError(format!("unable to list index files: {e}")))?;
 
-        let scalar_storage = Arc::clone(&self.scalar_storage);
-        task::spawn_blocking(move || {
-            scalar_storage
-                .put(&id.to_be_bytes(), &doc_bytes)
-                .map_err(|e| DBError::PutError(format!("unable to upsert scalar data: {e}")))?;
-            Ok(())
-        })
-        .await
-        .map_err(|e| {
-            DBError::PutError(format!(
-                "error while inserting scalar data asynchronously: {e}",
-            ))
-        })??;
+    let mut files = vec![];
+    for entry in entries {
+        let entry =
+            entry.map_err(|e| DBError::CreateError(format!("unable to list index files: {e}")))?;
+        let name = entry.file_name().to_string_lossy().to_string();
 
-        Ok(())
+        if let Some(rest) = name.strip_prefix(prefix) {
+            files.push((entry.path(), rest.to_string()));
+        }
     }
 
-    async fn insert_attribute(
-        &mut self,
-        attr: &HashMap<String, Value>,
-        id: u64,
-    ) -> Result<(), DBError> {
-        for (key, value) in attr {
-            match value {
-                Value::Number(num) => {
-                    if let Some(num) = num.as_i64() {
-                        self.filter_index.write().unwrap().upsert(key, num, id);
-                    }
-                }
-                _ => {
-                    return Err(DBError::PutError(format!(
-                        "unsupported attribute type for key {key}: {value:?}",
-                    )));
-                }
-            }
-        }
+    Ok(files)
+}
 
-        Ok(())
-    }
+// Concatenates the configured text fields of a doc, returns None when there is nothing to index
+fn extract_text(doc: &DocMap, text_fields: Option<&[String]>) -> Option<String> {
+    let mut parts: Vec<&str> = vec![];
 
-    fn revert_attributes(&mut self, attrs: &Vec<HashMap<String, Value>>, ids: &Vec<u64>) {
-        for (attr, id) in attrs.iter().zip(ids) {
-            for (key, value) in attr {
-                match value {
-                    Value::Number(num) => {
-                        if let Some(num) = num.as_i64() {
-                            self.filter_index.write().unwrap().remove(key, num, *id);
-                        }
-                    }
-                    _ => {}
-                }
-            }
+    for field in text_fields? {
+        match doc.get(field) {
+            Some(Value::String(s)) => parts.push(s),
+            Some(Value::Array(values)) => parts.extend(values.iter().filter_map(|v| v.as_str())),
+            _ => {}
         }
     }
 
-    pub async fn query(&mut self, search_args: VdbSearchArgs) -> Result<Vec<DocMap>, DBError> {
-        let mut query = SearchQuery::new(search_args.query);
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" "))
+    }
+}
 
-        if query.vector.len() != self.params.dim as usize {
-            return Err(DBError::GetError(format!(
-                "query vector length {} does not match index dimension {}",
-                query.vector.len(),
-                self.params.dim,
-            )));
-        }
+// Attributes feed the integer filter index, other value types are rejected
+pub fn validate_attributes(attributes: &HashMap<String, Value>) -> Result<(), DBError> {
+    match attributes.iter().find(|(_, value)| !value.is_number()) {
+        Some((key, value)) => Err(DBError::ValidationError(format!(
+            "unsupported attribute type for key {key}: {value:?}",
+        ))),
+        None => Ok(()),
+    }
+}
 
-        if search_args.hnsw_params.is_some() {
-            query = query.with(search_args.hnsw_params.as_ref().unwrap());
-        }
+fn is_valid_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
 
-        match &search_args.filter_inputs {
-            Some(filter_inputs) if !filter_inputs.is_empty() => {
-                let mut bitmap = roaring::RoaringBitmap::new();
+impl DatabaseParams {
+    fn vector_field(&self, name: &str) -> Option<&VectorFieldParams> {
+        self.vector_fields
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .find(|f| f.name == name)
+    }
 
-                for filter in search_args.filter_inputs.unwrap() {
-                    bitmap = self.filter_index.read().unwrap().apply(&filter, &bitmap);
-                }
+    fn set_ef_search(&mut self, field: Option<&str>, ef_search: u32) {
+        let hnsw_params = match field {
+            Some(name) => self
+                .vector_fields
+                .iter_mut()
+                .flatten()
+                .find(|f| f.name == name)
+                .map(|f| &mut f.hnsw_params),
+            None => Some(&mut self.hnsw_params),
+        };
 
-                query = query.with(&IdFilter::from(bitmap));
-            }
-            _ => {}
+        if let Some(hnsw_params) = hnsw_params {
+            hnsw_params.get_or_insert_with(Default::default).ef_search = Some(ef_search);
         }
+    }
 
-        let vector_index = Arc::clone(&self.vector_index);
-
-        let search_result = task::spawn_blocking(move || {
-            vector_index
-                .lock()
-                .unwrap()
-                .search(&query, search_args.k)
-                .map_err(|e| DBError::GetError(format!("unable to query vector data: {e}")))
-        })
-        .await
-        .map_err(|e| {
-            DBError::GetError(format!(
-                "error while querying vector database asynchronously: {e}",
-            ))
-        })??;
-
-        event!(Level::DEBUG, "search result inside: {search_result:?}");
-
-        if search_result.labels.is_empty() {
-            return Ok(vec![]);
+    // Applies rebuild args to the default field. An ef_search left out of the HNSW params
+    // keeps the current one
+    fn apply_rebuild_args(&mut self, rebuild_args: VdbRebuildArgs) {
+        if let Some(index_type) = rebuild_args.index_type {
+            self.index_type = index_type;
         }
-
-        #[cfg(debug_assertions)]
-        {
-            use crate::scalar::debug_print_scalar_db;
-
-            event!(
-                Level::DEBUG,
-                "start printing all contents in scalar storage",
-            );
-            debug_print_scalar_db(&*self.scalar_storage)?;
+        if let Some(metric_type) = rebuild_args.metric_type {
+            self.metric_type = metric_type;
+        }
+        if let Some(mut hnsw_params) = rebuild_args.hnsw_params {
+            if hnsw_params.ef_search.is_none() {
+                hnsw_params.ef_search = self.hnsw_params.as_ref().and_then(|p| p.ef_search);
+            }
+            self.hnsw_params = Some(hnsw_params);
+        }
+        if rebuild_args.vamana_params.is_some() {
+            self.vamana_params = rebuild_args.vamana_params;
         }
+    }
+
+    // Applies the default index params of the last rebuild, the index files on disk
+    // were built with them rather than with the configured ones
+    fn load_rebuild_params(&mut self, scalar_storage: &dyn ScalarStorage) -> Result<(), DBError> {
+        let Some(bytes) = scalar_storage.get(&scalar::rebuild_params_key())? else {
+            return Ok(());
+        };
+        let rebuild_args = serde_json::from_slice(&bytes)
+            .map_err(|e| DBError::GetError(format!("unable to decode rebuild params: {e}")))?;
 
-        let documents = self.scalar_storage.multi_get_value(&search_result.labels)?;
+        self.apply_rebuild_args(rebuild_args);
 
-        Ok(documents)
+        Ok(())
     }
 
-    pub async fn recover_database(&mut self) -> Result<(), DBError> {
-        event!(
-            Level::INFO,
-            "Recovering vector database from saved files..."
+    // Applies the ef_search values tuned by `tune_ef_search` and kept in the storage
+    fn load_tuned_ef_search(&mut self, scalar_storage: &dyn ScalarStorage) -> Result<(), DBError> {
+        let mut fields = vec![None];
+        fields.extend(
+            self.vector_fields
+                .iter()
+                .flatten()
+                .map(|f| Some(f.name.clone())),
         );
 
-        let wal_record_iter = self
-            .persistence
-            .get_wal_iterator()
-            .await
-            .map_err(|e| DBError::CreateError(format!("Failed to get WAL iterator: {e}")))?;
-        for record in wal_record_iter {
-            match record {
-                Ok(record) => {
-                    // Apply the wal record to the database
-                    apply_wal_record(record, self);
-                }
-                Err(e) => {
-                    event!(Level::ERROR, "Failed to read WAL record: {e}");
+        for field in fields {
+            let Some(bytes) = scalar_storage.get(&scalar::ef_search_key(field.as_deref()))? else {
+                continue;
+            };
+            let bytes: [u8; 4] = bytes
+                .try_into()
+                .map_err(|_| DBError::GetError("stored ef_search is not a u32".to_string()))?;
 
-                    return Err(DBError::CreateError(format!(
-                        "Failed to read WAL record: {e}"
-                    )));
-                }
-            }
+            self.set_ef_search(field.as_deref(), u32::from_be_bytes(bytes));
         }
 
         Ok(())
     }
-}
 
-impl VdbUpsertArgs {
-    fn validate(&self) -> (&str, usize, usize) {
-        if self.docs.len() != self.vectors.data_row {
-            return ("docs", self.docs.len(), self.vectors.data_row);
-        }
-
-        if !self.attributes.is_empty() && self.attributes.len() != self.vectors.data_row {
-            return ("attributes", self.attributes.len(), self.vectors.data_row);
-        }
-
-        if self.vectors.data_dim * self.vectors.data_row != self.vectors.flat_data.len() {
-            return (
-                "flat_data",
-                self.vectors.flat_data.len(),
-                self.vectors.data_dim * self.vectors.data_row,
-            );
+    // index params of a named vector field, the rest is inherited from the collection
+    fn for_field(&self, field: &VectorFieldParams) -> DatabaseParams {
+        DatabaseParams {
+            dim: field.dim,
+            metric_type: field.metric_type.clone(),
+            index_type: field.index_type.clone(),
+            hnsw_params: field.hnsw_params.clone(),
+            vamana_params: field.vamana_params.clone(),
+            vector_fields: None,
+            ..self.clone()
         }
-
-        ("", 0, 0)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::filter::FilterOp;
-    use ndarray::array;
-    use std::sync::Once;
-    use std::time::SystemTime;
-    use std::{fs, time::UNIX_EPOCH};
-    use tracing::span;
-    use tracing_subscriber::fmt;
-    use uuid::Uuid;
+fn dense_query(
+    search_args: &VdbSearchArgs,
+    params: &DatabaseParams,
+    vector: Vec<f32>,
+    allowed_ids: Option<&RoaringBitmap>,
+) -> SearchQuery {
+    let mut query = SearchQuery::new(vector);
 
-    fn create_test_index_params(metric_type: MetricType, index_type: IndexType) -> DatabaseParams {
-        DatabaseParams {
-            dim: 3,
-            metric_type,
-            index_type,
-            hnsw_params: None,
-            version: "0.1.0".to_string(),
-        }
-    }
+    // the collection default may have been tuned after the index was built
+    let default_ef_search = params.hnsw_params.as_ref().and_then(|p| p.ef_search);
 
-    struct TestPath {
-        db_path: PathBuf,
+    if let Some(hnsw_params) = &search_args.hnsw_params {
+        query = query.with(hnsw_params);
+    } else if let Some(ef_search) = default_ef_search {
+        query = query.with(&HnswSearchOption { ef_search });
     }
 
-    impl TestPath {
-        fn new() -> Self {
-            let db_path = PathBuf::from("/tmp/test_db")
-                .join(Uuid::new_v4().to_string())
-                .join(
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs()
-                        .to_string(),
-                );
-            fs::create_dir_all(&db_path).unwrap();
-            TestPath { db_path }
-        }
+    if let Some(vamana_params) = &search_args.vamana_params {
+        query = query.with(vamana_params);
     }
 
-    impl AsRef<Path> for TestPath {
-        fn as_ref(&self) -> &Path {
-            self.db_path.as_ref()
-        }
+    if let Some(bitmap) = allowed_ids {
+        query = query.with(&IdFilter::from(bitmap.clone()));
     }
 
-    impl Drop for TestPath {
-        fn drop(&mut self) {
-            if fs::exists(&self.db_path).unwrap() {
-                std::fs::remove_dir_all(&self.db_path).unwrap();
-            }
+    query
+}
+
+fn average_vector(vectors: &[Vec<f32>], dim: usize) -> Vec<f32> {
+    let mut average = vec![0.0; dim];
+
+    for vector in vectors {
+        for (a, v) in average.iter_mut().zip(vector) {
+            *a += v;
         }
     }
 
-    fn standardize_vecs(
-        matrix: &Array<f32, ndarray::Dim<[usize; 2]>>,
-    ) -> Array<f32, ndarray::Dim<[usize; 2]>> {
-        let mut result = matrix.clone();
+    average.iter_mut().for_each(|a| *a /= vectors.len() as f32);
 
-        for i in 0..matrix.shape()[0] {
-            let row = matrix.row(i);
-            let norm = row.dot(&row).sqrt();
+    average
+}
 
-            result.row_mut(i).assign(&row.map(|x| x / norm));
-        }
+// Hits are ordered by descending score then ascending id, a cursor holds the
+// score and id of the last hit of a page
+fn encode_cursor(score: f32, id: u64) -> String {
+    format!("{:08x}{:016x}", score.to_bits(), id)
+}
 
-        result
+fn decode_cursor(cursor: &str) -> Result<(f32, u64), DBError> {
+    let invalid = || DBError::ValidationError(format!("invalid search cursor: {cursor}"));
+
+    if cursor.len() != 24 || !cursor.is_ascii() {
+        return Err(invalid());
     }
 
-    static INIT: Once = Once::new();
+    let score = u32::from_str_radix(&cursor[..8], 16).map_err(|_| invalid())?;
+    let id = u64::from_str_radix(&cursor[8..], 16).map_err(|_| invalid())?;
 
-    fn init_tracing(test_name: &str) -> tracing::Span {
-        INIT.call_once(|| {
-            fmt::Subscriber::builder()
-                .with_max_level(Level::DEBUG)
-                .with_span_events(fmt::format::FmtSpan::ENTER | fmt::format::FmtSpan::CLOSE)
-                .with_line_number(true)
-                .init();
-        });
+    Ok((f32::from_bits(score), id))
+}
 
-        span!(Level::DEBUG, "tracing for test", test_name)
-    }
+fn is_after_cursor((score, id): (f32, u64), (cursor_score, cursor_id): (f32, u64)) -> bool {
+    score
+        .total_cmp(&cursor_score)
+        .reverse()
+        .then(id.cmp(&cursor_id))
+        .is_gt()
+}
 
-    macro_rules! vecdb_test_cases {
-        ($($name:ident: $index_type:expr, $metric_type: expr)*) => {
-        $(
-            mod $name {
-                use super::*;
+// Turns a distance reported by the vector index into the score `similarity` gives the
+// same vectors, so that hits of the index and of exact scans compare
+fn vector_score(params: &DatabaseParams, distance: f32) -> f32 {
+    match (&params.metric_type, &params.index_type) {
+        // hnsw reports `1 - ip` for the inner product and the euclidean distance, not
+        // its square, for L2
+        (MetricType::IP, IndexType::Hnsw) => 1.0 - distance,
+        (MetricType::L2, IndexType::Hnsw) => -distance * distance,
+        (MetricType::IP, _) => distance,
+        (MetricType::L2, _) => -distance,
+    }
+}
 
-                #[test]
-                fn test_vector_database_new() {
-                    let index_params = create_test_index_params($metric_type, $index_type);
+async fn search_index(
+    index: Arc<Mutex<dyn Index + Send>>,
+    query: SearchQuery,
+    k: usize,
+) -> Result<SearchResult, DBError> {
+    task::spawn_blocking(move || {
+        let mut index = index.lock().unwrap();
+        let _timer = metrics::INDEX_SEARCH_DURATION.start_timer();
 
-                    let result = VectorDatabase::new(TestPath::new(), index_params);
-                    assert!(result.is_ok());
-                }
+        index
+            .search(&query, k)
+            .map_err(|e| DBError::GetError(format!("unable to query vector data: {e}")))
+    })
+    .await
+    .map_err(|e| {
+        DBError::GetError(format!(
+            "error while querying vector database asynchronously: {e}",
+        ))
+    })?
+}
 
-                #[tokio::test]
-                async fn test_vector_database_upsert() {
-                    let span = init_tracing("test_vector_database_upsert");
-                    let _enter = span.enter();
+impl VectorDatabase {
+    pub fn new<D: AsRef<Path>>(db_path: D, mut db_params: DatabaseParams) -> Result<Self, DBError> {
+        let scalar_db_path = PathBuf::new().join(&db_path).join(SCALAR_DB_FILE_SUFFIX);
+        let scalar_storage = Arc::new(new_scalar_storage(scalar_db_path)?);
+        db_params.load_rebuild_params(scalar_storage.as_ref())?;
+        db_params.load_tuned_ef_search(scalar_storage.as_ref())?;
+        let db_params_copy = db_params.clone();
 
-                    let index_params = create_test_index_params($metric_type, $index_type);
-                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+        let index_path = PathBuf::new().join(&db_path).join(index_file_name(None));
+        let vector_index: Arc<Mutex<dyn Index + Send>> = new_index(db_params, &index_path)?;
+        let sparse_index: Arc<Mutex<dyn Index + Send>> = Arc::new(Mutex::new(SparseIndex::new()));
 
-                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3], [0.4, 0.5, 0.6]]);
-                    let doc = HashMap::from([(
-                        "key".to_string(),
-                        Value::String("value".to_string()),
-                    )]);
+        let mut named_indexes = HashMap::new();
+        for field in db_params_copy.vector_fields.as_deref().unwrap_or_default() {
+            if !is_valid_field_name(&field.name) || named_indexes.contains_key(&field.name) {
+                return Err(DBError::CreateError(format!(
+                    "invalid or duplicated vector field name: {:?}",
+                    field.name
+                )));
+            }
 
-                    let result = db.upsert(VdbUpsertArgs{
-                        vectors: VectorArgs {
-                            flat_data: data_array.iter().map(|x| *x).collect(),
-                            data_row: 2,
-                            data_dim: 3,
-                        },
-                        docs: vec![Some(doc.clone()), Some(doc.clone())],
-                        attributes: vec![],
-                        hnsw_params: None,
-                    }).await;
+            let field_index_path = PathBuf::new()
+                .join(&db_path)
+                .join(index_file_name(Some(&field.name)));
+            named_indexes.insert(
+                field.name.clone(),
+                new_index(db_params_copy.for_field(field), &field_index_path)?,
+            );
+        }
 
-                    assert!(result.is_ok());
-                }
+        let multi_vector_maps = db_params_copy
+            .vector_fields
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .filter(|field| field.is_multi_vector())
+            .map(|field| (field.name.clone(), RwLock::new(MultiVectorMap::new())))
+            .collect();
+        let filter_index = RwLock::new(IntFilterIndex::new());
+        let text_index = RwLock::new(Bm25Index::new(
+            db_params_copy.bm25_params.unwrap_or_default(),
+        ));
 
-                #[tokio::test]
-                async fn test_vector_database_query() {
-                    let span = init_tracing("test_vector_database_query");
-                    let _enter = span.enter();
+        let persistence_path = PathBuf::new().join(&db_path).join(WAL_FILE_SUFFIX);
+        let persistence_path_str = persistence_path.to_str().ok_or(DBError::CreateError(
+            "Failed to convert persistence path to str".into(),
+        ))?;
+        let persistence = Arc::new(
+            Persistence::new(
+                persistence_path_str,
+                &db_params_copy.version,
+                scalar_storage.as_ref(),
+            )
+            .map_err(|e| {
+                DBError::CreateError(format!("unable to create persistence layer: {e}"))
+            })?,
+        );
 
-                    let index_params = create_test_index_params($metric_type, $index_type);
-                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+        Ok(Self {
+            params: db_params_copy,
+            db_path: db_path.as_ref().to_path_buf(),
+            scalar_storage,
+            vector_index,
+            sparse_index,
+            named_indexes,
+            multi_vector_maps,
+            filter_index,
+            text_index,
+            persistence,
+            shadow_recall: Arc::new(ShadowRecall::default()),
+            shadow_scans: Arc::new(Semaphore::new(MAX_SHADOW_SCANS)),
+            deleted_ids: RwLock::new(RoaringBitmap::new()),
+        })
+    }
 
-                    let doc1 = HashMap::from([(
-                        "key".to_string(),
-                        Value::String("value1".to_string()),
-                    )]);
+    pub async fn upsert(&mut self, args: VdbUpsertArgs) -> Result<(), DBError> {
+        self.upsert_rows(args, None).await.map(|_| ())
+    }
 
-                    let doc2 = HashMap::from([(
-                        "key".to_string(),
-                        Value::String("value2".to_string()),
-                    )]);
+    // Upserts rows under generated ids like upsert, and returns the ids in row order
+    pub async fn insert(&mut self, args: VdbUpsertArgs) -> Result<Vec<u64>, DBError> {
+        self.upsert_rows(args, None).await
+    }
 
-                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3], [-0.1, 0.2, -0.3]]);
-                    let res = db.upsert(VdbUpsertArgs{
-                        vectors: VectorArgs {
-                           flat_data: data_array.iter().map(|x| *x).collect(),
-                            data_row: 2,
-                            data_dim: 3,
-                        },
-                        docs: vec![Some(doc1.clone()), Some(doc2)],
-                        attributes: vec![],
-                        hnsw_params: None,
-                    }).await;
+    // Upserts rows under the given ids instead of generated ones, for bulk loads of
+    // collections keeping the ids of their source. The ids must be new to the database
+    // and fit in a u32
+    pub async fn import(&mut self, ids: Vec<u64>, args: VdbUpsertArgs) -> Result<(), DBError> {
+        if ids.len() != args.vectors.data_row {
+            return Err(DBError::ValidationError(format!(
+                "unexpected length of field ids: {}, expected length is {}",
+                ids.len(),
+                args.vectors.data_row,
+            )));
+        }
 
-                    assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+        if ids.contains(&0) {
+            return Err(DBError::ValidationError("ids start at 1".to_string()));
+        }
 
-                    let mut search_args =  VdbSearchArgs{
-                        query: vec![0.1, 0.2, 0.3],
-                        k: 10,
-                        filter_inputs: None,
-                        hnsw_params: None,
-                    };
-                    if $index_type == IndexType::Hnsw {
-                        search_args.hnsw_params = Some(HnswSearchOption {
-                            ef_search: 200,
-                        });
-                    }
-                    let result = db.query(search_args).await;
+        // the filter and tombstone bitmaps hold ids as u32
+        if let Some(id) = ids.iter().find(|id| **id > u32::MAX as u64) {
+            return Err(DBError::ValidationError(format!(
+                "id {id} is above the largest id {}",
+                u32::MAX
+            )));
+        }
 
-                    assert!(result.is_ok());
-                    let docs_result = result.unwrap();
+        let unique = ids.iter().collect::<BTreeSet<_>>();
+        if unique.len() != ids.len() {
+            return Err(DBError::ValidationError("ids must be unique".to_string()));
+        }
 
-                    if $index_type == IndexType::Flat {
-                        assert_eq!(docs_result.len(), 2);
-                        assert_eq!(doc1.get("key"), docs_result[0].get("key"))
-                    } else {
-                        // HNSW index does not guarantee the number of results
-                        assert!(!docs_result.is_empty());
-                    }
-                }
+        let deleted_id = {
+            let deleted_ids = self.deleted_ids.read().unwrap();
+            ids.iter()
+                .find(|id| deleted_ids.contains(**id as u32))
+                .copied()
+        };
+        if let Some(id) = deleted_id {
+            return Err(DBError::ConflictError(format!(
+                "id {id} belongs to a deleted doc"
+            )));
+        }
 
-                #[tokio::test]
-                async fn test_vector_database_upsert_with_wrong_dim() {
-                    let index_params = create_test_index_params($metric_type, $index_type);
-                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+        if let Some(doc) = self.get(&ids, false)?.first() {
+            return Err(DBError::ConflictError(format!(
+                "id {} is already stored",
+                doc["id"]
+            )));
+        }
 
-                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3, 0.4], [0.4, 0.5, 0.6, 0.7]]);
-                    let result = db.upsert(VdbUpsertArgs{
-                        vectors: VectorArgs {
-                            flat_data: data_array.iter().map(|x| *x).collect(),
-                            data_row: 2,
-                            data_dim: 4,
-                        },
-                        docs: vec![None, None],
-                        attributes: vec![],
-                        hnsw_params: None,
-                    }).await;
+        self.upsert_rows(args, Some(ids)).await.map(|_| ())
+    }
 
-                    assert!(result.is_err());
+    async fn upsert_rows(
+        &mut self,
+        args: VdbUpsertArgs,
+        ids: Option<Vec<u64>>,
+    ) -> Result<Vec<u64>, DBError> {
+        let _timer = metrics::UPSERT_DURATION.start_timer();
+        let (mismatch_field, mismatch_value, expect_value) = args.validate();
 
-                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3], [0.4, 0.5, 0.6]]);
-                    let result = db.upsert(VdbUpsertArgs{
-                        vectors: VectorArgs {
-                            flat_data: data_array.iter().map(|x| *x).collect(),
-                            data_row: 3,
-                            data_dim: 3,
-                        },
-                        docs: vec![None, None, None],
-                        attributes: vec![],
-                        hnsw_params: None,
-                    }).await;
+        if !mismatch_field.is_empty() {
+            event!(
+                Level::ERROR,
+                "unexpected length of field {}: {}, expected length is {}",
+                mismatch_field,
+                mismatch_value,
+                expect_value,
+            );
 
-                    assert!(result.is_err());
-                }
+            return Err(DBError::ValidationError(format!(
+                "unexpected length of field {mismatch_field}: {mismatch_value}, expected length is {expect_value}",
+            )));
+        }
 
-                #[tokio::test]
-                async fn test_vector_database_query_with_no_results() {
-                    let span = init_tracing("test_vector_database_query_with_no_results");
-                    let _enter = span.enter();
+        let named_data = args.vectors.named_data.clone().unwrap_or_default();
+        let multi_data = args.vectors.multi_data.clone().unwrap_or_default();
+        let with_default_vectors =
+            !args.vectors.flat_data.is_empty() || (named_data.is_empty() && multi_data.is_empty());
 
-                    let index_params = create_test_index_params($metric_type, $index_type);
-                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+        if with_default_vectors && args.vectors.data_dim != self.params.dim as usize {
+            return Err(DBError::ValidationError(format!(
+                "vector dimension {} does not match index dimension {}",
+                args.vectors.data_dim, self.params.dim,
+            )));
+        }
 
-                    let mut search_args = VdbSearchArgs {
-                        query: vec![0.1, 0.2, 0.3],
-                        k: 1,
-                        filter_inputs: None,
-                        hnsw_params: None,
-                    };
-                    if $index_type == IndexType::Hnsw {
-                        search_args.hnsw_params = Some(HnswSearchOption {
-                            ef_search: 200,
-                        });
-                    }
-                    let result = db.query(search_args).await;
+        // every vector field written by this upsert, None being the default field
+        let mut dense_vectors: Vec<(Option<String>, VectorArgs)> = vec![];
+        if with_default_vectors {
+            dense_vectors.push((None, args.vectors.clone()));
+        }
+        for (name, flat_data) in named_data {
+            let field = self
+                .params
+                .vector_field(&name)
+                .ok_or(DBError::NotFoundError(format!(
+                    "unknown vector field: {name}"
+                )))?;
 
-                    assert!(result.is_ok());
-                    assert!(result.unwrap().is_empty());
-                }
+            if field.is_multi_vector() {
+                return Err(DBError::ValidationError(format!(
+                    "vector field {name} holds multiple vectors per row, use multi_data"
+                )));
+            }
 
-                #[tokio::test]
-                async fn test_vector_database_query_with_filter() {
-                    let span = init_tracing("test_vector_database_query_with_filter");
-                    let _enter = span.enter();
+            if flat_data.len() != field.dim as usize * args.vectors.data_row {
+                return Err(DBError::ValidationError(format!(
+                    "unexpected length of vector field {name}: {}, expected length is {}",
+                    flat_data.len(),
+                    field.dim as usize * args.vectors.data_row,
+                )));
+            }
 
-                    let index_params = create_test_index_params($metric_type, $index_type);
-                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+            dense_vectors.push((
+                Some(name),
+                VectorArgs {
+                    flat_data,
+                    data_row: args.vectors.data_row,
+                    data_dim: field.dim as usize,
+                    sparse_data: None,
+                    named_data: None,
+                    multi_data: None,
+                },
+            ));
+        }
 
-                    let doc1 = HashMap::from([(
-                        "key".to_string(),
-                        Value::String("value1".to_string()),
-                    )]);
+        for (name, rows) in &multi_data {
+            let field = self
+                .params
+                .vector_field(name)
+                .ok_or(DBError::NotFoundError(format!(
+                    "unknown vector field: {name}"
+                )))?;
 
-                    let doc2 = HashMap::from([(
-                        "key".to_string(),
-                        Value::String("value2".to_string()),
-                    )]);
+            if !field.is_multi_vector() {
+                return Err(DBError::ValidationError(format!(
+                    "vector field {name} is not a multi-vector field"
+                )));
+            }
 
-                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3], [0.1, -0.2, 0.3]]);
-                    let res = db.upsert(VdbUpsertArgs{
-                        vectors: VectorArgs {
-                            flat_data: data_array.iter().map(|x| *x).collect(),
-                            data_row: 2,
-                            data_dim: 3,
-                        },
-                        docs: vec![Some(doc1.clone()), Some(doc2.clone())],
-                        attributes: vec![
-                            Some(HashMap::from([("age".to_string(), Value::Number(10.into()))])),
-                            Some(HashMap::from([("age".to_string(), Value::Number(20.into()))])),
-                        ],
-                        hnsw_params: None,
-                    }).await;
+            if rows.len() != args.vectors.data_row {
+                return Err(DBError::ValidationError(format!(
+                    "unexpected rows of multi-vector field {name}: {}, expected {}",
+                    rows.len(),
+                    args.vectors.data_row,
+                )));
+            }
 
-                    assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+            if let Some(row) = rows.iter().find(|r| r.len() % field.dim as usize != 0) {
+                return Err(DBError::ValidationError(format!(
+                    "multi-vector row length {} of field {name} is not a multiple of dimension {}",
+                    row.len(),
+                    field.dim,
+                )));
+            }
+        }
 
-                    let mut search_args = VdbSearchArgs {
-                        query: vec![0.1, 0.2, 0.3],
-                        k: 10,
-                        filter_inputs: Some(vec![IntFilterInput {
-                            field: "age".to_string(),
-                            op: FilterOp::Equal,
-                            target: 20,
-                        }]),
-                        hnsw_params: None,
-                    };
+        for attr in args.attributes.iter().flatten() {
+            validate_attributes(attr)?;
+        }
 
-                    if $index_type == IndexType::Hnsw {
-                        search_args.hnsw_params = Some(HnswSearchOption {
-                            ef_search: 200,
-                        });
-                    }
+        let ids: Arc<Vec<u64>> = Arc::new(match ids {
+            Some(ids) => {
+                self.scalar_storage
+                    .reserve_ids(scalar::NAMESPACE_DOCS, &ids)?;
+                ids
+            }
+            None => self
+                .scalar_storage
+                .gen_incr_ids(scalar::NAMESPACE_DOCS, args.vectors.data_row)?,
+        });
 
-                    // without filter, the first doc should be returned
-                    // filter the first doc, then only the second doc should be returned
-                    let result = db.query(search_args.clone()).await;
+        event!(Level::DEBUG, "upsert vector data with ids: {:?}", ids);
 
-                    assert!(result.is_ok());
-                    let docs_result = result.unwrap();
-                    assert_eq!(docs_result.len(), 1);
-                    if $index_type == IndexType::Flat {
-                        assert_eq!(doc2.get("key"), docs_result[0].get("key"));
-                    } else {
-                        // HNSW index does not guarantee the number of results
-                        assert!(!docs_result.is_empty());
-                    }
+        // process attributes
+        let mut attributes: Vec<HashMap<String, Value>> = args
+            .attributes
+            .iter()
+            .map(|e| e.clone().unwrap_or_default())
+            .collect::<Vec<_>>();
+        if attributes.is_empty() {
+            attributes = vec![HashMap::new(); args.vectors.data_row];
+        }
 
-                    // test with not equal filter
-                    search_args.filter_inputs = Some(vec![IntFilterInput {
-                        field: "age".to_string(),
-                        op: FilterOp::NotEqual,
-                        target: 20,
-                    }]);
-                    let result = db.query(search_args).await;
+        // a failed upsert leaves nothing behind, not even the rows written before the failure
+        if let Err(e) = self
+            .write_rows(&args, &ids, &attributes, &dense_vectors)
+            .await
+        {
+            event!(Level::ERROR, "Failed to upsert rows: {e}");
 
-                    assert!(result.is_ok());
-                    let docs_result = result.unwrap();
-                    assert_eq!(docs_result.len(), 1);
-                    if $index_type == IndexType::Flat {
-                        assert_eq!(doc1.get("key"), docs_result[0].get("key"));
-                    } else {
-                        // HNSW index does not guarantee the number of results
-                        assert!(!docs_result.is_empty());
-                    }
+            if let Err(remove_err) = self.remove_docs(&ids, &attributes) {
+                event!(
+                    Level::ERROR,
+                    "Failed to remove the rows of a failed upsert: {remove_err}"
+                );
+            }
+
+            return Err(e);
+        }
+
+        // the rows are only counted once all of them are written
+        self.scalar_storage
+            .incr_count(scalar::NAMESPACE_DOCS, ids.len())?;
+        metrics::UPSERTED_ROWS.inc_by(ids.len() as u64);
+
+        Ok(ids.as_ref().clone())
+    }
+
+    // Writes the docs, attributes and vectors of an upsert under the allocated ids
+    async fn write_rows(
+        &mut self,
+        args: &VdbUpsertArgs,
+        ids: &Arc<Vec<u64>>,
+        attributes: &[HashMap<String, Value>],
+        dense_vectors: &[(Option<String>, VectorArgs)],
+    ) -> Result<(), DBError> {
+        for ((i, doc), attr) in args.docs.iter().enumerate().zip(attributes.iter()) {
+            let mut doc_map = match doc {
+                Some(m) => m.clone().to_owned(),
+                None => HashMap::new(),
+            };
+
+            self.insert_doc(&mut doc_map, attr, ids[i]).await?;
+
+            if !attr.is_empty() {
+                self.insert_attribute(attr, ids[i]);
+            }
+
+            self.insert_text(&doc_map, ids[i]);
+        }
+
+        for (field, vectors) in dense_vectors {
+            self.insert_raw_vectors(Arc::clone(ids), field.as_deref(), vectors)
+                .await?;
+        }
+
+        if let Some(sparse_data) = args.vectors.sparse_data.clone() {
+            self.insert_sparse_vectors(Arc::clone(ids), sparse_data)
+                .await?;
+        }
+
+        for (field, vectors) in dense_vectors {
+            let vector_index = match field {
+                Some(name) => Arc::clone(&self.named_indexes[name]),
+                None => Arc::clone(&self.vector_index),
+            };
+
+            self.insert_vectors(
+                vector_index,
+                ids.as_ref().clone(),
+                vectors,
+                args.hnsw_params.clone(),
+            )
+            .await?;
+        }
+
+        for (name, rows) in args.vectors.multi_data.clone().unwrap_or_default() {
+            self.insert_multi_vectors(Arc::clone(ids), name, rows, args.hnsw_params.clone())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn insert_multi_vectors(
+        &mut self,
+        ids: Arc<Vec<u64>>,
+        field: String,
+        rows: Vec<Vec<f32>>,
+        hnsw_params: Option<HnswParams>,
+    ) -> Result<(), DBError> {
+        let (vector_index, params) = self.field_index(Some(&field))?;
+        let dim = params.dim as usize;
+
+        let row_tokens = rows.iter().map(|row| row.len() / dim).collect::<Vec<_>>();
+        let num_tokens = row_tokens.iter().sum();
+        let tokens = Arc::new(
+            self.scalar_storage
+                .gen_incr_ids(&scalar::token_id_namespace(&field), num_tokens)?,
+        );
+
+        // the doc keeps all its vectors under one key, MaxSim reads them back in one go
+        let scalar_storage = Arc::clone(&self.scalar_storage);
+        let (store_ids, store_tokens, store_field) =
+            (Arc::clone(&ids), Arc::clone(&tokens), field.clone());
+        let flat_data = task::spawn_blocking(move || {
+            let mut token_iter = store_tokens.iter();
+            for (id, row) in store_ids.iter().zip(&rows) {
+                scalar_storage
+                    .put_field_vector(&store_field, *id, row)
+                    .map_err(|e| {
+                        DBError::PutError(format!("unable to store raw vector data: {e}"))
+                    })?;
+
+                for token in token_iter.by_ref().take(row.len() / dim) {
+                    scalar_storage
+                        .put_token_parent(&store_field, *token, *id)
+                        .map_err(|e| {
+                            DBError::PutError(format!("unable to store multi-vector mapping: {e}"))
+                        })?;
                 }
             }
-        )*
+            Ok::<_, DBError>(rows.concat())
+        })
+        .await
+        .map_err(|e| {
+            DBError::PutError(format!(
+                "error while storing multi-vectors asynchronously: {e}",
+            ))
+        })??;
+
+        {
+            let mut multi_vector_map = self.multi_vector_maps[&field].write().unwrap();
+            let mut token_iter = tokens.iter();
+            for (id, count) in ids.iter().zip(row_tokens) {
+                for token in token_iter.by_ref().take(count) {
+                    multi_vector_map.insert(*id, *token);
+                }
+            }
+        }
+
+        if num_tokens == 0 {
+            return Ok(());
+        }
+
+        let vectors = VectorArgs {
+            flat_data,
+            data_row: num_tokens,
+            data_dim: dim,
+            sparse_data: None,
+            named_data: None,
+            multi_data: None,
         };
+
+        self.insert_vectors(vector_index, tokens.as_ref().clone(), &vectors, hnsw_params)
+            .await
+    }
+
+    fn revert_upsert(&mut self, attrs: &[HashMap<String, Value>], ids: &[u64]) {
+        self.revert_attributes(attrs, ids);
+        self.revert_text(ids);
+        self.sparse_index.lock().unwrap().remove(ids).ok();
+
+        for multi_vector_map in self.multi_vector_maps.values() {
+            let mut multi_vector_map = multi_vector_map.write().unwrap();
+            for id in ids {
+                multi_vector_map.remove(*id);
+            }
+        }
+    }
+
+    async fn insert_vectors(
+        &mut self,
+        vector_index_writer: Arc<Mutex<dyn Index + Send>>,
+        ids: Vec<u64>,
+        args: &VectorArgs,
+        hnsw_params: Option<HnswParams>,
+    ) -> Result<(), DBError> {
+        let insert_data =
+            Array::from_shape_vec((args.data_row, args.data_dim), args.flat_data.clone()).map_err(
+                |e| DBError::PutError(format!("unable to create array from flat data: {e}")),
+            )?;
+
+        let res_async_insert = task::spawn_blocking(move || {
+            let index_insert_params = InsertParams {
+                data: &insert_data,
+                labels: &ids,
+                hnsw_params,
+                sparse_data: None,
+            };
+
+            let mut vector_index_writer = vector_index_writer.lock().unwrap();
+            let _timer = metrics::INDEX_INSERT_DURATION.start_timer();
+
+            vector_index_writer
+                .insert(&index_insert_params)
+                .map_err(|e| DBError::PutError(format!("unable to upsert vector data: {e}")))
+        })
+        .await;
+
+        res_async_insert.map_err(|e| {
+            DBError::PutError(format!(
+                "error while inserting vector database asynchronously: {e}",
+            ))
+        })??;
+
+        Ok(())
+    }
+
+    async fn insert_raw_vectors(
+        &mut self,
+        ids: Arc<Vec<u64>>,
+        field: Option<&str>,
+        args: &VectorArgs,
+    ) -> Result<(), DBError> {
+        let flat_data = args.flat_data.clone();
+        let dim = args.data_dim;
+        let field = field.map(|f| f.to_string());
+
+        let scalar_storage = Arc::clone(&self.scalar_storage);
+        task::spawn_blocking(move || {
+            for (id, vector) in ids.iter().zip(flat_data.chunks_exact(dim)) {
+                match &field {
+                    Some(name) => scalar_storage.put_field_vector(name, *id, vector),
+                    None => scalar_storage.put_vector(*id, vector),
+                }
+                .map_err(|e| DBError::PutError(format!("unable to store raw vector data: {e}")))?;
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| {
+            DBError::PutError(format!(
+                "error while storing raw vectors asynchronously: {e}",
+            ))
+        })??;
+
+        Ok(())
+    }
+
+    async fn insert_sparse_vectors(
+        &mut self,
+        ids: Arc<Vec<u64>>,
+        sparse_data: Vec<SparseVector>,
+    ) -> Result<(), DBError> {
+        let scalar_storage = Arc::clone(&self.scalar_storage);
+        let sparse_index = Arc::clone(&self.sparse_index);
+
+        task::spawn_blocking(move || {
+            for (id, vector) in ids.iter().zip(&sparse_data) {
+                scalar_storage.put_sparse_vector(*id, vector).map_err(|e| {
+                    DBError::PutError(format!("unable to store sparse vector data: {e}"))
+                })?;
+            }
+
+            let empty = Array::zeros((0, 0));
+            sparse_index
+                .lock()
+                .unwrap()
+                .insert(&InsertParams::new(&empty, &ids).with_sparse(&sparse_data))
+                .map_err(|e| DBError::PutError(format!("unable to upsert sparse vector data: {e}")))
+        })
+        .await
+        .map_err(|e| {
+            DBError::PutError(format!(
+                "error while inserting sparse vectors asynchronously: {e}",
+            ))
+        })??;
+
+        Ok(())
+    }
+
+    async fn insert_doc(
+        &mut self,
+        doc: &mut DocMap,
+        attributes: &HashMap<String, Value>,
+        id: u64,
+    ) -> Result<(), DBError> {
+        doc.insert("id".to_string(), Value::Number(id.into()));
+        doc.insert(
+            "attributes".to_string(),
+            serde_json::to_value(attributes).unwrap(),
+        );
+
+        let doc_bytes = serde_json::to_vec(&doc)
+            .map_err(|e| DBError::PutError(format!("unable to serialize doc data: {e}")))?;
+
+        let scalar_storage = Arc::clone(&self.scalar_storage);
+        task::spawn_blocking(move || {
+            scalar_storage
+                .put(&id.to_be_bytes(), &doc_bytes)
+                .map_err(|e| DBError::PutError(format!("unable to upsert scalar data: {e}")))?;
+            Ok(())
+        })
+        .await
+        .map_err(|e| {
+            DBError::PutError(format!(
+                "error while inserting scalar data asynchronously: {e}",
+            ))
+        })??;
+
+        Ok(())
+    }
+
+    // The attributes are checked through `validate_attributes` before any id is allocated
+    fn insert_attribute(&mut self, attr: &HashMap<String, Value>, id: u64) {
+        for (key, value) in attr {
+            if let Value::Number(num) = value {
+                if let Some(num) = num.as_i64() {
+                    self.filter_index.write().unwrap().upsert(key, num, id);
+                }
+            }
+        }
+    }
+
+    fn insert_text(&mut self, doc: &DocMap, id: u64) {
+        if let Some(text) = extract_text(doc, self.params.text_fields.as_deref()) {
+            self.text_index.write().unwrap().insert(id, &text);
+        }
+    }
+
+    fn revert_text(&mut self, ids: &[u64]) {
+        let mut text_index = self.text_index.write().unwrap();
+        for id in ids {
+            text_index.remove(*id);
+        }
+    }
+
+    fn revert_attributes(&mut self, attrs: &[HashMap<String, Value>], ids: &[u64]) {
+        for (attr, id) in attrs.iter().zip(ids) {
+            for (key, value) in attr {
+                if let Value::Number(num) = value {
+                    if let Some(num) = num.as_i64() {
+                        self.filter_index.write().unwrap().remove(key, num, *id);
+                    }
+                }
+            }
+        }
+    }
+
+    pub async fn query(&mut self, search_args: VdbSearchArgs) -> Result<Vec<DocMap>, DBError> {
+        Ok(self.query_page(search_args).await?.results)
+    }
+
+    // Like `query`, also returning the cursor of the next page
+    pub async fn query_page(&mut self, search_args: VdbSearchArgs) -> Result<SearchPage, DBError> {
+        let _timer = metrics::QUERY_DURATION.start_timer();
+
+        if search_args.group_by.is_some() {
+            return Err(DBError::ValidationError(
+                "grouped searches return groups, use query_groups".to_string(),
+            ));
+        }
+
+        let cursor = search_args
+            .cursor
+            .as_deref()
+            .map(decode_cursor)
+            .transpose()?;
+        if cursor.is_some() && search_args.mmr.is_some() {
+            return Err(DBError::ValidationError(
+                "MMR re-ranked results cannot be paged with a cursor, use offset".to_string(),
+            ));
+        }
+
+        let k = search_args.k;
+        let offset = search_args.offset.unwrap_or(0);
+        let with_vectors = search_args.with_vectors.unwrap_or(false);
+
+        // with a cursor, the hits before it are unknown until ranked, fetch more until the page is full
+        let mut fetch_k = offset + k;
+        let (hits, with_scores) = loop {
+            let ranked = self
+                .rank(VdbSearchArgs {
+                    k: fetch_k,
+                    ..search_args.clone()
+                })
+                .await?;
+            let is_exhausted = ranked.labels.len() < fetch_k;
+
+            let hits = ranked
+                .labels
+                .into_iter()
+                .zip(ranked.scores)
+                .filter(|(label, score)| {
+                    cursor.is_none_or(|cursor| is_after_cursor((*score, *label), cursor))
+                })
+                .skip(offset)
+                .take(k)
+                .collect::<Vec<_>>();
+
+            if cursor.is_none() || hits.len() == k || is_exhausted {
+                break (hits, ranked.with_scores);
+            }
+
+            fetch_k *= 2;
+        };
+
+        let next_cursor = hits
+            .last()
+            .filter(|_| hits.len() == k)
+            .map(|(label, score)| encode_cursor(*score, *label));
+
+        let (labels, scores): (Vec<u64>, Vec<f32>) = hits.into_iter().unzip();
+        let results = self.load_documents(&labels, with_scores.then_some(scores), with_vectors)?;
+
+        Ok(SearchPage {
+            results,
+            next_cursor,
+        })
+    }
+
+    // Searches with `group_by`, fetching more hits from the indexes until the best
+    // `limit` groups are full or no more hits are found
+    pub async fn query_groups(
+        &mut self,
+        search_args: VdbSearchArgs,
+    ) -> Result<Vec<SearchGroup>, DBError> {
+        let _timer = metrics::QUERY_DURATION.start_timer();
+
+        let group_by = search_args
+            .group_by
+            .clone()
+            .ok_or(DBError::ValidationError(
+                "group_by is not set on the grouped search".to_string(),
+            ))?;
+
+        if group_by.group_size == 0 || group_by.limit == 0 {
+            return Err(DBError::ValidationError(
+                "group_size and limit of group_by must be positive".to_string(),
+            ));
+        }
+
+        let wanted = group_by.group_size * group_by.limit;
+        let max_fetch = wanted * GROUP_BY_MAX_FETCH_FACTOR;
+        let mut fetch_k = wanted;
+
+        let groups = loop {
+            let ranked = self
+                .rank(VdbSearchArgs {
+                    k: fetch_k,
+                    group_by: None,
+                    ..search_args.clone()
+                })
+                .await?;
+
+            let values = self
+                .filter_index
+                .read()
+                .unwrap()
+                .values_of(&group_by.field, &ranked.labels);
+
+            // groups in order of their best hit
+            let mut groups: Vec<(i64, Vec<(u64, f32)>)> = vec![];
+            for ((label, score), value) in ranked.labels.iter().zip(&ranked.scores).zip(values) {
+                let Some(value) = value else {
+                    continue;
+                };
+
+                match groups.iter_mut().find(|(group, _)| *group == value) {
+                    Some((_, hits)) if hits.len() < group_by.group_size => {
+                        hits.push((*label, *score))
+                    }
+                    Some(_) => {}
+                    None => groups.push((value, vec![(*label, *score)])),
+                }
+            }
+            groups.truncate(group_by.limit);
+
+            let is_full = groups.len() == group_by.limit
+                && groups
+                    .iter()
+                    .all(|(_, hits)| hits.len() == group_by.group_size);
+
+            if is_full || ranked.labels.len() < fetch_k || fetch_k >= max_fetch {
+                break groups;
+            }
+
+            fetch_k = (fetch_k * 2).min(max_fetch);
+        };
+
+        let with_vectors = search_args.with_vectors.unwrap_or(false);
+
+        groups
+            .into_iter()
+            .map(|(group, hits)| {
+                let (labels, scores): (Vec<u64>, Vec<f32>) = hits.into_iter().unzip();
+
+                Ok(SearchGroup {
+                    group,
+                    score: scores[0],
+                    hits: self.load_documents(&labels, Some(scores), with_vectors)?,
+                })
+            })
+            .collect()
+    }
+
+    // Runs every requested ranking, fuses and re-ranks them into the final ranking
+    async fn rank(&mut self, search_args: VdbSearchArgs) -> Result<RankedHits, DBError> {
+        let k = search_args.k;
+
+        // the rankings are fetched at the size of the re-ranked pool
+        let search_args = match &search_args.mmr {
+            Some(mmr) if !(0.0..=1.0).contains(&mmr.lambda) => {
+                return Err(DBError::ValidationError(format!(
+                    "mmr lambda {} is not within [0, 1]",
+                    mmr.lambda
+                )));
+            }
+            Some(mmr) => VdbSearchArgs {
+                k: mmr.candidates.unwrap_or(k * MMR_CANDIDATE_FACTOR).max(k),
+                ..search_args
+            },
+            None => search_args,
+        };
+
+        let text_query = search_args
+            .text_query
+            .as_deref()
+            .filter(|q| !q.trim().is_empty());
+
+        if text_query.is_some() && self.params.text_fields.is_none() {
+            return Err(DBError::ValidationError(
+                "keyword search needs text fields configured on the database".to_string(),
+            ));
+        }
+
+        let named_queries = search_args.named_queries.clone().unwrap_or_default();
+        let multi_queries = search_args.multi_queries.clone().unwrap_or_default();
+
+        // the dense query vector may be left empty when another ranking is requested
+        let with_vector_search = !search_args.query.is_empty()
+            || (text_query.is_none()
+                && search_args.sparse_query.is_none()
+                && named_queries.is_empty()
+                && multi_queries.is_empty());
+
+        for name in search_args.vector_field.iter().chain(named_queries.keys()) {
+            if self.is_multi_vector_field(name) {
+                return Err(DBError::ValidationError(format!(
+                    "vector field {name} holds multiple vectors per doc, use multi_queries"
+                )));
+            }
+        }
+
+        let (_, dense_params) = self.field_index(search_args.vector_field.as_deref())?;
+
+        if with_vector_search && search_args.query.len() != dense_params.dim as usize {
+            return Err(DBError::ValidationError(format!(
+                "query vector length {} does not match index dimension {}",
+                search_args.query.len(),
+                dense_params.dim,
+            )));
+        }
+
+        let allowed_ids = self.allowed_ids(search_args.filter_inputs.as_deref());
+
+        let vector_hits = if with_vector_search {
+            self.dense_search(
+                search_args.vector_field.as_deref(),
+                &search_args,
+                search_args.query.clone(),
+                allowed_ids.as_ref(),
+                search_args.k,
+            )
+            .await?
+        } else {
+            vec![]
+        };
+
+        event!(Level::DEBUG, "search result inside: {vector_hits:?}");
+
+        // rankings fused with the dense one, scored so that higher is better
+        let mut other_hits: Vec<Vec<(u64, f32)>> = vec![];
+
+        if let Some(text_query) = text_query {
+            other_hits.push(self.text_index.read().unwrap().search(
+                text_query,
+                search_args.k,
+                allowed_ids.as_ref(),
+            ));
+        }
+
+        for (name, vector) in named_queries {
+            let (_, field_params) = self.field_index(Some(&name))?;
+
+            if vector.len() != field_params.dim as usize {
+                return Err(DBError::ValidationError(format!(
+                    "query vector length {} does not match dimension {} of vector field {name}",
+                    vector.len(),
+                    field_params.dim,
+                )));
+            }
+
+            other_hits.push(
+                self.dense_search(
+                    Some(&name),
+                    &search_args,
+                    vector,
+                    allowed_ids.as_ref(),
+                    search_args.k,
+                )
+                .await?,
+            );
+        }
+
+        for (name, flat_query) in multi_queries {
+            other_hits.push(
+                self.search_multi_vectors(&name, &flat_query, &search_args, allowed_ids.as_ref())
+                    .await?,
+            );
+        }
+
+        if let Some(sparse_query) = &search_args.sparse_query {
+            let mut query = SearchQuery::new(vec![]).with(sparse_query);
+
+            if let Some(bitmap) = &allowed_ids {
+                query = query.with(&IdFilter::from(bitmap.clone()));
+            }
+
+            let sparse_result =
+                search_index(Arc::clone(&self.sparse_index), query, search_args.k).await?;
+            other_hits.push(
+                sparse_result
+                    .labels
+                    .into_iter()
+                    .zip(sparse_result.distances)
+                    .collect(),
+            );
+        }
+
+        let (labels, scores, with_scores) = if other_hits.is_empty() {
+            let (labels, scores): (Vec<u64>, Vec<f32>) = vector_hits.into_iter().unzip();
+
+            (labels, scores, false)
+        } else if !with_vector_search && other_hits.len() == 1 {
+            // nothing to fuse with, keep the scores of the single ranking
+            let (labels, scores): (Vec<u64>, Vec<f32>) = other_hits.remove(0).into_iter().unzip();
+
+            (labels, scores, true)
+        } else {
+            let fused = fuse(
+                &vector_hits,
+                &other_hits,
+                search_args.fusion.unwrap_or_default(),
+                search_args.k,
+            );
+            let (labels, scores): (Vec<u64>, Vec<f32>) = fused.into_iter().unzip();
+
+            (labels, scores, true)
+        };
+
+        let (labels, scores) = match &search_args.mmr {
+            Some(mmr_params) => {
+                let hits = labels.iter().copied().zip(scores).collect::<Vec<_>>();
+                let vectors = self.stored_vectors(search_args.vector_field.as_deref(), &labels)?;
+
+                let reranked = mmr(
+                    &dense_params.metric_type,
+                    &hits,
+                    &vectors,
+                    mmr_params.lambda,
+                    k,
+                );
+                reranked.into_iter().unzip()
+            }
+            None => {
+                // ties are broken by id so that pages never overlap
+                let mut hits = labels.into_iter().zip(scores).collect::<Vec<_>>();
+                hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+                hits.into_iter().unzip()
+            }
+        };
+
+        Ok(RankedHits {
+            labels,
+            scores,
+            with_scores,
+        })
+    }
+
+    pub async fn recommend(&mut self, args: VdbRecommendArgs) -> Result<Vec<DocMap>, DBError> {
+        if args.positive.is_empty() {
+            return Err(DBError::ValidationError(
+                "recommend needs at least one positive example id".to_string(),
+            ));
+        }
+
+        if let Some(name) = &args.vector_field {
+            if self.is_multi_vector_field(name) {
+                return Err(DBError::ValidationError(format!(
+                    "vector field {name} holds multiple vectors per doc and cannot be recommended on"
+                )));
+            }
+        }
+
+        let negative = args.negative.clone().unwrap_or_default();
+        let field = args.vector_field.as_deref();
+        let (_, params) = self.field_index(field)?;
+        let dim = params.dim as usize;
+
+        let positive_vectors = self.example_vectors(field, &args.positive)?;
+        let negative_vectors = self.example_vectors(field, &negative)?;
+
+        // the examples never come back as results
+        let examples = args.positive.iter().chain(&negative).copied();
+        let allowed_ids = self
+            .allowed_ids(args.filter_inputs.as_deref())
+            .map(|mut bitmap| {
+                examples.clone().for_each(|id| {
+                    bitmap.remove(id as u32);
+                });
+                bitmap
+            });
+        let is_example = |id: &u64| args.positive.contains(id) || negative.contains(id);
+
+        // examples may still be returned by the index when no filter is given
+        let fetch_k = args.k + args.positive.len() + negative.len();
+        let search_args = VdbSearchArgs {
+            hnsw_params: args.hnsw_params.clone(),
+            vamana_params: args.vamana_params.clone(),
+            ..Default::default()
+        };
+
+        let mut hits: Vec<(u64, f32)> = match args.strategy.unwrap_or_default() {
+            RecommendStrategy::AverageVector => {
+                let mut vector = average_vector(&positive_vectors, dim);
+
+                if !negative_vectors.is_empty() {
+                    let negative_average = average_vector(&negative_vectors, dim);
+                    vector
+                        .iter_mut()
+                        .zip(negative_average)
+                        .for_each(|(v, n)| *v += *v - n);
+                }
+
+                self.dense_search(field, &search_args, vector, allowed_ids.as_ref(), fetch_k)
+                    .await?
+                    .into_iter()
+                    .filter(|(label, _)| !is_example(label))
+                    .collect()
+            }
+            RecommendStrategy::BestScore => {
+                let mut candidates = RoaringBitmap::new();
+
+                for vector in &positive_vectors {
+                    let hits = self
+                        .dense_search(
+                            field,
+                            &search_args,
+                            vector.clone(),
+                            allowed_ids.as_ref(),
+                            fetch_k,
+                        )
+                        .await?;
+                    candidates.extend(
+                        hits.into_iter()
+                            .map(|(label, _)| label)
+                            .filter(|label| !is_example(label))
+                            .map(|label| label as u32),
+                    );
+                }
+
+                let candidates = candidates.iter().map(u64::from).collect::<Vec<_>>();
+                let candidate_vectors = self.example_vectors(field, &candidates)?;
+
+                let best_similarity = |vector: &[f32], examples: &[Vec<f32>]| {
+                    examples
+                        .iter()
+                        .map(|example| similarity(&params.metric_type, vector, example))
+                        .fold(f32::NEG_INFINITY, f32::max)
+                };
+
+                candidates
+                    .into_iter()
+                    .zip(candidate_vectors)
+                    .map(|(label, vector)| {
+                        let mut score = best_similarity(&vector, &positive_vectors);
+
+                        if !negative_vectors.is_empty() {
+                            score -= best_similarity(&vector, &negative_vectors);
+                        }
+
+                        (label, score)
+                    })
+                    .collect()
+            }
+        };
+
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits.truncate(args.k);
+
+        let (labels, scores): (Vec<u64>, Vec<f32>) = hits.into_iter().unzip();
+
+        self.load_documents(&labels, Some(scores), args.with_vectors.unwrap_or(false))
+    }
+
+    // Searches the index of a single-vector field, scored so that higher is better. Very
+    // selective filters are served by an exact scan of the allowed ids, returning
+    // k hits whenever k docs match
+    async fn dense_search(
+        &self,
+        field: Option<&str>,
+        search_args: &VdbSearchArgs,
+        vector: Vec<f32>,
+        allowed_ids: Option<&RoaringBitmap>,
+        k: usize,
+    ) -> Result<Vec<(u64, f32)>, DBError> {
+        let (index, params) = self.field_index(field)?;
+
+        if let Some(bitmap) = allowed_ids {
+            let total = self.scalar_storage.count(scalar::NAMESPACE_DOCS)?;
+            let selectivity = self
+                .params
+                .brute_force_selectivity
+                .unwrap_or(DEFAULT_BRUTE_FORCE_SELECTIVITY);
+
+            if (bitmap.len() as f64) < selectivity as f64 * total as f64 {
+                event!(
+                    Level::DEBUG,
+                    "scanning {} allowed ids out of {total} docs",
+                    bitmap.len()
+                );
+
+                let ids = bitmap.iter().map(u64::from).collect::<Vec<_>>();
+                let vectors = self.stored_vectors(field, &ids)?;
+
+                let mut hits = ids
+                    .into_iter()
+                    .zip(vectors)
+                    .filter_map(|(id, stored)| {
+                        stored.map(|stored| (id, similarity(&params.metric_type, &vector, &stored)))
+                    })
+                    .collect::<Vec<_>>();
+                hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+                hits.truncate(k);
+
+                return Ok(hits);
+            }
+        }
+
+        let hits = self
+            .search_live_index(index, &params, search_args, &vector, allowed_ids, k)
+            .await?;
+
+        if self.shadow_sampled(field, &params) {
+            let labels = hits.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+            self.spawn_shadow_scan(
+                field,
+                params.metric_type.clone(),
+                vector,
+                allowed_ids,
+                k,
+                &labels,
+            );
+        }
+
+        Ok(hits)
+    }
+
+    // Searches a dense index for the top k docs that are not deleted. Filtered searches
+    // keep the deleted docs out through the filter itself, others fetch more hits only
+    // while the deleted docs crowd out the requested ones
+    async fn search_live_index(
+        &self,
+        index: Arc<Mutex<dyn Index + Send>>,
+        params: &DatabaseParams,
+        search_args: &VdbSearchArgs,
+        vector: &[f32],
+        allowed_ids: Option<&RoaringBitmap>,
+        k: usize,
+    ) -> Result<Vec<(u64, f32)>, DBError> {
+        let deleted_ids = self.deleted_ids.read().unwrap().clone();
+        let allowed_ids = allowed_ids.map(|bitmap| bitmap - &deleted_ids);
+        let max_fetch_k = match allowed_ids {
+            Some(_) => k,
+            None => k + deleted_ids.len() as usize,
+        };
+
+        let mut fetch_k = max_fetch_k.min(2 * k);
+        loop {
+            let query = dense_query(search_args, params, vector.to_vec(), allowed_ids.as_ref());
+            let result = search_index(Arc::clone(&index), query, fetch_k).await?;
+            let fetched = result.labels.len();
+
+            let hits = result
+                .labels
+                .into_iter()
+                .zip(result.distances)
+                .filter(|(label, _)| !deleted_ids.contains(*label as u32))
+                .take(k)
+                .map(|(label, distance)| (label, vector_score(params, distance)))
+                .collect::<Vec<_>>();
+
+            if hits.len() == k || fetched < fetch_k || fetch_k == max_fetch_k {
+                return Ok(hits);
+            }
+            fetch_k = max_fetch_k.min(2 * fetch_k);
+        }
+    }
+
+    // Whether this search of an approximate index is compared with an exact scan
+    fn shadow_sampled(&self, field: Option<&str>, params: &DatabaseParams) -> bool {
+        let Some(rate) = self.params.shadow_sample_rate else {
+            return false;
+        };
+
+        // flat indexes are exact and multi-vector fields store token vectors
+        let approximate = matches!(params.index_type, IndexType::Hnsw | IndexType::Vamana);
+        approximate
+            && !field.is_some_and(|name| self.is_multi_vector_field(name))
+            && self.shadow_recall.sample(rate)
+    }
+
+    // Scans the stored vectors in the background for the exact top k of a query
+    // and records how many of them the index returned
+    fn spawn_shadow_scan(
+        &self,
+        field: Option<&str>,
+        metric_type: MetricType,
+        vector: Vec<f32>,
+        allowed_ids: Option<&RoaringBitmap>,
+        k: usize,
+        labels: &[u64],
+    ) {
+        // a scan reads the whole collection, it must not crowd out the searches it measures
+        let Ok(permit) = Arc::clone(&self.shadow_scans).try_acquire_owned() else {
+            event!(
+                Level::DEBUG,
+                "skipped a shadow scan, {MAX_SHADOW_SCANS} are already running"
+            );
+            return;
+        };
+
+        let scalar_storage = Arc::clone(&self.scalar_storage);
+        let shadow_recall = Arc::clone(&self.shadow_recall);
+        let field = field.map(str::to_string);
+        let allowed_ids = allowed_ids.cloned();
+        let labels = labels.to_vec();
+
+        task::spawn_blocking(move || {
+            let _permit = permit;
+            let vectors = match field.as_deref() {
+                Some(name) => scalar_storage.field_vector_iter(name),
+                None => scalar_storage.vector_iter(),
+            };
+
+            // similarities are higher-is-better like inner products
+            let mut top_k = TopK::new(k, MetricType::IP);
+            for item in vectors {
+                let (id, stored) = match item {
+                    Ok(item) => item,
+                    Err(e) => {
+                        event!(Level::WARN, "shadow scan failed: {e}");
+                        return;
+                    }
+                };
+
+                if allowed_ids
+                    .as_ref()
+                    .is_some_and(|bitmap| !bitmap.contains(id as u32))
+                {
+                    continue;
+                }
+
+                top_k.push(id, similarity(&metric_type, &vector, &stored));
+            }
+
+            let (_, truth) = top_k.into_sorted();
+            let found = truth.iter().filter(|id| labels.contains(id)).count();
+            shadow_recall.record(found, truth.len());
+
+            event!(
+                Level::DEBUG,
+                "shadow scan found {found} of the {} exact hits",
+                truth.len()
+            );
+        });
+    }
+
+    pub fn params(&self) -> &DatabaseParams {
+        &self.params
+    }
+
+    pub fn stats(&self) -> Result<DatabaseStats, DBError> {
+        let doc_count = self.scalar_storage.count(scalar::NAMESPACE_DOCS)?;
+
+        let mut fields = vec![self.params.clone()];
+        fields.extend(
+            self.params
+                .vector_fields
+                .iter()
+                .flatten()
+                .filter(|field| !field.is_multi_vector())
+                .map(|field| self.params.for_field(field)),
+        );
+        let memory_estimate_bytes = fields
+            .iter()
+            .filter(|params| !params.index_type.is_persistent())
+            .map(|params| doc_count * params.dim as u64 * size_of::<f32>() as u64)
+            .sum();
+
+        Ok(DatabaseStats {
+            doc_count,
+            params: self.params.clone(),
+            memory_estimate_bytes,
+            wal_log_id: self.persistence.get_log_id(),
+            storage: self.scalar_storage.properties()?,
+            shadow_recall: self.shadow_recall(),
+        })
+    }
+
+    // Refreshes the metrics read from the database state rather than counted as it goes
+    pub fn export_metrics(&self) -> Result<(), DBError> {
+        let collection = self.db_path.to_string_lossy();
+        let labels = [collection.as_ref()];
+
+        metrics::DOCS
+            .with_label_values(&labels)
+            .set(self.scalar_storage.count(scalar::NAMESPACE_DOCS)? as i64);
+
+        for (property, value) in self.scalar_storage.properties()? {
+            metrics::STORAGE_PROPERTIES
+                .with_label_values(&[collection.as_ref(), property.as_str()])
+                .set(value as i64);
+        }
+
+        let shadow_recall = self.shadow_recall();
+        metrics::sync_counter(
+            &metrics::SHADOW_SAMPLES.with_label_values(&labels),
+            shadow_recall.samples,
+        );
+        metrics::sync_counter(
+            &metrics::SHADOW_FOUND.with_label_values(&labels),
+            shadow_recall.found,
+        );
+        metrics::sync_counter(
+            &metrics::SHADOW_EXPECTED.with_label_values(&labels),
+            shadow_recall.expected,
+        );
+
+        Ok(())
+    }
+
+    // Recall of the dense index measured on the sampled live searches
+    pub fn shadow_recall(&self) -> ShadowRecallStats {
+        let found = self.shadow_recall.found.load(Ordering::Relaxed);
+        let expected = self.shadow_recall.expected.load(Ordering::Relaxed);
+
+        ShadowRecallStats {
+            samples: self.shadow_recall.samples.load(Ordering::Relaxed),
+            found,
+            expected,
+            recall: (expected > 0).then(|| found as f32 / expected as f32),
+        }
+    }
+
+    // Stored vectors of the given ids in the default or a named vector field
+    fn stored_vectors(
+        &self,
+        field: Option<&str>,
+        ids: &[u64],
+    ) -> Result<Vec<Option<Vec<f32>>>, DBError> {
+        match field {
+            Some(name) => self.scalar_storage.multi_get_field_vectors(name, ids),
+            None => self.scalar_storage.multi_get_vectors(ids),
+        }
+    }
+
+    // Like `stored_vectors`, but every id must have a stored vector
+    fn example_vectors(&self, field: Option<&str>, ids: &[u64]) -> Result<Vec<Vec<f32>>, DBError> {
+        ids.iter()
+            .zip(self.stored_vectors(field, ids)?)
+            .map(|(id, vector)| {
+                vector.ok_or(DBError::NotFoundError(format!(
+                    "no stored vector found for doc {id}"
+                )))
+            })
+            .collect()
+    }
+
+    // Fetches docs by id in the given order, unknown ids are skipped
+    pub fn get(&self, ids: &[u64], with_vectors: bool) -> Result<Vec<DocMap>, DBError> {
+        let documents = self.load_documents(ids, None, with_vectors)?;
+
+        Ok(documents
+            .into_iter()
+            .filter(|doc| doc.contains_key("id"))
+            .collect())
+    }
+
+    // Deletes docs by id from the storage and every index, returns the number of docs
+    // that existed
+    pub async fn delete(&mut self, ids: &[u64]) -> Result<usize, DBError> {
+        let mut existing = vec![];
+        let mut attributes = vec![];
+
+        for id in ids.iter().copied().collect::<BTreeSet<_>>() {
+            if let Some(doc) = self.scalar_storage.get_value(id)? {
+                let attrs = doc
+                    .get("attributes")
+                    .and_then(|attrs| serde_json::from_value(attrs.clone()).ok())
+                    .unwrap_or_default();
+
+                existing.push(id);
+                attributes.push(attrs);
+            }
+        }
+
+        if existing.is_empty() {
+            return Ok(0);
+        }
+
+        self.remove_docs(&existing, &attributes)?;
+        self.scalar_storage
+            .decr_count(scalar::NAMESPACE_DOCS, existing.len())?;
+
+        event!(Level::INFO, "Deleted {} docs", existing.len());
+
+        Ok(existing.len())
+    }
+
+    // Removes docs stored with the given attributes from the storage and every index
+    fn remove_docs(
+        &mut self,
+        ids: &[u64],
+        attributes: &[HashMap<String, Value>],
+    ) -> Result<(), DBError> {
+        let mut fields: Vec<Option<String>> = vec![None];
+        let mut tokens = vec![];
+        for field in self.params.vector_fields.iter().flatten() {
+            match self.multi_vector_maps.get(&field.name) {
+                Some(multi_vector_map) => {
+                    let mut multi_vector_map = multi_vector_map.write().unwrap();
+                    let field_tokens = ids
+                        .iter()
+                        .flat_map(|id| multi_vector_map.remove(*id))
+                        .collect::<Vec<_>>();
+                    tokens.push((field.name.clone(), field_tokens));
+                }
+                None => fields.push(Some(field.name.clone())),
+            }
+        }
+
+        for field in &fields {
+            let (index, _) = self.field_index(field.as_deref())?;
+            if index.lock().unwrap().remove(ids).is_err() {
+                // such as HNSW, searches skip the ids until the index is rebuilt
+                self.deleted_ids
+                    .write()
+                    .unwrap()
+                    .extend(ids.iter().map(|id| *id as u32));
+            }
+        }
+
+        // token ids are only resolved through the multi-vector maps, which no longer hold them
+        for (name, field_tokens) in &tokens {
+            let (index, _) = self.field_index(Some(name))?;
+            index.lock().unwrap().remove(field_tokens).ok();
+        }
+
+        self.revert_upsert(attributes, ids);
+
+        let named_fields = fields
+            .into_iter()
+            .flatten()
+            .chain(tokens.iter().map(|(name, _)| name.clone()))
+            .collect::<Vec<_>>();
+        self.scalar_storage.delete_docs(ids, &named_fields, &tokens)
+    }
+
+    // Counts docs without searching, filters are evaluated on the attribute index only
+    pub fn count(&self, args: VdbCountArgs) -> Result<CountResult, DBError> {
+        let total = self.scalar_storage.count(scalar::NAMESPACE_DOCS)?;
+
+        let count = match self.allowed_ids(args.filter_inputs.as_deref()) {
+            Some(bitmap) => bitmap.len(),
+            None => total,
+        };
+
+        Ok(CountResult { count, total })
+    }
+
+    // Counts the docs per value of each field, within the filtered docs if filters are given
+    pub fn facet(&self, args: VdbFacetArgs) -> Result<HashMap<String, Vec<FacetCount>>, DBError> {
+        let allowed_ids = self.allowed_ids(args.filter_inputs.as_deref());
+        let limit = args.limit.unwrap_or(DEFAULT_FACET_LIMIT);
+        let filter_index = self.filter_index.read().unwrap();
+
+        let facets = args
+            .fields
+            .into_iter()
+            .map(|field| {
+                let counts = filter_index
+                    .facet(&field, allowed_ids.as_ref(), limit)
+                    .into_iter()
+                    .map(|(value, count)| FacetCount { value, count })
+                    .collect();
+
+                (field, counts)
+            })
+            .collect();
+
+        Ok(facets)
+    }
+
+    // Walks the stored docs in id order, one page at a time
+    pub fn scroll(&self, args: VdbScrollArgs) -> Result<ScrollPage, DBError> {
+        if args.limit == 0 {
+            return Err(DBError::ValidationError(
+                "scroll limit must be positive".to_string(),
+            ));
+        }
+
+        let allowed_ids = self.allowed_ids(args.filter_inputs.as_deref());
+
+        let mut labels = vec![];
+        let mut documents = vec![];
+        let mut next_cursor = None;
+
+        for item in self.scalar_storage.doc_iter_from(args.cursor.unwrap_or(0)) {
+            let (id, doc) = item?;
+
+            if let Some(bitmap) = &allowed_ids {
+                if !bitmap.contains(id as u32) {
+                    continue;
+                }
+            }
+
+            // the page is full, resume from the first doc left over
+            if documents.len() == args.limit {
+                next_cursor = Some(id);
+                break;
+            }
+
+            labels.push(id);
+            documents.push(doc);
+        }
+
+        if args.with_vectors.unwrap_or(false) {
+            self.attach_vectors(&mut documents, &labels)?;
+        }
+
+        Ok(ScrollPage {
+            results: documents,
+            next_cursor,
+        })
+    }
+
+    // Ids matching the attribute filters, None when no filter is given
+    fn allowed_ids(&self, filter_inputs: Option<&[IntFilterInput]>) -> Option<RoaringBitmap> {
+        match filter_inputs {
+            Some(filter_inputs) if !filter_inputs.is_empty() => {
+                let filter_index = self.filter_index.read().unwrap();
+                let mut bitmap = RoaringBitmap::new();
+
+                for filter in filter_inputs {
+                    bitmap = filter_index.apply(filter, &bitmap);
+                }
+                metrics::FILTER_BITMAP_SIZE.observe(bitmap.len() as f64);
+
+                Some(bitmap)
+            }
+            _ => None,
+        }
+    }
+
+    // Fetches the docs of the given ids in order, with their scores and stored vectors if asked
+    fn load_documents(
+        &self,
+        labels: &[u64],
+        scores: Option<Vec<f32>>,
+        with_vectors: bool,
+    ) -> Result<Vec<DocMap>, DBError> {
+        if labels.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut documents = self.scalar_storage.multi_get_value(labels)?;
+
+        if with_vectors {
+            self.attach_vectors(&mut documents, labels)?;
+        }
+
+        if let Some(scores) = scores {
+            for (doc, score) in documents.iter_mut().zip(scores) {
+                doc.insert("score".to_string(), serde_json::json!(score));
+            }
+        }
+
+        Ok(documents)
+    }
+
+    // Adds the stored vectors of every doc under the "vector", "sparse_vector" and "named_vectors" keys
+    fn attach_vectors(&self, documents: &mut [DocMap], labels: &[u64]) -> Result<(), DBError> {
+        let vectors = self.scalar_storage.multi_get_vectors(labels)?;
+        let sparse_vectors = self.scalar_storage.multi_get_sparse_vectors(labels)?;
+
+        for ((doc, vector), sparse_vector) in documents.iter_mut().zip(vectors).zip(sparse_vectors)
+        {
+            doc.insert(
+                "vector".to_string(),
+                serde_json::to_value(vector).unwrap_or(Value::Null),
+            );
+
+            if let Some(sparse_vector) = sparse_vector {
+                doc.insert(
+                    "sparse_vector".to_string(),
+                    serde_json::to_value(sparse_vector).unwrap_or(Value::Null),
+                );
+            }
+        }
+
+        let mut named_vectors: Vec<serde_json::Map<String, Value>> =
+            vec![serde_json::Map::new(); labels.len()];
+        for name in self.named_indexes.keys() {
+            let vectors = self.scalar_storage.multi_get_field_vectors(name, labels)?;
+
+            let dim = self.field_index(Some(name))?.1.dim as usize;
+            let is_multi_vector = self.is_multi_vector_field(name);
+
+            for (named, vector) in named_vectors.iter_mut().zip(vectors) {
+                match vector {
+                    Some(vector) if is_multi_vector => {
+                        let rows = vector.chunks_exact(dim).collect::<Vec<_>>();
+                        named.insert(name.clone(), serde_json::json!(rows));
+                    }
+                    Some(vector) => {
+                        named.insert(name.clone(), serde_json::json!(vector));
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        for (doc, named) in documents.iter_mut().zip(named_vectors) {
+            if !named.is_empty() {
+                doc.insert("named_vectors".to_string(), Value::Object(named));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Finds the docs owning the nearest token vectors of every query vector, then
+    // ranks them by exact MaxSim over all their stored vectors
+    async fn search_multi_vectors(
+        &self,
+        field: &str,
+        flat_query: &[f32],
+        search_args: &VdbSearchArgs,
+        allowed_ids: Option<&RoaringBitmap>,
+    ) -> Result<Vec<(u64, f32)>, DBError> {
+        if !self.is_multi_vector_field(field) {
+            return Err(DBError::ValidationError(format!(
+                "vector field {field} is not a multi-vector field"
+            )));
+        }
+
+        let (field_index, field_params) = self.field_index(Some(field))?;
+        let dim = field_params.dim as usize;
+
+        if flat_query.is_empty() || flat_query.len() % dim != 0 {
+            return Err(DBError::ValidationError(format!(
+                "multi-vector query length {} is not a multiple of dimension {dim} of vector field {field}",
+                flat_query.len(),
+            )));
+        }
+
+        let token_filter = allowed_ids.map(|allowed| {
+            self.multi_vector_maps[field]
+                .read()
+                .unwrap()
+                .token_filter(allowed)
+        });
+
+        let mut candidates: Vec<u64> = vec![];
+        for vector in flat_query.chunks_exact(dim) {
+            let query = dense_query(
+                search_args,
+                &field_params,
+                vector.to_vec(),
+                token_filter.as_ref(),
+            );
+            let token_result = search_index(
+                Arc::clone(&field_index),
+                query,
+                search_args.k * MULTI_VECTOR_CANDIDATE_FACTOR,
+            )
+            .await?;
+
+            let multi_vector_map = self.multi_vector_maps[field].read().unwrap();
+            candidates.extend(
+                token_result
+                    .labels
+                    .iter()
+                    .filter_map(|token| multi_vector_map.parent_of(*token)),
+            );
+        }
+        candidates.sort();
+        candidates.dedup();
+
+        let doc_vectors = self
+            .scalar_storage
+            .multi_get_field_vectors(field, &candidates)?;
+
+        let mut hits = candidates
+            .into_iter()
+            .zip(doc_vectors)
+            .filter_map(|(id, doc)| {
+                doc.map(|doc| {
+                    (
+                        id,
+                        max_sim(&field_params.metric_type, flat_query, &doc, dim),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits.truncate(search_args.k);
+
+        Ok(hits)
+    }
+
+    // Restores the token mapping of a multi-vector field, and its index unless persistent
+    async fn load_multi_vectors(&mut self, field: &str) -> Result<(), DBError> {
+        let (vector_index, params) = self.field_index(Some(field))?;
+        let dim = params.dim as usize;
+
+        {
+            let mut multi_vector_map = self.multi_vector_maps[field].write().unwrap();
+            for item in self.scalar_storage.token_parent_iter(field) {
+                let (token, parent) = item?;
+                multi_vector_map.insert(parent, token);
+            }
+        }
+
+        if params.index_type.is_persistent() {
+            return Ok(());
+        }
+
+        let multi_vector_map = self.multi_vector_maps[field].read().unwrap();
+        let total = load_index_from_storage(
+            vector_index.as_ref(),
+            token_vector_iter(self.scalar_storage.as_ref(), field, &multi_vector_map, dim),
+            dim,
+        )?;
+
+        event!(
+            Level::INFO,
+            "Loaded {total} stored vectors into vector index of multi-vector field {field}"
+        );
+
+        Ok(())
+    }
+
+    fn is_multi_vector_field(&self, name: &str) -> bool {
+        self.params
+            .vector_field(name)
+            .is_some_and(|field| field.is_multi_vector())
+    }
+
+    // Returns the index and index params of a vector field, None being the default field
+    fn field_index(
+        &self,
+        field: Option<&str>,
+    ) -> Result<(Arc<Mutex<dyn Index + Send>>, DatabaseParams), DBError> {
+        let Some(name) = field else {
+            return Ok((Arc::clone(&self.vector_index), self.params.clone()));
+        };
+
+        match (self.named_indexes.get(name), self.params.vector_field(name)) {
+            (Some(index), Some(field)) => Ok((Arc::clone(index), self.params.for_field(field))),
+            _ => Err(DBError::NotFoundError(format!(
+                "unknown vector field: {name}"
+            ))),
+        }
+    }
+
+    /// Picks the smallest HNSW `ef_search` reaching the recall target at `k` and makes
+    /// it the default of the queries that do not set their own. The value is kept in the
+    /// storage and takes over the configured one when the database is opened again.
+    ///
+    /// The recall is measured by querying with a sample of the stored vectors and
+    /// comparing the hits with an exact scan over all of them.
+    pub async fn tune_ef_search(&mut self, args: VdbTuneArgs) -> Result<TuneResult, DBError> {
+        let field = args.vector_field.as_deref();
+        let (index, params) = self.field_index(field)?;
+
+        if params.index_type != IndexType::Hnsw {
+            return Err(DBError::ConflictError(format!(
+                "ef_search only applies to HNSW indexes, got {:?}",
+                params.index_type
+            )));
+        }
+
+        if field.is_some_and(|name| self.is_multi_vector_field(name)) {
+            return Err(DBError::ConflictError(
+                "ef_search of multi-vector fields cannot be tuned".to_string(),
+            ));
+        }
+
+        let k = args.k.max(1);
+        let recall_target = args.recall_target.unwrap_or(DEFAULT_TUNE_RECALL_TARGET);
+        let sample_size = args.sample_size.unwrap_or(DEFAULT_TUNE_SAMPLE_SIZE).max(1);
+
+        // samples spread over the docs, each with its exact top k as ground truth. The
+        // stored vectors are read once for the samples and once to score them
+        let doc_count = self.scalar_storage.count(scalar::NAMESPACE_DOCS)? as usize;
+        let scalar_storage = Arc::clone(&self.scalar_storage);
+        let field_name = field.map(str::to_string);
+        let metric_type = params.metric_type.clone();
+        let samples = task::spawn_blocking(move || {
+            let vectors = || match field_name.as_deref() {
+                Some(name) => scalar_storage.field_vector_iter(name),
+                None => scalar_storage.vector_iter(),
+            };
+
+            let step = (doc_count / sample_size).max(1);
+            let queries = vectors()
+                .step_by(step)
+                .take(sample_size)
+                .map(|item| item.map(|(_, vector)| vector))
+                .collect::<Result<Vec<_>, DBError>>()?;
+
+            // similarities are higher-is-better like inner products
+            let mut top_ks = queries
+                .iter()
+                .map(|_| TopK::new(k, MetricType::IP))
+                .collect::<Vec<_>>();
+            for item in vectors() {
+                let (id, vector) = item?;
+                for (query, top_k) in queries.iter().zip(&mut top_ks) {
+                    top_k.push(id, similarity(&metric_type, query, &vector));
+                }
+            }
+
+            Ok::<_, DBError>(
+                queries
+                    .into_iter()
+                    .zip(top_ks)
+                    .map(|(query, top_k)| (query, top_k.into_sorted().1))
+                    .collect::<Vec<(Vec<f32>, Vec<u64>)>>(),
+            )
+        })
+        .await
+        .map_err(|e| DBError::GetError(format!("error while scanning stored vectors: {e}")))??;
+
+        if samples.is_empty() {
+            return Err(DBError::ConflictError(
+                "no stored vectors to tune ef_search on".to_string(),
+            ));
+        }
+
+        // searched like queries are, deleted docs still held by the index left out
+        let db = &*self;
+        let measure = |ef_search: u32| {
+            let index = Arc::clone(&index);
+            let params = &params;
+            let samples = &samples;
+            let search_args = VdbSearchArgs {
+                hnsw_params: Some(HnswSearchOption { ef_search }),
+                ..Default::default()
+            };
+
+            async move {
+                let mut found = 0;
+                let mut expected = 0;
+
+                for (query, truth) in samples {
+                    let hits = db
+                        .search_live_index(Arc::clone(&index), params, &search_args, query, None, k)
+                        .await?;
+
+                    found += hits.iter().filter(|(id, _)| truth.contains(id)).count();
+                    expected += truth.len();
+                }
+
+                Ok::<f32, DBError>(found as f32 / expected as f32)
+            }
+        };
+
+        // double ef until the target is reached, then bisect down to the smallest one
+        let mut high = k as u32;
+        let mut low = high - 1;
+        let mut recall = measure(high).await?;
+
+        while recall < recall_target && high < MAX_TUNE_EF_SEARCH {
+            low = high;
+            high = (high * 2).min(MAX_TUNE_EF_SEARCH);
+            recall = measure(high).await?;
+        }
+
+        if recall >= recall_target {
+            while high - low > 1 {
+                let mid = low + (high - low) / 2;
+                let mid_recall = measure(mid).await?;
+
+                if mid_recall >= recall_target {
+                    high = mid;
+                    recall = mid_recall;
+                } else {
+                    low = mid;
+                }
+            }
+        }
+
+        event!(
+            Level::INFO,
+            "tuned ef_search to {high} with recall {recall} at k={k}"
+        );
+
+        // kept with the data, the config file only gives the value of new databases
+        self.scalar_storage
+            .put(&scalar::ef_search_key(field), &high.to_be_bytes())?;
+        self.params.set_ef_search(field, high);
+
+        Ok(TuneResult {
+            ef_search: high,
+            recall,
+        })
+    }
+
+    /// Rebuilds the vector indexes of every field from the raw vectors kept in the
+    /// scalar storage.
+    ///
+    /// `rebuild_args` applies to the default field, any value left empty keeps its current
+    /// one, so this can switch the index type, the metric or the HNSW parameters of a live
+    /// database. Named fields are rebuilt with their own params. The resulting params of
+    /// the default field are kept in the storage and replace the configured ones when the
+    /// database is opened again.
+    pub async fn rebuild_index(&mut self, rebuild_args: VdbRebuildArgs) -> Result<(), DBError> {
+        // an ef_search given here replaces the tuned one, which is kept otherwise
+        let given_ef_search = rebuild_args.hnsw_params.as_ref().and_then(|p| p.ef_search);
+        let mut new_params = self.params.clone();
+        new_params.apply_rebuild_args(rebuild_args);
+
+        event!(
+            Level::INFO,
+            "Rebuilding vector index with params: {new_params:?}"
+        );
+
+        let mut fields = self.vector_field_params();
+        fields[0].1 = new_params.clone();
+        self.rebuild_fields(fields).await?;
+
+        // reopening the database applies them again, the configured ones no longer match
+        // the rebuilt index files
+        let rebuild_params = serde_json::to_vec(&VdbRebuildArgs {
+            index_type: Some(new_params.index_type.clone()),
+            metric_type: Some(new_params.metric_type.clone()),
+            hnsw_params: new_params.hnsw_params.clone(),
+            vamana_params: new_params.vamana_params.clone(),
+        })
+        .map_err(|e| DBError::PutError(format!("unable to encode rebuild params: {e}")))?;
+        self.scalar_storage
+            .put(&scalar::rebuild_params_key(), &rebuild_params)?;
+        if let Some(ef_search) = given_ef_search {
+            self.scalar_storage
+                .put(&scalar::ef_search_key(None), &ef_search.to_be_bytes())?;
+        }
+
+        self.params = new_params;
+        // the rebuilt indexes only hold the stored vectors, deleted docs included
+        self.deleted_ids.get_mut().unwrap().clear();
+
+        Ok(())
+    }
+
+    // Every vector field, the default one first, with the params of its index and
+    // whether it is a multi-vector field
+    fn vector_field_params(&self) -> Vec<(Option<String>, DatabaseParams, bool)> {
+        let mut fields = vec![(None, self.params.clone(), false)];
+        fields.extend(self.params.vector_fields.iter().flatten().map(|field| {
+            (
+                Some(field.name.clone()),
+                self.params.for_field(field),
+                field.is_multi_vector(),
+            )
+        }));
+
+        fields
+    }
+
+    // Builds new indexes for the given fields out of the stored vectors and swaps them in
+    async fn rebuild_fields(
+        &mut self,
+        fields: Vec<(Option<String>, DatabaseParams, bool)>,
+    ) -> Result<(), DBError> {
+        // persistent indexes are built apart and moved over the live files once every
+        // field is built, so a failed rebuild leaves the live indexes untouched
+        let rebuild_dir = self.db_path.join(REBUILD_DIR);
+        if rebuild_dir.exists() {
+            std::fs::remove_dir_all(&rebuild_dir).map_err(|e| {
+                DBError::CreateError(format!("unable to remove stale rebuild files: {e}"))
+            })?;
+        }
+        std::fs::create_dir_all(&rebuild_dir).map_err(|e| {
+            DBError::CreateError(format!("unable to create rebuild directory: {e}"))
+        })?;
+
+        let mut rebuilt = vec![];
+        for (field, params, is_multi_vector) in fields {
+            let scalar_storage = Arc::clone(&self.scalar_storage);
+            let rebuild_path = rebuild_dir.join(index_file_name(field.as_deref()));
+            let field_name = field.clone();
+
+            let (index, total) = task::spawn_blocking(move || {
+                let dim = params.dim as usize;
+                match field_name.as_deref() {
+                    Some(name) if is_multi_vector => {
+                        // token ids are kept as they are, so the multi-vector map stays valid
+                        let mut multi_vector_map = MultiVectorMap::new();
+                        for item in scalar_storage.token_parent_iter(name) {
+                            let (token, parent) = item?;
+                            multi_vector_map.insert(parent, token);
+                        }
+
+                        let vectors = token_vector_iter(
+                            scalar_storage.as_ref(),
+                            name,
+                            &multi_vector_map,
+                            dim,
+                        );
+                        build_index_from_storage(params, &rebuild_path, vectors)
+                    }
+                    Some(name) => build_index_from_storage(
+                        params,
+                        &rebuild_path,
+                        scalar_storage.field_vector_iter(name),
+                    ),
+                    None => build_index_from_storage(
+                        params,
+                        &rebuild_path,
+                        scalar_storage.vector_iter(),
+                    ),
+                }
+            })
+            .await
+            .map_err(|e| {
+                DBError::CreateError(format!(
+                    "error while rebuilding vector index asynchronously: {e}",
+                ))
+            })??;
+
+            event!(
+                Level::INFO,
+                "Rebuilt vector index of field {} with {total} vectors",
+                field.as_deref().unwrap_or("default")
+            );
+            rebuilt.push((field, index));
+        }
+
+        // rename the rebuilt files over the live ones, then drop the live files the
+        // rebuilt indexes have no use for, such as those of another index type
+        for (field, _) in &rebuilt {
+            let file_name = index_file_name(field.as_deref());
+            let rebuilt_files = list_index_files(&rebuild_dir, &file_name)?;
+
+            for (rebuilt_path, rest) in &rebuilt_files {
+                let live_path = self.db_path.join(format!("{file_name}{rest}"));
+                std::fs::rename(rebuilt_path, &live_path).map_err(|e| {
+                    DBError::CreateError(format!("unable to move rebuilt index in place: {e}"))
+                })?;
+            }
+
+            for (live_path, rest) in list_index_files(&self.db_path, &file_name)? {
+                if !rebuilt_files
+                    .iter()
+                    .any(|(_, rebuilt_rest)| *rebuilt_rest == rest)
+                {
+                    std::fs::remove_file(&live_path).map_err(|e| {
+                        DBError::CreateError(format!("unable to remove previous index file: {e}"))
+                    })?;
+                }
+            }
+        }
+        std::fs::remove_dir_all(&rebuild_dir).map_err(|e| {
+            DBError::CreateError(format!("unable to remove rebuild directory: {e}"))
+        })?;
+
+        for (field, index) in rebuilt {
+            match field {
+                Some(name) => {
+                    self.named_indexes.insert(name, index);
+                }
+                None => self.vector_index = index,
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn recover_database(&mut self) -> Result<(), DBError> {
+        event!(
+            Level::INFO,
+            "Recovering vector database from saved files..."
+        );
+
+        // persistent indexes outlive the process, and may have missed writes made to the
+        // storage right before a crash. Those out of step with the storage are rebuilt
+        let mut stale_fields = vec![];
+        for (field, params, is_multi_vector) in self.vector_field_params() {
+            if !params.index_type.is_persistent() {
+                continue;
+            }
+
+            let (index, _) = self.field_index(field.as_deref())?;
+            let indexed = index.lock().unwrap().count();
+            let stored = stored_vector_count(
+                self.scalar_storage.as_ref(),
+                field.as_deref(),
+                is_multi_vector,
+            )?;
+
+            if indexed != stored {
+                event!(
+                    Level::WARN,
+                    "vector index of field {} holds {indexed} vectors, {stored} are stored, rebuilding it",
+                    field.as_deref().unwrap_or("default")
+                );
+                stale_fields.push((field, params, is_multi_vector));
+            }
+        }
+        if !stale_fields.is_empty() {
+            self.rebuild_fields(stale_fields).await?;
+        }
+
+        let mut fields: Vec<Option<String>> = vec![None];
+        fields.extend(
+            self.named_indexes
+                .keys()
+                .filter(|name| !self.is_multi_vector_field(name))
+                .cloned()
+                .map(Some),
+        );
+
+        for field in fields {
+            let (vector_index, params) = self.field_index(field.as_deref())?;
+
+            // persistent indexes already hold every stored vector
+            if params.index_type.is_persistent() {
+                continue;
+            }
+
+            let scalar_storage = Arc::clone(&self.scalar_storage);
+            let dim = params.dim as usize;
+            let field_name = field.clone();
+
+            let total = task::spawn_blocking(move || {
+                let vectors = match &field_name {
+                    Some(name) => scalar_storage.field_vector_iter(name),
+                    None => scalar_storage.vector_iter(),
+                };
+                load_index_from_storage(vector_index.as_ref(), vectors, dim)
+            })
+            .await
+            .map_err(|e| {
+                DBError::CreateError(format!(
+                    "error while loading vector index asynchronously: {e}",
+                ))
+            })??;
+
+            event!(
+                Level::INFO,
+                "Loaded {total} stored vectors into vector index of field {}",
+                field.as_deref().unwrap_or("default")
+            );
+        }
+
+        let multi_vector_fields = self.multi_vector_maps.keys().cloned().collect::<Vec<_>>();
+        for field in multi_vector_fields {
+            self.load_multi_vectors(&field).await?;
+        }
+
+        // the sparse and keyword indexes only live in memory, refill them from storage
+        let sparse_index = Arc::clone(&self.sparse_index);
+        let scalar_storage = Arc::clone(&self.scalar_storage);
+        let total = task::spawn_blocking(move || {
+            load_sparse_index_from_storage(sparse_index.as_ref(), scalar_storage.as_ref())
+        })
+        .await
+        .map_err(|e| {
+            DBError::CreateError(format!(
+                "error while loading sparse index asynchronously: {e}",
+            ))
+        })??;
+
+        event!(
+            Level::INFO,
+            "Loaded {total} stored sparse vectors into sparse index"
+        );
+
+        if self.params.text_fields.is_some() {
+            let scalar_storage = Arc::clone(&self.scalar_storage);
+            for item in scalar_storage.doc_iter() {
+                let (id, doc) = item?;
+                self.insert_text(&doc, id);
+            }
+
+            event!(
+                Level::INFO,
+                "Loaded {} stored docs into text index",
+                self.text_index.read().unwrap().len()
+            );
+        }
+
+        let wal_record_iter = self
+            .persistence
+            .get_wal_iterator()
+            .await
+            .map_err(|e| DBError::CreateError(format!("Failed to get WAL iterator: {e}")))?;
+        for record in wal_record_iter {
+            match record {
+                Ok(record) => {
+                    // Apply the wal record to the database
+                    apply_wal_record(record, self).await.map_err(|e| {
+                        DBError::CreateError(format!("Failed to apply WAL record: {e}"))
+                    })?;
+                }
+                Err(e) => {
+                    event!(Level::ERROR, "Failed to read WAL record: {e}");
+
+                    return Err(DBError::CreateError(format!(
+                        "Failed to read WAL record: {e}"
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl VdbUpsertArgs {
+    fn validate(&self) -> (&str, usize, usize) {
+        if self.docs.len() != self.vectors.data_row {
+            return ("docs", self.docs.len(), self.vectors.data_row);
+        }
+
+        if !self.attributes.is_empty() && self.attributes.len() != self.vectors.data_row {
+            return ("attributes", self.attributes.len(), self.vectors.data_row);
+        }
+
+        if let Some(sparse_data) = &self.vectors.sparse_data {
+            if sparse_data.len() != self.vectors.data_row {
+                return ("sparse_data", sparse_data.len(), self.vectors.data_row);
+            }
+
+            if let Some(invalid) = sparse_data.iter().find(|v| !v.is_valid()) {
+                return (
+                    "sparse_data.values",
+                    invalid.values.len(),
+                    invalid.indices.len(),
+                );
+            }
+        }
+
+        let skips_default_field = self.vectors.flat_data.is_empty()
+            && (self
+                .vectors
+                .named_data
+                .as_ref()
+                .is_some_and(|named| !named.is_empty())
+                || self
+                    .vectors
+                    .multi_data
+                    .as_ref()
+                    .is_some_and(|multi| !multi.is_empty()));
+
+        if !skips_default_field
+            && self.vectors.data_dim * self.vectors.data_row != self.vectors.flat_data.len()
+        {
+            return (
+                "flat_data",
+                self.vectors.flat_data.len(),
+                self.vectors.data_dim * self.vectors.data_row,
+            );
+        }
+
+        ("", 0, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterOp;
+    use ndarray::array;
+    use serde_json::json;
+    use std::sync::Once;
+    use std::time::SystemTime;
+    use std::{fs, time::UNIX_EPOCH};
+    use tracing::span;
+    use tracing_subscriber::fmt;
+    use uuid::Uuid;
+
+    fn create_test_index_params(metric_type: MetricType, index_type: IndexType) -> DatabaseParams {
+        DatabaseParams {
+            dim: 3,
+            metric_type,
+            index_type,
+            hnsw_params: None,
+            vamana_params: None,
+            text_fields: Some(vec!["title".to_string(), "tags".to_string()]),
+            bm25_params: None,
+            vector_fields: None,
+            brute_force_selectivity: None,
+            shadow_sample_rate: None,
+            version: "0.1.0".to_string(),
+        }
+    }
+
+    struct TestPath {
+        db_path: PathBuf,
+    }
+
+    impl TestPath {
+        fn new() -> Self {
+            let db_path = PathBuf::from("/tmp/test_db")
+                .join(Uuid::new_v4().to_string())
+                .join(
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs()
+                        .to_string(),
+                );
+            fs::create_dir_all(&db_path).unwrap();
+            TestPath { db_path }
+        }
+    }
+
+    impl AsRef<Path> for TestPath {
+        fn as_ref(&self) -> &Path {
+            self.db_path.as_ref()
+        }
+    }
+
+    impl Drop for TestPath {
+        fn drop(&mut self) {
+            if fs::exists(&self.db_path).unwrap() {
+                std::fs::remove_dir_all(&self.db_path).unwrap();
+            }
+        }
+    }
+
+    fn standardize_vecs(
+        matrix: &Array<f32, ndarray::Dim<[usize; 2]>>,
+    ) -> Array<f32, ndarray::Dim<[usize; 2]>> {
+        let mut result = matrix.clone();
+
+        for i in 0..matrix.shape()[0] {
+            let row = matrix.row(i);
+            let norm = row.dot(&row).sqrt();
+
+            result.row_mut(i).assign(&row.map(|x| x / norm));
+        }
+
+        result
+    }
+
+    static INIT: Once = Once::new();
+
+    fn init_tracing(test_name: &str) -> tracing::Span {
+        INIT.call_once(|| {
+            fmt::Subscriber::builder()
+                .with_max_level(Level::DEBUG)
+                .with_span_events(fmt::format::FmtSpan::ENTER | fmt::format::FmtSpan::CLOSE)
+                .with_line_number(true)
+                .init();
+        });
+
+        span!(Level::DEBUG, "tracing for test", test_name)
+    }
+
+    macro_rules! vecdb_test_cases {
+        ($($name:ident: $index_type:expr, $metric_type: expr)*) => {
+        $(
+            mod $name {
+                use super::*;
+
+                #[test]
+                fn test_vector_database_new() {
+                    let index_params = create_test_index_params($metric_type, $index_type);
+
+                    let result = VectorDatabase::new(TestPath::new(), index_params);
+                    assert!(result.is_ok());
+                }
+
+                #[tokio::test]
+                async fn test_vector_database_upsert() {
+                    let span = init_tracing("test_vector_database_upsert");
+                    let _enter = span.enter();
+
+                    let index_params = create_test_index_params($metric_type, $index_type);
+                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3], [0.4, 0.5, 0.6]]);
+                    let doc = HashMap::from([(
+                        "key".to_string(),
+                        Value::String("value".to_string()),
+                    )]);
+
+                    let result = db.upsert(VdbUpsertArgs{
+                        vectors: VectorArgs {
+                            flat_data: data_array.iter().map(|x| *x).collect(),
+                            data_row: 2,
+                            data_dim: 3,
+                            sparse_data: None,
+                            named_data: None,
+                            multi_data: None,
+                        },
+                        docs: vec![Some(doc.clone()), Some(doc.clone())],
+                        attributes: vec![],
+                        hnsw_params: None,
+                    }).await;
+
+                    assert!(result.is_ok());
+                }
+
+                #[tokio::test]
+                async fn test_vector_database_query() {
+                    let span = init_tracing("test_vector_database_query");
+                    let _enter = span.enter();
+
+                    let index_params = create_test_index_params($metric_type, $index_type);
+                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+                    let doc1 = HashMap::from([(
+                        "key".to_string(),
+                        Value::String("value1".to_string()),
+                    )]);
+
+                    let doc2 = HashMap::from([(
+                        "key".to_string(),
+                        Value::String("value2".to_string()),
+                    )]);
+
+                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3], [-0.1, 0.2, -0.3]]);
+                    let res = db.upsert(VdbUpsertArgs{
+                        vectors: VectorArgs {
+                           flat_data: data_array.iter().map(|x| *x).collect(),
+                            data_row: 2,
+                            data_dim: 3,
+                            sparse_data: None,
+                            named_data: None,
+                            multi_data: None,
+                        },
+                        docs: vec![Some(doc1.clone()), Some(doc2)],
+                        attributes: vec![],
+                        hnsw_params: None,
+                    }).await;
+
+                    assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+                    let mut search_args =  VdbSearchArgs{
+                        query: vec![0.1, 0.2, 0.3],
+                        k: 10,
+                        filter_inputs: None,
+                        hnsw_params: None,
+                        ..Default::default()
+                    };
+                    if $index_type == IndexType::Hnsw {
+                        search_args.hnsw_params = Some(HnswSearchOption {
+                            ef_search: 200,
+                        });
+                    }
+                    let result = db.query(search_args).await;
+
+                    assert!(result.is_ok());
+                    let docs_result = result.unwrap();
+
+                    if $index_type != IndexType::Hnsw {
+                        assert_eq!(docs_result.len(), 2);
+                        assert_eq!(doc1.get("key"), docs_result[0].get("key"))
+                    } else {
+                        // HNSW index does not guarantee the number of results
+                        assert!(!docs_result.is_empty());
+                    }
+                }
+
+                #[tokio::test]
+                async fn test_vector_database_query_with_vectors() {
+                    let span = init_tracing("test_vector_database_query_with_vectors");
+                    let _enter = span.enter();
+
+                    let index_params = create_test_index_params($metric_type, $index_type);
+                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3]]);
+                    let flat_data: Vec<f32> = data_array.iter().map(|x| *x).collect();
+                    let res = db.upsert(VdbUpsertArgs{
+                        vectors: VectorArgs {
+                            flat_data: flat_data.clone(),
+                            data_row: 1,
+                            data_dim: 3,
+                            sparse_data: None,
+                            named_data: None,
+                            multi_data: None,
+                        },
+                        docs: vec![None],
+                        attributes: vec![],
+                        hnsw_params: None,
+                    }).await;
+                    assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+                    let mut search_args = VdbSearchArgs {
+                        query: vec![0.1, 0.2, 0.3],
+                        k: 1,
+                        with_vectors: Some(true),
+                        ..Default::default()
+                    };
+                    if $index_type == IndexType::Hnsw {
+                        search_args.hnsw_params = Some(HnswSearchOption {
+                            ef_search: 200,
+                        });
+                    }
+                    let docs_result = db.query(search_args).await.unwrap();
+
+                    assert_eq!(docs_result.len(), 1);
+                    assert_eq!(
+                        docs_result[0].get("vector"),
+                        Some(&serde_json::to_value(&flat_data).unwrap())
+                    );
+                }
+
+                #[tokio::test]
+                async fn test_vector_database_rebuild_index() {
+                    let span = init_tracing("test_vector_database_rebuild_index");
+                    let _enter = span.enter();
+
+                    // keep the directory alive, rebuilding writes index files into it
+                    let test_path = TestPath::new();
+                    let index_params = create_test_index_params($metric_type, $index_type);
+                    let mut db = VectorDatabase::new(&test_path, index_params).unwrap();
+
+                    let doc1 = HashMap::from([(
+                        "key".to_string(),
+                        Value::String("value1".to_string()),
+                    )]);
+
+                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3], [-0.1, 0.2, -0.3]]);
+                    let res = db.upsert(VdbUpsertArgs{
+                        vectors: VectorArgs {
+                            flat_data: data_array.iter().map(|x| *x).collect(),
+                            data_row: 2,
+                            data_dim: 3,
+                            sparse_data: None,
+                            named_data: None,
+                            multi_data: None,
+                        },
+                        docs: vec![Some(doc1.clone()), None],
+                        attributes: vec![],
+                        hnsw_params: None,
+                    }).await;
+                    assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+                    // switch to the other index type, the stored vectors must be carried over
+                    let rebuild_type = if $index_type != IndexType::Hnsw {
+                        IndexType::Hnsw
+                    } else {
+                        IndexType::Flat
+                    };
+                    let res = db.rebuild_index(VdbRebuildArgs {
+                        index_type: Some(rebuild_type.clone()),
+                        ..Default::default()
+                    }).await;
+                    assert!(res.is_ok(), "rebuild failed: {:?}", res.err().unwrap());
+                    assert_eq!(db.params.index_type, rebuild_type);
+
+                    let search_args = VdbSearchArgs {
+                        query: vec![0.1, 0.2, 0.3],
+                        k: 1,
+                        hnsw_params: Some(HnswSearchOption {
+                            ef_search: 200,
+                        }),
+                        ..Default::default()
+                    };
+                    let docs_result = db.query(search_args).await.unwrap();
+
+                    assert_eq!(docs_result.len(), 1);
+                    assert_eq!(doc1.get("key"), docs_result[0].get("key"));
+                }
+
+                #[tokio::test]
+                async fn test_vector_database_upsert_with_wrong_dim() {
+                    let index_params = create_test_index_params($metric_type, $index_type);
+                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3, 0.4], [0.4, 0.5, 0.6, 0.7]]);
+                    let result = db.upsert(VdbUpsertArgs{
+                        vectors: VectorArgs {
+                            flat_data: data_array.iter().map(|x| *x).collect(),
+                            data_row: 2,
+                            data_dim: 4,
+                            sparse_data: None,
+                            named_data: None,
+                            multi_data: None,
+                        },
+                        docs: vec![None, None],
+                        attributes: vec![],
+                        hnsw_params: None,
+                    }).await;
+
+                    assert!(matches!(result, Err(DBError::ValidationError(_))));
+
+                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3], [0.4, 0.5, 0.6]]);
+                    let result = db.upsert(VdbUpsertArgs{
+                        vectors: VectorArgs {
+                            flat_data: data_array.iter().map(|x| *x).collect(),
+                            data_row: 3,
+                            data_dim: 3,
+                            sparse_data: None,
+                            named_data: None,
+                            multi_data: None,
+                        },
+                        docs: vec![None, None, None],
+                        attributes: vec![],
+                        hnsw_params: None,
+                    }).await;
+
+                    assert!(result.is_err());
+                }
+
+                #[tokio::test]
+                async fn test_vector_database_query_with_no_results() {
+                    let span = init_tracing("test_vector_database_query_with_no_results");
+                    let _enter = span.enter();
+
+                    let index_params = create_test_index_params($metric_type, $index_type);
+                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+                    let mut search_args = VdbSearchArgs {
+                        query: vec![0.1, 0.2, 0.3],
+                        k: 1,
+                        filter_inputs: None,
+                        hnsw_params: None,
+                        ..Default::default()
+                    };
+                    if $index_type == IndexType::Hnsw {
+                        search_args.hnsw_params = Some(HnswSearchOption {
+                            ef_search: 200,
+                        });
+                    }
+                    let result = db.query(search_args).await;
+
+                    assert!(result.is_ok());
+                    assert!(result.unwrap().is_empty());
+                }
+
+                #[tokio::test]
+                async fn test_vector_database_query_with_filter() {
+                    let span = init_tracing("test_vector_database_query_with_filter");
+                    let _enter = span.enter();
+
+                    let index_params = create_test_index_params($metric_type, $index_type);
+                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+                    let doc1 = HashMap::from([(
+                        "key".to_string(),
+                        Value::String("value1".to_string()),
+                    )]);
+
+                    let doc2 = HashMap::from([(
+                        "key".to_string(),
+                        Value::String("value2".to_string()),
+                    )]);
+
+                    let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3], [0.1, -0.2, 0.3]]);
+                    let res = db.upsert(VdbUpsertArgs{
+                        vectors: VectorArgs {
+                            flat_data: data_array.iter().map(|x| *x).collect(),
+                            data_row: 2,
+                            data_dim: 3,
+                            sparse_data: None,
+                            named_data: None,
+                            multi_data: None,
+                        },
+                        docs: vec![Some(doc1.clone()), Some(doc2.clone())],
+                        attributes: vec![
+                            Some(HashMap::from([("age".to_string(), Value::Number(10.into()))])),
+                            Some(HashMap::from([("age".to_string(), Value::Number(20.into()))])),
+                        ],
+                        hnsw_params: None,
+                    }).await;
+
+                    assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+                    let mut search_args = VdbSearchArgs {
+                        query: vec![0.1, 0.2, 0.3],
+                        k: 10,
+                        filter_inputs: Some(vec![IntFilterInput {
+                            field: "age".to_string(),
+                            op: FilterOp::Equal,
+                            target: 20,
+                        }]),
+                        hnsw_params: None,
+                        ..Default::default()
+                    };
+
+                    if $index_type == IndexType::Hnsw {
+                        search_args.hnsw_params = Some(HnswSearchOption {
+                            ef_search: 200,
+                        });
+                    }
+
+                    // without filter, the first doc should be returned
+                    // filter the first doc, then only the second doc should be returned
+                    let result = db.query(search_args.clone()).await;
+
+                    assert!(result.is_ok());
+                    let docs_result = result.unwrap();
+                    assert_eq!(docs_result.len(), 1);
+                    if $index_type != IndexType::Hnsw {
+                        assert_eq!(doc2.get("key"), docs_result[0].get("key"));
+                    } else {
+                        // HNSW index does not guarantee the number of results
+                        assert!(!docs_result.is_empty());
+                    }
+
+                    // test with not equal filter
+                    search_args.filter_inputs = Some(vec![IntFilterInput {
+                        field: "age".to_string(),
+                        op: FilterOp::NotEqual,
+                        target: 20,
+                    }]);
+                    let result = db.query(search_args).await;
+
+                    assert!(result.is_ok());
+                    let docs_result = result.unwrap();
+                    assert_eq!(docs_result.len(), 1);
+                    if $index_type != IndexType::Hnsw {
+                        assert_eq!(doc1.get("key"), docs_result[0].get("key"));
+                    } else {
+                        // HNSW index does not guarantee the number of results
+                        assert!(!docs_result.is_empty());
+                    }
+                }
+
+                #[tokio::test]
+                async fn test_vector_database_hybrid_query() {
+                    let span = init_tracing("test_vector_database_hybrid_query");
+                    let _enter = span.enter();
+
+                    let index_params = create_test_index_params($metric_type, $index_type);
+                    let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+                    let docs = [
+                        json!({"title": "An introduction to vector search"}),
+                        json!({"title": "Cooking pasta", "tags": ["food", "italian"]}),
+                        json!({"title": "A vector database written in Rust"}),
+                    ];
+                    let data_array = standardize_vecs(&array![
+                        [0.1, 0.2, 0.3],
+                        [0.1, -0.2, 0.3],
+                        [-0.1, 0.2, -0.3]
+                    ]);
+                    let res = db.upsert(VdbUpsertArgs{
+                        vectors: VectorArgs {
+                            flat_data: data_array.iter().map(|x| *x).collect(),
+                            data_row: 3,
+                            data_dim: 3,
+                            sparse_data: None,
+                            named_data: None,
+                            multi_data: None,
+                        },
+                        docs: docs
+                            .iter()
+                            .map(|d| serde_json::from_value(d.clone()).unwrap())
+                            .collect(),
+                        attributes: [10, 20, 10]
+                            .iter()
+                            .map(|age| Some(HashMap::from([("age".to_string(), Value::Number((*age).into()))])))
+                            .collect(),
+                        hnsw_params: None,
+                    }).await;
+                    assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+                    let doc_ids = |docs: &[DocMap]| {
+                        let mut ids = docs
+                            .iter()
+                            .map(|d| d.get("id").unwrap().as_u64().unwrap())
+                            .collect::<Vec<_>>();
+                        ids.sort();
+                        ids
+                    };
+
+                    // keyword only search
+                    let search_args = VdbSearchArgs {
+                        k: 10,
+                        text_query: Some("vector".to_string()),
+                        ..Default::default()
+                    };
+                    let docs_result = db.query(search_args.clone()).await.unwrap();
+                    assert_eq!(doc_ids(&docs_result), vec![1, 3]);
+                    assert!(docs_result.iter().all(|d| d.contains_key("score")));
+
+                    // filters apply to the keyword side as well
+                    let filter_inputs = Some(vec![IntFilterInput {
+                        field: "age".to_string(),
+                        op: FilterOp::Equal,
+                        target: 20,
+                    }]);
+                    let docs_result = db.query(VdbSearchArgs {
+                        filter_inputs: filter_inputs.clone(),
+                        ..search_args.clone()
+                    }).await.unwrap();
+                    assert!(docs_result.is_empty());
+                    let docs_result = db.query(VdbSearchArgs {
+                        filter_inputs,
+                        text_query: Some("Italian".to_string()),
+                        ..search_args
+                    }).await.unwrap();
+                    assert_eq!(doc_ids(&docs_result), vec![2]);
+
+                    // hybrid search fuses both rankings
+                    let mut search_args = VdbSearchArgs {
+                        query: vec![0.1, 0.2, 0.3],
+                        k: 2,
+                        text_query: Some("rust".to_string()),
+                        fusion: Some(FusionMethod::Rrf { k: 60.0 }),
+                        ..Default::default()
+                    };
+                    if $index_type == IndexType::Hnsw {
+                        search_args.hnsw_params = Some(HnswSearchOption {
+                            ef_search: 200,
+                        });
+                    }
+                    let docs_result = db.query(search_args.clone()).await.unwrap();
+                    assert_eq!(docs_result.len(), 2);
+                    assert!(doc_ids(&docs_result).contains(&3));
+                    if $index_type != IndexType::Hnsw {
+                        assert_eq!(doc_ids(&docs_result), vec![1, 3]);
+                    }
+
+                    // a weight of 1 only keeps the vector ranking
+                    search_args.k = 1;
+                    search_args.fusion = Some(FusionMethod::Weighted { vector_weight: 1.0 });
+                    let docs_result = db.query(search_args).await.unwrap();
+                    assert_eq!(docs_result.len(), 1);
+                    if $index_type != IndexType::Hnsw {
+                        assert_eq!(doc_ids(&docs_result), vec![1]);
+                    }
+                }
+            }
+        )*
+        };
+    }
+
+    #[tokio::test]
+    async fn test_sparse_vector_query() {
+        let index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+        let sparse = |pairs: &[(u32, f32)]| SparseVector {
+            indices: pairs.iter().map(|(i, _)| *i).collect(),
+            values: pairs.iter().map(|(_, v)| *v).collect(),
+        };
+
+        let data_array = standardize_vecs(&array![
+            [0.1, 0.2, 0.3],
+            [0.1, -0.2, 0.3],
+            [-0.1, 0.2, -0.3]
+        ]);
+        let mut upsert_args = VdbUpsertArgs {
+            vectors: VectorArgs {
+                flat_data: data_array.iter().copied().collect(),
+                data_row: 3,
+                data_dim: 3,
+                sparse_data: Some(vec![sparse(&[(10, 1.0)]), sparse(&[(10, 0.5), (20, 1.0)])]),
+                named_data: None,
+                multi_data: None,
+            },
+            docs: vec![None, None, None],
+            attributes: vec![],
+            hnsw_params: None,
+        };
+
+        // every row needs a sparse vector once they are given
+        assert!(db.upsert(upsert_args.clone()).await.is_err());
+
+        upsert_args.vectors.sparse_data = Some(vec![
+            sparse(&[(10, 1.0)]),
+            sparse(&[(10, 0.5), (20, 1.0)]),
+            sparse(&[(20, 3.0)]),
+        ]);
+        let res = db.upsert(upsert_args).await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        let doc_id = |doc: &DocMap| doc.get("id").unwrap().as_u64().unwrap();
+
+        // sparse only search
+        let docs_result = db
+            .query(VdbSearchArgs {
+                k: 10,
+                sparse_query: Some(sparse(&[(20, 1.0)])),
+                with_vectors: Some(true),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            docs_result.iter().map(doc_id).collect::<Vec<_>>(),
+            vec![3, 2]
+        );
+        assert_eq!(
+            docs_result[0].get("sparse_vector").unwrap(),
+            &json!({"indices": [20], "values": [3.0]})
+        );
+
+        // combined with the dense ranking
+        let docs_result = db
+            .query(VdbSearchArgs {
+                query: vec![0.1, 0.2, 0.3],
+                k: 1,
+                sparse_query: Some(sparse(&[(20, 1.0)])),
+                fusion: Some(FusionMethod::Weighted { vector_weight: 0.9 }),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(docs_result.iter().map(doc_id).collect::<Vec<_>>(), vec![1]);
+        assert!(docs_result[0].contains_key("score"));
+    }
+
+    #[tokio::test]
+    async fn test_recommend() {
+        let index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+        let res = db
+            .upsert(VdbUpsertArgs {
+                vectors: VectorArgs {
+                    flat_data: vec![
+                        1.0, 0.0, 0.0, //
+                        0.9, 0.1, 0.0, //
+                        0.0, 1.0, 0.0, //
+                        0.0, 0.9, 0.1, //
+                        0.0, 0.0, 1.0,
+                    ],
+                    data_row: 5,
+                    data_dim: 3,
+                    sparse_data: None,
+                    named_data: None,
+                    multi_data: None,
+                },
+                docs: vec![None; 5],
+                attributes: (0..5)
+                    .map(|i| Some(HashMap::from([("age".to_string(), json!(i * 10))])))
+                    .collect(),
+                hnsw_params: None,
+            })
+            .await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        let doc_ids = |docs: Vec<DocMap>| {
+            docs.iter()
+                .map(|doc| doc.get("id").unwrap().as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // the example itself is never returned
+        let docs_result = db
+            .recommend(VdbRecommendArgs {
+                positive: vec![1],
+                k: 2,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(doc_ids(docs_result), vec![2, 4]);
+
+        for strategy in [
+            RecommendStrategy::AverageVector,
+            RecommendStrategy::BestScore,
+        ] {
+            let docs_result = db
+                .recommend(VdbRecommendArgs {
+                    positive: vec![1],
+                    negative: Some(vec![3]),
+                    k: 10,
+                    strategy: Some(strategy),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            assert!(docs_result.iter().all(|doc| doc.contains_key("score")));
+            assert_eq!(doc_ids(docs_result), vec![2, 5, 4], "{strategy:?}");
+        }
+
+        // attribute filters are honoured
+        let docs_result = db
+            .recommend(VdbRecommendArgs {
+                positive: vec![1],
+                k: 10,
+                filter_inputs: Some(vec![IntFilterInput {
+                    field: "age".to_string(),
+                    op: FilterOp::NotEqual,
+                    target: 10,
+                }]),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(doc_ids(docs_result), vec![4, 3, 5]);
+
+        assert!(matches!(
+            db.recommend(VdbRecommendArgs::default()).await,
+            Err(DBError::ValidationError(_))
+        ));
+        assert!(matches!(
+            db.recommend(VdbRecommendArgs {
+                positive: vec![42],
+                k: 1,
+                ..Default::default()
+            })
+            .await,
+            Err(DBError::NotFoundError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_mmr_query() {
+        let index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+        let res = db
+            .upsert(VdbUpsertArgs {
+                vectors: VectorArgs {
+                    flat_data: vec![
+                        1.0, 0.0, 0.0, //
+                        0.99, 0.01, 0.0, //
+                        0.0, 1.0, 0.0,
+                    ],
+                    data_row: 3,
+                    data_dim: 3,
+                    sparse_data: None,
+                    named_data: None,
+                    multi_data: None,
+                },
+                docs: vec![None; 3],
+                attributes: vec![],
+                hnsw_params: None,
+            })
+            .await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        let doc_ids = |docs: Vec<DocMap>| {
+            docs.iter()
+                .map(|doc| doc.get("id").unwrap().as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let mut search_args = VdbSearchArgs {
+            query: vec![1.0, 0.0, 0.0],
+            k: 2,
+            ..Default::default()
+        };
+        let docs_result = db.query(search_args.clone()).await.unwrap();
+        assert_eq!(doc_ids(docs_result), vec![1, 2]);
+
+        // the near duplicate gives way to the more diverse doc
+        search_args.mmr = Some(MmrParams {
+            lambda: 0.3,
+            candidates: None,
+        });
+        let docs_result = db.query(search_args.clone()).await.unwrap();
+        assert_eq!(doc_ids(docs_result), vec![1, 3]);
+
+        // the pool never holds less than k candidates
+        search_args.mmr = Some(MmrParams {
+            lambda: 0.3,
+            candidates: Some(1),
+        });
+        let docs_result = db.query(search_args.clone()).await.unwrap();
+        assert_eq!(docs_result.len(), 2);
+
+        search_args.mmr = Some(MmrParams {
+            lambda: 1.5,
+            candidates: None,
+        });
+        assert!(db.query(search_args).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_group_by_query() {
+        let index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+        // the last doc is the closest one but has no group
+        let groups = [Some(1), Some(1), Some(1), Some(2), Some(3), Some(2), None];
+        let res = db
+            .upsert(VdbUpsertArgs {
+                vectors: VectorArgs {
+                    flat_data: (1..=7)
+                        .flat_map(|i| vec![1.0, 0.1 * (i % 7) as f32, 0.0])
+                        .collect(),
+                    data_row: 7,
+                    data_dim: 3,
+                    sparse_data: None,
+                    named_data: None,
+                    multi_data: None,
+                },
+                docs: vec![None; 7],
+                attributes: groups
+                    .iter()
+                    .map(|group| group.map(|g| HashMap::from([("doc".to_string(), json!(g))])))
+                    .collect(),
+                hnsw_params: None,
+            })
+            .await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        let mut search_args = VdbSearchArgs {
+            query: vec![1.0, 0.0, 0.0],
+            group_by: Some(GroupByParams {
+                field: "doc".to_string(),
+                group_size: 2,
+                limit: 2,
+            }),
+            ..Default::default()
+        };
+
+        // the second group is only filled after fetching more hits
+        let result = db.query_groups(search_args.clone()).await.unwrap();
+        let group_ids = result
+            .iter()
+            .map(|group| {
+                let ids = group
+                    .hits
+                    .iter()
+                    .map(|doc| doc.get("id").unwrap().as_u64().unwrap())
+                    .collect::<Vec<_>>();
+                (group.group, ids)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(group_ids, vec![(1, vec![1, 2]), (2, vec![4, 6])]);
+        assert!((result[1].score + 0.16).abs() < 1e-5);
+        assert!(result[1].hits.iter().all(|doc| doc.contains_key("score")));
+
+        // groups are returned even if they cannot be filled
+        search_args.group_by = Some(GroupByParams {
+            field: "doc".to_string(),
+            group_size: 3,
+            limit: 5,
+        });
+        let result = db.query_groups(search_args.clone()).await.unwrap();
+        assert_eq!(
+            result
+                .iter()
+                .map(|group| (group.group, group.hits.len()))
+                .collect::<Vec<_>>(),
+            vec![(1, 3), (2, 2), (3, 1)]
+        );
+
+        assert!(db.query(search_args).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_paged_query() {
+        let index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+        // two pairs of docs with tied distances
+        let res = db
+            .upsert(VdbUpsertArgs {
+                vectors: VectorArgs {
+                    flat_data: vec![
+                        0.0, 1.0, 0.0, //
+                        1.0, 0.0, 0.0, //
+                        0.9, 0.1, 0.0, //
+                        0.0, 1.0, 0.0, //
+                        1.0, 0.0, 0.0,
+                    ],
+                    data_row: 5,
+                    data_dim: 3,
+                    sparse_data: None,
+                    named_data: None,
+                    multi_data: None,
+                },
+                docs: vec![None; 5],
+                attributes: vec![],
+                hnsw_params: None,
+            })
+            .await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        let doc_ids = |docs: &[DocMap]| {
+            docs.iter()
+                .map(|doc| doc.get("id").unwrap().as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let mut search_args = VdbSearchArgs {
+            query: vec![1.0, 0.0, 0.0],
+            k: 2,
+            ..Default::default()
+        };
+
+        let mut pages = vec![];
+        loop {
+            let page = db.query_page(search_args.clone()).await.unwrap();
+            pages.push(doc_ids(&page.results));
+
+            match page.next_cursor {
+                Some(cursor) => search_args.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(pages, vec![vec![2, 5], vec![3, 1], vec![4]]);
+
+        search_args.cursor = None;
+        search_args.offset = Some(1);
+        let docs_result = db.query(search_args.clone()).await.unwrap();
+        assert_eq!(doc_ids(&docs_result), vec![5, 3]);
+
+        search_args.cursor = Some("not a cursor".to_string());
+        assert!(db.query(search_args).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scroll_count_and_facet() {
+        let index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+        let res = db
+            .upsert(VdbUpsertArgs {
+                vectors: VectorArgs {
+                    flat_data: (0..15).map(|i| i as f32).collect(),
+                    data_row: 5,
+                    data_dim: 3,
+                    sparse_data: None,
+                    named_data: None,
+                    multi_data: None,
+                },
+                docs: vec![None; 5],
+                attributes: (1..=5)
+                    .map(|i| Some(HashMap::from([("odd".to_string(), json!(i % 2))])))
+                    .collect(),
+                hnsw_params: None,
+            })
+            .await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        let doc_ids = |docs: &[DocMap]| {
+            docs.iter()
+                .map(|doc| doc.get("id").unwrap().as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let mut scroll_args = VdbScrollArgs {
+            limit: 2,
+            ..Default::default()
+        };
+        let mut pages = vec![];
+        loop {
+            let page = db.scroll(scroll_args.clone()).unwrap();
+            pages.push(doc_ids(&page.results));
+
+            match page.next_cursor {
+                Some(cursor) => scroll_args.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(pages, vec![vec![1, 2], vec![3, 4], vec![5]]);
+
+        let page = db
+            .scroll(VdbScrollArgs {
+                limit: 2,
+                cursor: None,
+                filter_inputs: Some(vec![IntFilterInput {
+                    field: "odd".to_string(),
+                    op: FilterOp::Equal,
+                    target: 1,
+                }]),
+                with_vectors: Some(true),
+            })
+            .unwrap();
+        assert_eq!(doc_ids(&page.results), vec![1, 3]);
+        assert_eq!(page.next_cursor, Some(5));
+        assert_eq!(
+            page.results[1].get("vector").unwrap(),
+            &json!([6.0, 7.0, 8.0])
+        );
+
+        assert!(db.scroll(VdbScrollArgs::default()).is_err());
+
+        let count = db.count(VdbCountArgs::default()).unwrap();
+        assert_eq!((count.count, count.total), (5, 5));
+
+        let count = db
+            .count(VdbCountArgs {
+                filter_inputs: Some(vec![IntFilterInput {
+                    field: "odd".to_string(),
+                    op: FilterOp::Equal,
+                    target: 0,
+                }]),
+            })
+            .unwrap();
+        assert_eq!((count.count, count.total), (2, 5));
+
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.doc_count, 5);
+        assert_eq!(stats.params.index_type, IndexType::Flat);
+        assert_eq!(stats.memory_estimate_bytes, 5 * 3 * 4);
+
+        let facets = db
+            .facet(VdbFacetArgs {
+                fields: vec!["odd".to_string(), "missing".to_string()],
+                filter_inputs: None,
+                limit: None,
+            })
+            .unwrap();
+        assert_eq!(
+            facets["odd"],
+            vec![
+                FacetCount { value: 1, count: 3 },
+                FacetCount { value: 0, count: 2 }
+            ]
+        );
+        assert!(facets["missing"].is_empty());
+
+        let facets = db
+            .facet(VdbFacetArgs {
+                fields: vec!["odd".to_string()],
+                filter_inputs: Some(vec![IntFilterInput {
+                    field: "odd".to_string(),
+                    op: FilterOp::Equal,
+                    target: 0,
+                }]),
+                limit: Some(1),
+            })
+            .unwrap();
+        assert_eq!(facets["odd"], vec![FacetCount { value: 0, count: 2 }]);
+    }
+
+    #[tokio::test]
+    async fn test_selective_filter_query() {
+        let index_params = create_test_index_params(MetricType::L2, IndexType::Hnsw);
+        let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+        let vector = |i: u64| vec![i as f32, ((i * 7) % 13) as f32, ((i * 3) % 5) as f32];
+        let tagged = [5, 50, 95];
+
+        let res = db
+            .upsert(VdbUpsertArgs {
+                vectors: VectorArgs {
+                    flat_data: (1..=100).flat_map(vector).collect(),
+                    data_row: 100,
+                    data_dim: 3,
+                    sparse_data: None,
+                    named_data: None,
+                    multi_data: None,
+                },
+                docs: vec![None; 100],
+                attributes: (1..=100)
+                    .map(|i| {
+                        Some(HashMap::from([(
+                            "tag".to_string(),
+                            json!(tagged.contains(&i) as i64),
+                        )]))
+                    })
+                    .collect(),
+                hnsw_params: None,
+            })
+            .await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        // 3 docs out of 100 match, below the default selectivity threshold
+        let search_args = VdbSearchArgs {
+            query: vector(50),
+            k: 10,
+            filter_inputs: Some(vec![IntFilterInput {
+                field: "tag".to_string(),
+                op: FilterOp::Equal,
+                target: 1,
+            }]),
+            hnsw_params: Some(HnswSearchOption { ef_search: 16 }),
+            ..Default::default()
+        };
+        let docs_result = db.query(search_args).await.unwrap();
+        let ids = docs_result
+            .iter()
+            .map(|doc| doc.get("id").unwrap().as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[0], 50);
+        assert!(ids.iter().all(|id| tagged.contains(id)));
+    }
+
+    #[tokio::test]
+    async fn test_filter_selectivity_scores() {
+        for (metric_type, index_type) in [
+            (MetricType::L2, IndexType::Hnsw),
+            (MetricType::IP, IndexType::Hnsw),
+            (MetricType::L2, IndexType::Flat),
+            (MetricType::IP, IndexType::MmapFlat),
+        ] {
+            let index_params = create_test_index_params(metric_type, index_type);
+            let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+            // unit vectors, as hnsw requires for the inner product
+            let data = Array::from_shape_fn((40, 3), |(i, j)| ((i * 3 + j) as f32).sin() + 1.5);
+            let res = db
+                .upsert(VdbUpsertArgs {
+                    vectors: VectorArgs {
+                        flat_data: standardize_vecs(&data).iter().copied().collect(),
+                        data_row: 40,
+                        data_dim: 3,
+                        sparse_data: None,
+                        named_data: None,
+                        multi_data: None,
+                    },
+                    docs: vec![None; 40],
+                    attributes: vec![],
+                    hnsw_params: None,
+                })
+                .await;
+            assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+            let query = standardize_vecs(&array![[0.2, 0.5, 0.8]]).row(0).to_vec();
+            let search_args = VdbSearchArgs::default();
+
+            // a single allowed doc is scanned, all of them go through the index
+            let selective = RoaringBitmap::from([5]);
+            let scanned = db
+                .dense_search(None, &search_args, query.clone(), Some(&selective), 1)
+                .await
+                .unwrap();
+            let unselective = RoaringBitmap::from_iter(1..=40);
+            let searched = db
+                .dense_search(None, &search_args, query, Some(&unselective), 40)
+                .await
+                .unwrap();
+
+            assert_eq!(scanned.len(), 1);
+            let (_, searched_score) = searched.iter().find(|(id, _)| *id == 5).unwrap();
+            assert!(
+                (scanned[0].1 - searched_score).abs() < 1e-5,
+                "{:?}: scanned score {} != searched score {searched_score}",
+                db.params.index_type,
+                scanned[0].1
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tune_ef_search() {
+        let db_path = TestPath::new();
+        let index_params = create_test_index_params(MetricType::L2, IndexType::Hnsw);
+        let mut db = VectorDatabase::new(&db_path, index_params.clone()).unwrap();
+
+        let res = db
+            .upsert(VdbUpsertArgs {
+                vectors: VectorArgs {
+                    flat_data: (0..200)
+                        .flat_map(|i: u32| {
+                            vec![(i % 10) as f32, ((i / 10) % 5) as f32, (i / 50) as f32]
+                        })
+                        .collect(),
+                    data_row: 200,
+                    data_dim: 3,
+                    sparse_data: None,
+                    named_data: None,
+                    multi_data: None,
+                },
+                docs: vec![None; 200],
+                attributes: vec![],
+                hnsw_params: None,
+            })
+            .await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        // the deleted docs stay in the hnsw graph, they must not count as misses
+        let deleted = (1..=200).step_by(2).collect::<Vec<u64>>();
+        assert_eq!(db.delete(&deleted).await.unwrap(), 100);
+
+        let result = db
+            .tune_ef_search(VdbTuneArgs {
+                k: 5,
+                recall_target: Some(0.9),
+                sample_size: Some(20),
+                vector_field: None,
+            })
+            .await
+            .unwrap();
+        assert!(result.recall >= 0.9);
+        assert!(result.ef_search >= 5);
+        assert_eq!(
+            db.params.hnsw_params.as_ref().unwrap().ef_search,
+            Some(result.ef_search)
+        );
+
+        // queries no longer need to set ef_search
+        let docs_result = db
+            .query(VdbSearchArgs {
+                query: vec![0.0, 0.0, 0.0],
+                k: 5,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(docs_result.len(), 5);
+
+        // the tuned value outlives the process
+        drop(db);
+        let db = VectorDatabase::new(&db_path, index_params).unwrap();
+        assert_eq!(
+            db.params.hnsw_params.as_ref().unwrap().ef_search,
+            Some(result.ef_search)
+        );
+
+        let mut flat_db = VectorDatabase::new(
+            TestPath::new(),
+            create_test_index_params(MetricType::L2, IndexType::Flat),
+        )
+        .unwrap();
+        assert!(matches!(
+            flat_db
+                .tune_ef_search(VdbTuneArgs {
+                    k: 5,
+                    ..Default::default()
+                })
+                .await,
+            Err(DBError::ConflictError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_and_delete() {
+        for index_type in [IndexType::Flat, IndexType::Hnsw] {
+            let index_params = create_test_index_params(MetricType::L2, index_type);
+            let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+            let res = db
+                .upsert(VdbUpsertArgs {
+                    vectors: VectorArgs {
+                        flat_data: (0..12).map(|i| i as f32).collect(),
+                        data_row: 4,
+                        data_dim: 3,
+                        sparse_data: None,
+                        named_data: None,
+                        multi_data: None,
+                    },
+                    docs: vec![None; 4],
+                    attributes: (1..=4)
+                        .map(|i| Some(HashMap::from([("odd".to_string(), json!(i % 2))])))
+                        .collect(),
+                    hnsw_params: None,
+                })
+                .await;
+            assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+            let doc_ids = |docs: &[DocMap]| {
+                docs.iter()
+                    .map(|doc| doc.get("id").unwrap().as_u64().unwrap())
+                    .collect::<Vec<_>>()
+            };
+
+            let docs = db.get(&[3, 42, 1], true).unwrap();
+            assert_eq!(doc_ids(&docs), vec![3, 1]);
+            assert_eq!(docs[0].get("vector").unwrap(), &json!([6.0, 7.0, 8.0]));
+
+            assert_eq!(db.delete(&[1, 1, 42]).await.unwrap(), 1);
+            assert_eq!(db.delete(&[1]).await.unwrap(), 0);
+            assert!(db.get(&[1], false).unwrap().is_empty());
+
+            let docs = db
+                .query(VdbSearchArgs {
+                    query: vec![0.0, 1.0, 2.0],
+                    k: 2,
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            assert_eq!(doc_ids(&docs), vec![2, 3]);
+
+            let count = db
+                .count(VdbCountArgs {
+                    filter_inputs: Some(vec![IntFilterInput {
+                        field: "odd".to_string(),
+                        op: FilterOp::Equal,
+                        target: 1,
+                    }]),
+                })
+                .unwrap();
+            assert_eq!((count.count, count.total), (1, 3));
+
+            // hnsw keeps the deleted doc until the index is rebuilt
+            let is_hnsw = db.params.index_type == IndexType::Hnsw;
+            assert_eq!(db.deleted_ids.read().unwrap().len(), is_hnsw as u64);
+
+            let search_args = VdbSearchArgs {
+                query: vec![0.0, 1.0, 2.0],
+                k: 2,
+                filter_inputs: Some(vec![IntFilterInput {
+                    field: "odd".to_string(),
+                    op: FilterOp::Equal,
+                    target: 1,
+                }]),
+                ..Default::default()
+            };
+            let docs = db.query(search_args.clone()).await.unwrap();
+            assert_eq!(doc_ids(&docs), vec![3]);
+
+            db.rebuild_index(VdbRebuildArgs::default()).await.unwrap();
+            assert!(db.deleted_ids.read().unwrap().is_empty());
+
+            let docs = db.query(search_args).await.unwrap();
+            assert_eq!(doc_ids(&docs), vec![3]);
+            let docs = db
+                .query(VdbSearchArgs {
+                    query: vec![0.0, 1.0, 2.0],
+                    k: 2,
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            assert_eq!(doc_ids(&docs), vec![2, 3]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+        let rows = |values: Vec<f32>| VdbUpsertArgs {
+            vectors: VectorArgs {
+                data_row: values.len() / 3,
+                flat_data: values,
+                data_dim: 3,
+                sparse_data: None,
+                named_data: None,
+                multi_data: None,
+            },
+            docs: vec![None; 2],
+            attributes: vec![],
+            hnsw_params: None,
+        };
+
+        db.import(vec![10, 5], rows((0..6).map(|i| i as f32).collect()))
+            .await
+            .unwrap();
+
+        for (ids, expect_conflict) in [
+            (vec![5, 6], true),
+            (vec![0, 6], false),
+            (vec![6, 6], false),
+            (vec![6, u32::MAX as u64 + 1], false),
+            (vec![6], false),
+        ] {
+            let res = db.import(ids, rows(vec![0.0; 6])).await;
+            match expect_conflict {
+                true => assert!(matches!(res, Err(DBError::ConflictError(_)))),
+                false => assert!(matches!(res, Err(DBError::ValidationError(_)))),
+            }
+        }
+
+        // generated ids follow the imported ones
+        db.upsert(rows(vec![1.0; 6])).await.unwrap();
+
+        let docs = db.get(&[5, 10, 11, 12], true).unwrap();
+        assert_eq!(docs.len(), 4);
+        assert_eq!(docs[0].get("vector").unwrap(), &json!([3.0, 4.0, 5.0]));
+        assert_eq!(db.stats().unwrap().doc_count, 4);
+    }
+
+    #[tokio::test]
+    async fn test_recover_new_database() {
+        let db_path = TestPath::new();
+        let index_params = create_test_index_params(MetricType::L2, IndexType::Hnsw);
+        let mut db = VectorDatabase::new(&db_path, index_params).unwrap();
+
+        db.recover_database().await.unwrap();
+        assert!(db_path.as_ref().join(WAL_FILE_SUFFIX).is_file());
+        assert_eq!(db.stats().unwrap().wal_log_id, 0);
+    }
+
+    #[tokio::test]
+    async fn test_recover_stale_persistent_indexes() {
+        let db_path = TestPath::new();
+        let mut index_params = create_test_index_params(MetricType::L2, IndexType::MmapFlat);
+        index_params.vector_fields = Some(vec![VectorFieldParams {
+            name: "title".to_string(),
+            dim: 2,
+            metric_type: MetricType::L2,
+            index_type: IndexType::Vamana,
+            hnsw_params: None,
+            vamana_params: None,
+            multi_vector: None,
+        }]);
+
+        {
+            let mut db = VectorDatabase::new(&db_path, index_params.clone()).unwrap();
+            let res = db
+                .upsert(VdbUpsertArgs {
+                    vectors: VectorArgs {
+                        flat_data: vec![0.1, 0.2, 0.3, -0.1, -0.2, -0.3],
+                        data_row: 2,
+                        data_dim: 3,
+                        sparse_data: None,
+                        named_data: Some(HashMap::from([(
+                            "title".to_string(),
+                            vec![1.0, 0.0, 0.0, 1.0],
+                        )])),
+                        multi_data: None,
+                    },
+                    docs: vec![None, None],
+                    attributes: vec![],
+                    hnsw_params: None,
+                })
+                .await;
+            assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+            // the default index misses a stored vector, the title one holds an unknown one
+            db.vector_index.lock().unwrap().remove(&[1]).unwrap();
+            let (title_index, _) = db.field_index(Some("title")).unwrap();
+            title_index
+                .lock()
+                .unwrap()
+                .insert(&InsertParams::new(&array![[0.0, 1.0]], &vec![99]))
+                .unwrap();
+        }
+
+        let mut db = VectorDatabase::new(&db_path, index_params).unwrap();
+        assert_eq!(db.vector_index.lock().unwrap().count(), 1);
+        db.recover_database().await.unwrap();
+
+        for field in [None, Some("title")] {
+            let (index, _) = db.field_index(field).unwrap();
+            assert_eq!(index.lock().unwrap().count(), 2, "field {field:?}");
+        }
+
+        let docs = db
+            .query(VdbSearchArgs {
+                query: vec![0.1, 0.2, 0.3],
+                k: 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].get("id").unwrap(), &json!(1));
+    }
+
+    #[tokio::test]
+    async fn test_failed_upsert_leaves_nothing() {
+        let db_path = TestPath::new();
+        let mut index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        index_params.vector_fields = Some(vec![VectorFieldParams {
+            name: "title".to_string(),
+            dim: 2,
+            metric_type: MetricType::L2,
+            index_type: IndexType::Flat,
+            hnsw_params: None,
+            vamana_params: None,
+            multi_vector: None,
+        }]);
+        let upsert_args = VdbUpsertArgs {
+            vectors: VectorArgs {
+                flat_data: vec![0.1, 0.2, 0.3, -0.1, -0.2, -0.3],
+                data_row: 2,
+                data_dim: 3,
+                sparse_data: None,
+                named_data: Some(HashMap::from([(
+                    "title".to_string(),
+                    vec![1.0, 0.0, 0.0, 1.0],
+                )])),
+                multi_data: None,
+            },
+            docs: vec![None, None],
+            attributes: vec![
+                Some(HashMap::from([("num".to_string(), json!(1))])),
+                Some(HashMap::from([("num".to_string(), json!(2))])),
+            ],
+            hnsw_params: None,
+        };
+
+        {
+            let mut db = VectorDatabase::new(&db_path, index_params.clone()).unwrap();
+
+            // rejected before any id is allocated
+            let mut invalid_args = upsert_args.clone();
+            invalid_args.attributes[1] = Some(HashMap::from([("num".to_string(), json!("two"))]));
+            let res = db.upsert(invalid_args).await;
+            assert!(matches!(res, Err(DBError::ValidationError(_))));
+
+            // the docs, raw vectors and default index entries are written before the title
+            // index fails
+            db.named_indexes.insert(
+                "title".to_string(),
+                Arc::new(Mutex::new(FlatIndex::new(4, MetricType::L2).unwrap())),
+            );
+            assert!(db.upsert(upsert_args.clone()).await.is_err());
+            assert!(db.get(&[1, 2], false).unwrap().is_empty());
+            assert_eq!(db.vector_index.lock().unwrap().count(), 0);
+            let counts = db
+                .count(VdbCountArgs {
+                    filter_inputs: Some(vec![IntFilterInput {
+                        field: "num".to_string(),
+                        op: FilterOp::Equal,
+                        target: 1,
+                    }]),
+                })
+                .unwrap();
+            assert_eq!((counts.count, counts.total), (0, 0));
+        }
+
+        let mut db = VectorDatabase::new(&db_path, index_params).unwrap();
+        db.recover_database().await.unwrap();
+
+        let docs = db
+            .query(VdbSearchArgs {
+                query: vec![0.1, 0.2, 0.3],
+                k: 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(docs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shadow_recall() {
+        let mut index_params = create_test_index_params(MetricType::L2, IndexType::Hnsw);
+        index_params.shadow_sample_rate = Some(0.5);
+        let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+        let res = db
+            .upsert(VdbUpsertArgs {
+                vectors: VectorArgs {
+                    flat_data: (0..50).flat_map(|i| vec![i as f32, 0.0, 0.0]).collect(),
+                    data_row: 50,
+                    data_dim: 3,
+                    sparse_data: None,
+                    named_data: None,
+                    multi_data: None,
+                },
+                docs: vec![None; 50],
+                attributes: vec![],
+                hnsw_params: None,
+            })
+            .await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+        assert_eq!(db.shadow_recall().recall, None);
+
+        for i in 0..4 {
+            db.query(VdbSearchArgs {
+                query: vec![i as f32 * 10.0, 0.0, 0.0],
+                k: 5,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+
+        // the exact scans run in the background
+        let mut stats = db.shadow_recall();
+        for _ in 0..100 {
+            if stats.samples == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            stats = db.shadow_recall();
+        }
+        assert_eq!(stats.samples, 2);
+        assert_eq!(stats.expected, 10);
+        assert!(stats.recall.unwrap() > 0.0);
+
+        // sampled searches are not scanned while every scan slot is taken
+        // the finished scans may still be releasing their permits
+        let permits = Arc::clone(&db.shadow_scans)
+            .acquire_many_owned(MAX_SHADOW_SCANS as u32)
+            .await
+            .unwrap();
+        for i in 0..2 {
+            db.query(VdbSearchArgs {
+                query: vec![i as f32, 0.0, 0.0],
+                k: 5,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        drop(permits);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(db.shadow_recall().samples, 2);
+
+        // exact indexes are never sampled
+        let mut index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        index_params.shadow_sample_rate = Some(1.0);
+        let mut flat_db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+        flat_db
+            .query(VdbSearchArgs {
+                query: vec![0.0, 0.0, 0.0],
+                k: 5,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(flat_db.shadow_recall().samples, 0);
+    }
+
+    #[tokio::test]
+    async fn test_named_vector_fields() {
+        let mut index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        index_params.vector_fields = Some(vec![
+            VectorFieldParams {
+                name: "title".to_string(),
+                dim: 2,
+                metric_type: MetricType::L2,
+                index_type: IndexType::Flat,
+                hnsw_params: None,
+                vamana_params: None,
+                multi_vector: None,
+            },
+            VectorFieldParams {
+                name: "body".to_string(),
+                dim: 4,
+                metric_type: MetricType::IP,
+                index_type: IndexType::MmapFlat,
+                hnsw_params: None,
+                vamana_params: None,
+                multi_vector: None,
+            },
+        ]);
+        let test_path = TestPath::new();
+        let mut db = VectorDatabase::new(&test_path, index_params).unwrap();
+        assert!(test_path.db_path.join("body.index.bin").exists());
+
+        // default and title vectors
+        let res = db
+            .upsert(VdbUpsertArgs {
+                vectors: VectorArgs {
+                    flat_data: vec![0.1, 0.2, 0.3, -0.1, -0.2, -0.3],
+                    data_row: 2,
+                    data_dim: 3,
+                    sparse_data: None,
+                    named_data: Some(HashMap::from([(
+                        "title".to_string(),
+                        vec![1.0, 0.0, 0.0, 1.0],
+                    )])),
+                    multi_data: None,
+                },
+                docs: vec![None, None],
+                attributes: vec![],
+                hnsw_params: None,
+            })
+            .await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        // only the body vector, the default field is skipped
+        let mut upsert_args = VdbUpsertArgs {
+            vectors: VectorArgs {
+                flat_data: vec![],
+                data_row: 1,
+                data_dim: 0,
+                sparse_data: None,
+                named_data: Some(HashMap::from([(
+                    "body".to_string(),
+                    vec![0.5, 0.5, 0.5, 0.5],
+                )])),
+                multi_data: None,
+            },
+            docs: vec![None],
+            attributes: vec![],
+            hnsw_params: None,
+        };
+        let res = db.upsert(upsert_args.clone()).await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        upsert_args.vectors.named_data = Some(HashMap::from([("body".to_string(), vec![0.5])]));
+        assert!(db.upsert(upsert_args.clone()).await.is_err());
+        upsert_args.vectors.named_data =
+            Some(HashMap::from([("summary".to_string(), vec![0.5, 0.5])]));
+        assert!(db.upsert(upsert_args).await.is_err());
+
+        let doc_ids = |docs: &[DocMap]| {
+            docs.iter()
+                .map(|d| d.get("id").unwrap().as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // search a single named field
+        let docs_result = db
+            .query(VdbSearchArgs {
+                query: vec![0.0, 1.0],
+                k: 10,
+                vector_field: Some("title".to_string()),
+                with_vectors: Some(true),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(doc_ids(&docs_result), vec![2, 1]);
+        assert_eq!(
+            docs_result[0].get("named_vectors").unwrap(),
+            &json!({"title": [0.0, 1.0]})
+        );
+
+        let docs_result = db
+            .query(VdbSearchArgs {
+                query: vec![1.0, 1.0, 1.0, 1.0],
+                k: 10,
+                vector_field: Some("body".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(doc_ids(&docs_result), vec![3]);
+
+        // fuse the default field with the named ones
+        let docs_result = db
+            .query(VdbSearchArgs {
+                query: vec![0.1, 0.2, 0.3],
+                k: 10,
+                named_queries: Some(HashMap::from([
+                    ("title".to_string(), vec![1.0, 0.0]),
+                    ("body".to_string(), vec![1.0, 1.0, 1.0, 1.0]),
+                ])),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(doc_ids(&docs_result)[0], 1);
+        assert_eq!(docs_result.len(), 3);
+        assert!(docs_result.iter().all(|d| d.contains_key("score")));
+
+        // unknown fields and mismatched dimensions are rejected
+        for search_args in [
+            VdbSearchArgs {
+                query: vec![0.0, 1.0],
+                k: 1,
+                vector_field: Some("summary".to_string()),
+                ..Default::default()
+            },
+            VdbSearchArgs {
+                query: vec![0.1, 0.2, 0.3],
+                k: 1,
+                named_queries: Some(HashMap::from([("title".to_string(), vec![1.0])])),
+                ..Default::default()
+            },
+        ] {
+            assert!(db.query(search_args).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multi_vector_field() {
+        let mut index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        index_params.vector_fields = Some(vec![VectorFieldParams {
+            name: "colbert".to_string(),
+            dim: 2,
+            metric_type: MetricType::IP,
+            index_type: IndexType::Flat,
+            hnsw_params: None,
+            vamana_params: None,
+            multi_vector: Some(true),
+        }]);
+        let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+        let mut upsert_args = VdbUpsertArgs {
+            vectors: VectorArgs {
+                flat_data: vec![],
+                data_row: 3,
+                data_dim: 0,
+                sparse_data: None,
+                named_data: None,
+                multi_data: Some(HashMap::from([(
+                    "colbert".to_string(),
+                    vec![
+                        vec![1.0, 0.0, 0.0, 1.0],
+                        vec![1.0, 0.0],
+                        vec![0.0, 1.0, 0.0, 1.0, 0.5, 0.5],
+                    ],
+                )])),
+            },
+            docs: vec![None, None, None],
+            attributes: [10, 20, 10]
+                .iter()
+                .map(|age| Some(HashMap::from([("age".to_string(), json!(age))])))
+                .collect(),
+            hnsw_params: None,
+        };
+        let res = db.upsert(upsert_args.clone()).await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        // rows must hold whole vectors, and multi-vector fields only take multi_data
+        upsert_args.vectors.multi_data =
+            Some(HashMap::from([("colbert".to_string(), vec![vec![1.0]; 3])]));
+        assert!(db.upsert(upsert_args.clone()).await.is_err());
+        upsert_args.vectors.multi_data = None;
+        upsert_args.vectors.named_data =
+            Some(HashMap::from([("colbert".to_string(), vec![1.0; 6])]));
+        assert!(db.upsert(upsert_args).await.is_err());
+
+        let search_args = VdbSearchArgs {
+            k: 10,
+            multi_queries: Some(HashMap::from([(
+                "colbert".to_string(),
+                vec![1.0, 0.0, 0.0, 1.0],
+            )])),
+            with_vectors: Some(true),
+            ..Default::default()
+        };
+        let docs_result = db.query(search_args.clone()).await.unwrap();
+        let hits = docs_result
+            .iter()
+            .map(|d| {
+                (
+                    d.get("id").unwrap().as_u64().unwrap(),
+                    d.get("score").unwrap().as_f64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(hits, vec![(1, 2.0), (3, 1.5), (2, 1.0)]);
+        assert_eq!(
+            docs_result[0].get("named_vectors").unwrap(),
+            &json!({"colbert": [[1.0, 0.0], [0.0, 1.0]]})
+        );
+
+        // filters apply to the owner docs of the token vectors
+        let docs_result = db
+            .query(VdbSearchArgs {
+                filter_inputs: Some(vec![IntFilterInput {
+                    field: "age".to_string(),
+                    op: FilterOp::Equal,
+                    target: 10,
+                }]),
+                ..search_args
+            })
+            .await
+            .unwrap();
+        let ids = docs_result
+            .iter()
+            .map(|d| d.get("id").unwrap().as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 3]);
+
+        // the token index can not be searched as a single vector field
+        let result = db
+            .query(VdbSearchArgs {
+                query: vec![1.0, 0.0],
+                k: 1,
+                vector_field: Some("colbert".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delete_multi_vector_docs() {
+        let db_path = TestPath::new();
+        let mut index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        index_params.vector_fields = Some(vec![VectorFieldParams {
+            name: "colbert".to_string(),
+            dim: 2,
+            metric_type: MetricType::IP,
+            index_type: IndexType::Flat,
+            hnsw_params: None,
+            vamana_params: None,
+            multi_vector: Some(true),
+        }]);
+
+        let search_args = VdbSearchArgs {
+            k: 10,
+            multi_queries: Some(HashMap::from([(
+                "colbert".to_string(),
+                vec![1.0, 0.0, 0.0, 1.0],
+            )])),
+            ..Default::default()
+        };
+        let doc_ids = |docs: &[DocMap]| {
+            docs.iter()
+                .map(|doc| doc.get("id").unwrap().as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        {
+            let mut db = VectorDatabase::new(&db_path, index_params.clone()).unwrap();
+            // doc 1 owns tokens 1 and 2, whose ids are also those of docs 1 and 2
+            let res = db
+                .upsert(VdbUpsertArgs {
+                    vectors: VectorArgs {
+                        flat_data: vec![],
+                        data_row: 3,
+                        data_dim: 0,
+                        sparse_data: None,
+                        named_data: None,
+                        multi_data: Some(HashMap::from([(
+                            "colbert".to_string(),
+                            vec![
+                                vec![1.0, 0.0, 0.0, 1.0],
+                                vec![1.0, 0.0],
+                                vec![0.0, 1.0, 0.5, 0.5],
+                            ],
+                        )])),
+                    },
+                    docs: vec![None; 3],
+                    attributes: vec![],
+                    hnsw_params: None,
+                })
+                .await;
+            assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+            assert_eq!(db.delete(&[1]).await.unwrap(), 1);
+            let docs = db.query(search_args.clone()).await.unwrap();
+            assert_eq!(doc_ids(&docs), vec![3, 2]);
+        }
+
+        let mut db = VectorDatabase::new(&db_path, index_params).unwrap();
+        db.recover_database().await.unwrap();
+
+        let docs = db.query(search_args).await.unwrap();
+        assert_eq!(doc_ids(&docs), vec![3, 2]);
+        assert_eq!(
+            db.get(&[2], true).unwrap()[0].get("named_vectors").unwrap(),
+            &json!({"colbert": [[1.0, 0.0]]})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keyword_search_without_text_fields() {
+        let mut index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        index_params.text_fields = None;
+        let mut db = VectorDatabase::new(TestPath::new(), index_params).unwrap();
+
+        let result = db
+            .query(VdbSearchArgs {
+                k: 1,
+                text_query: Some("vector".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_into_persistent_index() {
+        let test_path = TestPath::new();
+        let index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        let mut db = VectorDatabase::new(&test_path, index_params.clone()).unwrap();
+
+        let data_array = standardize_vecs(&array![[0.1, 0.2, 0.3], [-0.1, 0.2, -0.3]]);
+        db.upsert(VdbUpsertArgs {
+            vectors: VectorArgs {
+                flat_data: data_array.iter().copied().collect(),
+                data_row: 2,
+                data_dim: 3,
+                sparse_data: None,
+                named_data: None,
+                multi_data: None,
+            },
+            docs: vec![None, None],
+            attributes: vec![],
+            hnsw_params: None,
+        })
+        .await
+        .unwrap();
+
+        for index_type in [IndexType::MmapFlat, IndexType::Vamana, IndexType::MmapFlat] {
+            let res = db
+                .rebuild_index(VdbRebuildArgs {
+                    index_type: Some(index_type.clone()),
+                    ..Default::default()
+                })
+                .await;
+            assert!(res.is_ok(), "rebuild failed: {:?}", res.err().unwrap());
+
+            let index_files = list_index_files(test_path.as_ref(), INDEX_FILE_SUFFIX).unwrap();
+            let mut suffixes = index_files
+                .into_iter()
+                .map(|(_, rest)| rest)
+                .collect::<Vec<_>>();
+            suffixes.sort();
+            if index_type == IndexType::Vamana {
+                // the vamana graph keeps vectors inserted after the build in a companion file
+                assert_eq!(suffixes, vec!["".to_string(), ".fresh".to_string()]);
+            } else {
+                assert_eq!(suffixes, vec!["".to_string()]);
+            }
+
+            let docs_result = db
+                .query(VdbSearchArgs {
+                    query: vec![0.1, 0.2, 0.3],
+                    k: 10,
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            assert_eq!(docs_result.len(), 2);
+        }
+
+        // the rebuilt params outlive a restart with the configured ones
+        db.rebuild_index(VdbRebuildArgs {
+            metric_type: Some(MetricType::IP),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        drop(db);
+
+        let mut db = VectorDatabase::new(&test_path, index_params).unwrap();
+        assert_eq!(db.params().index_type, IndexType::MmapFlat);
+        assert!(matches!(db.params().metric_type, MetricType::IP));
+        db.recover_database().await.unwrap();
+
+        let docs_result = db
+            .query(VdbSearchArgs {
+                query: vec![0.1, 0.2, 0.3],
+                k: 10,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(docs_result.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_every_field() {
+        let test_path = TestPath::new();
+        let mut index_params = create_test_index_params(MetricType::L2, IndexType::Flat);
+        index_params.vector_fields = Some(vec![
+            VectorFieldParams {
+                name: "title".to_string(),
+                dim: 2,
+                metric_type: MetricType::L2,
+                index_type: IndexType::MmapFlat,
+                hnsw_params: None,
+                vamana_params: None,
+                multi_vector: None,
+            },
+            VectorFieldParams {
+                name: "colbert".to_string(),
+                dim: 2,
+                metric_type: MetricType::IP,
+                index_type: IndexType::Flat,
+                hnsw_params: None,
+                vamana_params: None,
+                multi_vector: Some(true),
+            },
+        ]);
+        let mut db = VectorDatabase::new(&test_path, index_params).unwrap();
+
+        let res = db
+            .upsert(VdbUpsertArgs {
+                vectors: VectorArgs {
+                    flat_data: vec![0.1, 0.2, 0.3, -0.1, -0.2, -0.3],
+                    data_row: 2,
+                    data_dim: 3,
+                    sparse_data: None,
+                    named_data: Some(HashMap::from([(
+                        "title".to_string(),
+                        vec![1.0, 0.0, 0.0, 1.0],
+                    )])),
+                    multi_data: Some(HashMap::from([(
+                        "colbert".to_string(),
+                        vec![vec![1.0, 0.0, 0.0, 1.0], vec![0.0, 1.0]],
+                    )])),
+                },
+                docs: vec![None, None],
+                attributes: vec![],
+                hnsw_params: None,
+            })
+            .await;
+        assert!(res.is_ok(), "upsert failed: {:?}", res.err().unwrap());
+
+        // a vector the title index holds without it being stored, dropped by the rebuild
+        let (title_index, _) = db.field_index(Some("title")).unwrap();
+        title_index
+            .lock()
+            .unwrap()
+            .insert(&InsertParams::new(&array![[0.0, 1.0]], &vec![99]))
+            .unwrap();
+
+        let res = db
+            .rebuild_index(VdbRebuildArgs {
+                index_type: Some(IndexType::MmapFlat),
+                ..Default::default()
+            })
+            .await;
+        assert!(res.is_ok(), "rebuild failed: {:?}", res.err().unwrap());
+        assert!(!test_path.db_path.join(REBUILD_DIR).exists());
+
+        let doc_ids = |docs: &[DocMap]| {
+            docs.iter()
+                .map(|d| d.get("id").unwrap().as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        for (search_args, expected) in [
+            (
+                VdbSearchArgs {
+                    query: vec![-0.1, -0.2, -0.3],
+                    k: 10,
+                    ..Default::default()
+                },
+                vec![2, 1],
+            ),
+            (
+                VdbSearchArgs {
+                    query: vec![0.0, 1.0],
+                    k: 10,
+                    vector_field: Some("title".to_string()),
+                    ..Default::default()
+                },
+                vec![2, 1],
+            ),
+            (
+                VdbSearchArgs {
+                    k: 10,
+                    multi_queries: Some(HashMap::from([("colbert".to_string(), vec![1.0, 0.0])])),
+                    ..Default::default()
+                },
+                vec![1, 2],
+            ),
+        ] {
+            let docs = db.query(search_args).await.unwrap();
+            assert_eq!(doc_ids(&docs), expected);
+        }
+
+        // the rebuilt title index holds each stored vector once
+        let (title_index, _) = db.field_index(Some("title")).unwrap();
+        let result = search_index(title_index, SearchQuery::new(vec![0.0, 1.0]), 10)
+            .await
+            .unwrap();
+        assert_eq!(result.labels.len(), 2);
     }
 
     vecdb_test_cases! {
@@ -755,5 +5232,9 @@ mod tests {
         hnsw_l2: IndexType::Hnsw, MetricType::L2
         flat_inner_product: IndexType::Flat, MetricType::IP
         hnsw_inner_product: IndexType::Hnsw, MetricType::IP
+        mmap_flat_l2: IndexType::MmapFlat, MetricType::L2
+        mmap_flat_inner_product: IndexType::MmapFlat, MetricType::IP
+        vamana_l2: IndexType::Vamana, MetricType::L2
+        vamana_inner_product: IndexType::Vamana, MetricType::IP
     }
 }